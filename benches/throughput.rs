@@ -0,0 +1,104 @@
+// Multi-client throughput against an in-process server: every client owns a connection and either
+// writes its own series or reads one shared series. Run with `cargo bench --bench throughput`.
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use byteorder::{BigEndian, WriteBytesExt};
+use rmp_serde::to_vec_named;
+use serde::Serialize;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+
+use time_cache::config::{self, Config};
+use time_cache::db::CacheDb;
+use time_cache::entity::{DataType, SaveTimePeriod, TSCacheValue, TSItem, TSValue};
+use time_cache::method::MethodKind;
+use time_cache::handle;
+
+const REQUESTS: usize = 20_000;
+
+fn frame<T: Serialize>(kind: MethodKind, value: &T) -> Vec<u8> {
+    let param = to_vec_named(value).unwrap();
+    let mut buff = vec![];
+    buff.write_u16::<BigEndian>(kind.as_code()).unwrap();
+    buff.write_u32::<BigEndian>(param.len() as u32).unwrap();
+    buff.extend_from_slice(&param);
+    buff
+}
+
+// `reply` is the size of the whole response frame
+fn call(stream: &mut TcpStream, request: &[u8], reply: usize) {
+    stream.write_all(request).unwrap();
+    let mut out = vec![0u8; reply];
+    stream.read_exact(&mut out).unwrap();
+}
+
+fn start_server() -> String {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            sender.send(listener.local_addr().unwrap().to_string()).unwrap();
+            let db = Arc::new(CacheDb::new());
+            let (_stop, shutdown) = watch::channel(false);
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(handle::serve(socket, db.clone(), shutdown.clone()));
+            }
+        });
+    });
+    receiver.recv().unwrap()
+}
+
+fn create(addr: &str, name: &str) {
+    let item = TSItem { tsName: name.to_string(), capacity: 1000, datatype: DataType::Long, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision: Default::default(), compression: Default::default() };
+    call(&mut TcpStream::connect(addr).unwrap(), &frame(MethodKind::Create, &item), 6);
+}
+
+fn run(clients: usize, task: impl Fn(usize, &mut TcpStream) + Send + Sync + Copy + 'static, addr: &str) -> f64 {
+    let start = Instant::now();
+    let handles: Vec<_> = (0..clients).map(|client| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        thread::spawn(move || task(client, &mut stream))
+    }).collect();
+    handles.into_iter().for_each(|it| it.join().unwrap());
+    (clients * REQUESTS) as f64 / start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    // the series are memory only, the catalog still goes to the data directory
+    let data_dir = std::env::temp_dir().join(format!("tc-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&data_dir).unwrap();
+    config::init(Config { data_dir: data_dir.to_string_lossy().to_string(), ..Default::default() });
+    let addr = start_server();
+    create(&addr, "bench-shared");
+    let mut stream = TcpStream::connect(&addr).unwrap();
+    let value = TSValue { name: "bench-shared".to_string(), key: 1, value: TSCacheValue::Long(7) };
+    call(&mut stream, &frame(MethodKind::Set, &value), 6);
+
+    println!("{:>8} {:>16} {:>16}", "clients", "set req/s", "get req/s");
+    for clients in [1, 2, 4, 8] {
+        for client in 0..clients {
+            create(&addr, &format!("bench-{}-{}", clients, client));
+        }
+        let writes = run(clients, move |client, stream| {
+            let name = format!("bench-{}-{}", clients, client);
+            for key in 1..=REQUESTS {
+                let value = TSValue { name: name.clone(), key: key as u128, value: TSCacheValue::Long(7) };
+                call(stream, &frame(MethodKind::Set, &value), 6);
+            }
+        }, &addr);
+        let reads = run(clients, |_, stream| {
+            let request = frame(MethodKind::Get, &"bench-shared");
+            for _ in 0..REQUESTS {
+                call(stream, &request, 7);
+            }
+        }, &addr);
+        println!("{:>8} {:>16.0} {:>16.0}", clients, writes, reads);
+    }
+    let _ = std::fs::remove_dir_all(data_dir);
+}
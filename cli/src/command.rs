@@ -0,0 +1,181 @@
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use time_cache_client::{Compression, DataType, Precision, SaveTimePeriod, TSCacheValue};
+
+pub const COMMANDS: [&str; 11] = ["create", "list", "drop", "set", "get", "range", "query", "format", "help", "quit", "exit"];
+
+pub const HELP: &str = "\
+create <name> [capacity=N] [type=long|double|float|number|string|bytes] [save=nerve|minute|ten_minutes|hour|day] [precision=s|ms|us|ns] [compression=none|lz4|zstd]
+list [prefix]
+drop <name>
+set <name> <value> [time]
+get <name>
+range <name> <start> <end>
+query <name> <time>
+format table|json|csv
+help
+quit
+
+times are milliseconds since the epoch, `now`, or relative to now like -30s, -5m, -2h, -1d,
+range and query show keys in milliseconds whatever the precision of the series";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Table,
+    Json,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("unknown format `{}`, expected table, json or csv", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Create { name: String, capacity: Option<usize>, datatype: DataType, save: Option<SaveTimePeriod>, precision: Option<Precision>, compression: Option<Compression> },
+    List { prefix: Option<String> },
+    Drop { name: String },
+    // the value is parsed once the series' type is known
+    Set { name: String, value: String, time: Option<u128> },
+    Get { name: String },
+    Range { name: String, start: u128, end: u128 },
+    Query { name: String, time: u128 },
+    Format(Format),
+    Help,
+    Quit,
+}
+
+pub fn now_millis() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()
+}
+
+// a time from the shell as a key of a series with `precision`, milliseconds when unset
+pub fn millis_as(time: u128, precision: Option<Precision>) -> u128 {
+    match precision {
+        Some(Precision::Seconds) => time / 1000,
+        Some(Precision::Micros) => time.saturating_mul(1000),
+        Some(Precision::Nanos) => time.saturating_mul(1_000_000),
+        Some(Precision::Millis) | None => time,
+    }
+}
+
+// `now`, `-5m` style offsets from `now`, or absolute milliseconds
+pub fn parse_time(text: &str, now: u128) -> Result<u128, String> {
+    if text == "now" {
+        return Ok(now);
+    }
+    if let Some(offset) = text.strip_prefix('-') {
+        let split = offset.find(|c: char| !c.is_ascii_digit()).unwrap_or(offset.len());
+        let (amount, unit) = offset.split_at(split);
+        let amount: u128 = amount.parse().map_err(|_| format!("bad time `{}`", text))?;
+        let scale = match unit {
+            "ms" => 1,
+            "s" | "" => 1000,
+            "m" => 60 * 1000,
+            "h" => 3600 * 1000,
+            "d" => 24 * 3600 * 1000,
+            _ => return Err(format!("unknown time unit `{}` in `{}`", unit, text)),
+        };
+        return Ok(now.saturating_sub(amount * scale));
+    }
+    text.parse().map_err(|_| format!("bad time `{}`", text))
+}
+
+pub fn parse_value(text: &str, datatype: DataType) -> Result<TSCacheValue, String> {
+    let bad = || format!("`{}` is not a {:?} value", text, datatype);
+    match datatype {
+        DataType::Float => text.parse().map(TSCacheValue::Float).map_err(|_| bad()),
+        DataType::Long => text.parse().map(TSCacheValue::Long).map_err(|_| bad()),
+        DataType::Double => text.parse().map(TSCacheValue::Double).map_err(|_| bad()),
+        DataType::Number => text.parse().map(TSCacheValue::Number).map_err(|_| bad()),
+        DataType::String => Ok(TSCacheValue::String(text.to_string())),
+        // hex, with or without 0x
+        DataType::ByteArray => parse_hex(text).map(TSCacheValue::ByteArray).ok_or_else(bad),
+    }
+}
+
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    let text = text.strip_prefix("0x").unwrap_or(text);
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+// splits on whitespace, double quotes keep a value with spaces together
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut current: Option<String> = None;
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !quoted => words.extend(current.take()),
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quote".to_string());
+    }
+    words.extend(current);
+    Ok(words)
+}
+
+pub fn parse(line: &str, now: u128) -> Result<Option<Command>, String> {
+    let words = split_words(line)?;
+    let Some((first, args)) = words.split_first() else {
+        return Ok(None);
+    };
+    let arg = |i: usize, what: &str| args.get(i).cloned().ok_or_else(|| format!("{}: missing {}", first, what));
+    let command = match first.to_lowercase().as_str() {
+        "create" => {
+            let name = arg(0, "series name")?;
+            let (mut capacity, mut datatype, mut save, mut precision, mut compression) = (None, DataType::Double, None, None, None);
+            for option in &args[1..] {
+                let (key, value) = option.split_once('=').ok_or_else(|| format!("create: expected key=value, got `{}`", option))?;
+                match key {
+                    "capacity" => capacity = Some(value.parse().map_err(|_| format!("bad capacity `{}`", value))?),
+                    "type" => datatype = value.parse()?,
+                    "save" => save = Some(value.parse()?),
+                    "precision" => precision = Some(value.parse()?),
+                    "compression" => compression = Some(value.parse()?),
+                    _ => return Err(format!("create: unknown option `{}`", key)),
+                }
+            }
+            Command::Create { name, capacity, datatype, save, precision, compression }
+        }
+        "list" => Command::List { prefix: args.first().cloned() },
+        "drop" => Command::Drop { name: arg(0, "series name")? },
+        "set" => {
+            let time = match args.get(2) {
+                Some(time) => Some(parse_time(time, now)?),
+                None => None,
+            };
+            Command::Set { name: arg(0, "series name")?, value: arg(1, "value")?, time }
+        }
+        "get" => Command::Get { name: arg(0, "series name")? },
+        "range" => Command::Range {
+            name: arg(0, "series name")?,
+            start: parse_time(&arg(1, "start")?, now)?,
+            end: parse_time(&arg(2, "end")?, now)?,
+        },
+        "query" => Command::Query { name: arg(0, "series name")?, time: parse_time(&arg(1, "time")?, now)? },
+        "format" => Command::Format(arg(0, "format")?.parse()?),
+        "help" | "?" => Command::Help,
+        "quit" | "exit" => Command::Quit,
+        _ => return Err(format!("unknown command `{}`, type help", first)),
+    };
+    Ok(Some(command))
+}
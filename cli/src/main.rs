@@ -0,0 +1,169 @@
+mod command;
+mod output;
+
+use std::process::exit;
+use clap::Parser;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use time_cache_client::blocking::Client;
+use time_cache_client::{Error, Precision, TSItem, TSValue};
+use crate::command::{millis_as, now_millis, parse, parse_value, Command, Format, COMMANDS, HELP};
+use crate::output::{render, Output};
+
+#[derive(Debug, Parser)]
+#[command(name = "tc-cli", version, about = "interactive shell for the time-cache server")]
+struct Args {
+    #[arg(short, long, env = "TC_ADDR", default_value = "127.0.0.1:8080")]
+    addr: String,
+    #[arg(short, long, default_value = "table")]
+    format: Format,
+    /// run the `;` separated commands and exit, the exit status is 1 if one of them failed
+    #[arg(short, long)]
+    execute: Option<String>,
+}
+
+fn run(client: &Client, command: Command) -> Result<Output, Error> {
+    let output = match command {
+        Command::Create { name, capacity, datatype, save, precision, compression } => {
+            client.create(&TSItem { capacity, save_time: save, precision, compression, ..TSItem::new(&name, datatype) })?;
+            Output::Done(format!("created {}", name))
+        }
+        Command::List { prefix } => Output::Items(client.list(prefix.as_deref())?),
+        Command::Drop { name } => {
+            client.drop(&name)?;
+            Output::Done(format!("dropped {}", name))
+        }
+        Command::Set { name, value, time } => {
+            let item = client.list(Some(&name))?.into_iter().find(|it| it.ts_name == name);
+            let Some(item) = item else {
+                return Err(Error::Protocol(format!("series {} does not exist", name)));
+            };
+            let value = parse_value(&value, item.datatype).map_err(Error::Protocol)?;
+            // without a time the server stamps the point
+            let key = client.set(&TSValue::new(&name, time.map(|it| millis_as(it, item.precision)).unwrap_or(0), value))?;
+            Output::Done(format!("set {} at {}", name, key))
+        }
+        Command::Get { name } => Output::Value(client.get(&name)?),
+        Command::Range { name, start, end } => Output::Points(client.range_as(&name, start, end, Precision::Millis)?),
+        Command::Query { name, time } => Output::Points(vec![client.query_as(&name, time, Precision::Millis)?]),
+        Command::Format(_) | Command::Help | Command::Quit => unreachable!(),
+    };
+    Ok(output)
+}
+
+// false when the shell should exit
+fn execute(client: &Client, line: &str, format: &mut Format) -> Result<bool, String> {
+    let command = match parse(line, now_millis())? {
+        Some(command) => command,
+        None => return Ok(true),
+    };
+    match command {
+        Command::Quit => return Ok(false),
+        Command::Help => println!("{}", HELP),
+        Command::Format(next) => *format = next,
+        command => {
+            let text = render(&run(client, command).map_err(|e| e.to_string())?, *format);
+            if !text.is_empty() {
+                println!("{}", text);
+            }
+        }
+    }
+    Ok(true)
+}
+
+struct CliHelper {
+    client: Client,
+}
+
+// command names first, then the server's series names
+impl Completer for CliHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &line[start..];
+        if start == 0 {
+            return Ok((0, COMMANDS.iter().filter(|it| it.starts_with(word)).map(|it| it.to_string()).collect()));
+        }
+        if line[..start].split_whitespace().count() != 1 {
+            return Ok((start, vec![]));
+        }
+        let names = match self.client.list(Some(word)) {
+            Ok(items) => items.into_iter().map(|it| it.ts_name).collect(),
+            Err(_) => vec![],
+        };
+        Ok((start, names))
+    }
+}
+
+impl Hinter for CliHelper {
+    type Hint = String;
+}
+
+impl Highlighter for CliHelper {}
+
+impl Validator for CliHelper {}
+
+impl Helper for CliHelper {}
+
+fn main() {
+    let args = Args::parse();
+    let client = match Client::connect(&args.addr) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("cannot connect to {}: {}", args.addr, e);
+            exit(2);
+        }
+    };
+    let mut format = args.format;
+    if let Some(script) = args.execute {
+        let mut failed = false;
+        for line in script.split(';') {
+            match execute(&client, line, &mut format) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    failed = true;
+                }
+            }
+        }
+        exit(if failed { 1 } else { 0 });
+    }
+
+    let mut editor: Editor<CliHelper, DefaultHistory> = Editor::new().unwrap();
+    editor.set_helper(Some(CliHelper { client: client.clone() }));
+    let history = std::env::var("HOME").map(|home| format!("{}/.tc_cli_history", home)).ok();
+    if let Some(ref history) = history {
+        let _ = editor.load_history(history);
+    }
+    println!("connected to {}, type help for commands", args.addr);
+    loop {
+        let line = match editor.readline("tc> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                break;
+            }
+        };
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.as_str());
+        }
+        match execute(&client, &line, &mut format) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+    if let Some(ref history) = history {
+        let _ = editor.save_history(history);
+    }
+}
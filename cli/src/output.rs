@@ -0,0 +1,121 @@
+use serde_json::{json, Value};
+use time_cache_client::{TSCacheValue, TSItem, TSPoint};
+use crate::command::Format;
+
+pub enum Output {
+    Done(String),
+    Value(TSCacheValue),
+    Points(Vec<TSPoint>),
+    Items(Vec<TSItem>),
+}
+
+pub fn value_text(value: &TSCacheValue) -> String {
+    match value {
+        TSCacheValue::Float(it) => it.to_string(),
+        TSCacheValue::Long(it) => it.to_string(),
+        TSCacheValue::Double(it) | TSCacheValue::Number(it) => it.to_string(),
+        TSCacheValue::String(it) => it.clone(),
+        TSCacheValue::ByteArray(it) => format!("0x{}", it.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+    }
+}
+
+fn value_json(value: &TSCacheValue) -> Value {
+    match value {
+        TSCacheValue::Float(it) => json!(it),
+        TSCacheValue::Long(it) => json!(it),
+        TSCacheValue::Double(it) | TSCacheValue::Number(it) => json!(it),
+        TSCacheValue::String(it) => json!(it),
+        TSCacheValue::ByteArray(_) => json!(value_text(value)),
+    }
+}
+
+// keys beyond u64 would not survive a JSON parser, they are written as strings
+fn key_json(key: u128) -> Value {
+    match u64::try_from(key) {
+        Ok(key) => json!(key),
+        Err(_) => json!(key.to_string()),
+    }
+}
+
+fn item_row(item: &TSItem) -> Vec<String> {
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    vec![
+        item.ts_name.clone(),
+        format!("{:?}", item.datatype),
+        optional(item.capacity.map(|it| it.to_string())),
+        optional(item.save_time.map(|it| format!("{:?}", it))),
+    ]
+}
+
+// header and rows of a result, None for results without rows
+fn rows(output: &Output) -> Option<(Vec<&'static str>, Vec<Vec<String>>)> {
+    match output {
+        Output::Done(_) => None,
+        Output::Value(value) => Some((vec!["value"], vec![vec![value_text(value)]])),
+        Output::Points(points) => Some((
+            vec!["key", "value"],
+            points.iter().map(|it| vec![it.key.to_string(), value_text(&it.value)]).collect(),
+        )),
+        Output::Items(items) => Some((vec!["name", "type", "capacity", "save"], items.iter().map(item_row).collect())),
+    }
+}
+
+pub fn render(output: &Output, format: Format) -> String {
+    match format {
+        Format::Table => match rows(output) {
+            Some((header, rows)) => table(&header, &rows),
+            None => match output {
+                Output::Done(message) => message.clone(),
+                _ => unreachable!(),
+            },
+        },
+        Format::Json => {
+            let value = match output {
+                Output::Done(message) => json!({ "result": message }),
+                Output::Value(value) => value_json(value),
+                Output::Points(points) => points.iter().map(|it| json!({ "key": key_json(it.key), "value": value_json(&it.value) })).collect(),
+                Output::Items(items) => items.iter().map(|it| json!({
+                    "name": it.ts_name,
+                    "type": format!("{:?}", it.datatype),
+                    "capacity": it.capacity,
+                    "save": it.save_time.map(|save| format!("{:?}", save)),
+                })).collect(),
+            };
+            value.to_string()
+        }
+        Format::Csv => match rows(output) {
+            Some((header, rows)) => {
+                let mut lines = vec![header.join(",")];
+                lines.extend(rows.iter().map(|row| row.iter().map(|it| csv_field(it)).collect::<Vec<_>>().join(",")));
+                lines.join("\n")
+            }
+            None => String::new(),
+        },
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        return format!("\"{}\"", field.replace('"', "\"\""));
+    }
+    field.to_string()
+}
+
+fn table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|it| it.chars().count()).collect();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+    let line = format!("+{}+", widths.iter().map(|w| "-".repeat(w + 2)).collect::<Vec<_>>().join("+"));
+    let format_row = |cells: Vec<&str>| {
+        let cells: Vec<String> = cells.iter().zip(&widths).map(|(cell, w)| format!(" {:<w$} ", cell, w = w)).collect();
+        format!("|{}|", cells.join("|"))
+    };
+    let mut lines = vec![line.clone(), format_row(header.to_vec()), line.clone()];
+    lines.extend(rows.iter().map(|row| format_row(row.iter().map(|it| it.as_str()).collect())));
+    lines.push(line);
+    lines.push(format!("{} row{}", rows.len(), if rows.len() == 1 { "" } else { "s" }));
+    lines.join("\n")
+}
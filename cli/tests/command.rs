@@ -0,0 +1,66 @@
+use time_cache_client::{Compression, DataType, Precision, SaveTimePeriod, TSCacheValue, TSItem, TSPoint};
+
+// the shell-only parts are unused here
+#[allow(dead_code)]
+#[path = "../src/command.rs"]
+mod command;
+
+#[allow(dead_code)]
+#[path = "../src/output.rs"]
+mod output;
+
+use crate::command::{parse, parse_time, parse_value, Command, Format};
+use crate::output::{render, Output};
+
+const NOW: u128 = 1_700_000_000_000;
+
+#[test]
+fn parse_commands() {
+    assert_eq!(
+        parse("create cpu capacity=1000 type=double save=minute precision=us compression=zstd", NOW).unwrap(),
+        Some(Command::Create { name: "cpu".into(), capacity: Some(1000), datatype: DataType::Double, save: Some(SaveTimePeriod::Minute), precision: Some(Precision::Micros), compression: Some(Compression::Zstd) })
+    );
+    assert_eq!(parse("set cpu 1.5", NOW).unwrap(), Some(Command::Set { name: "cpu".into(), value: "1.5".into(), time: None }));
+    assert_eq!(parse("  set log \"disk full\" now", NOW).unwrap(), Some(Command::Set { name: "log".into(), value: "disk full".into(), time: Some(NOW) }));
+    assert_eq!(parse("range cpu -5m now", NOW).unwrap(), Some(Command::Range { name: "cpu".into(), start: NOW - 300_000, end: NOW }));
+    assert_eq!(parse("format csv", NOW).unwrap(), Some(Command::Format(Format::Csv)));
+    assert_eq!(parse("   ", NOW).unwrap(), None);
+
+    assert!(parse("range cpu -5m", NOW).unwrap_err().contains("missing end"));
+    assert!(parse("create cpu size=1", NOW).unwrap_err().contains("unknown option"));
+    assert!(parse("frobnicate", NOW).is_err());
+}
+
+#[test]
+fn parse_times_and_values() {
+    assert_eq!(parse_time("-30s", NOW), Ok(NOW - 30_000));
+    assert_eq!(parse_time("-2h", NOW), Ok(NOW - 7_200_000));
+    assert_eq!(parse_time("1234", NOW), Ok(1234));
+    assert!(parse_time("-3w", NOW).is_err());
+
+    assert_eq!(parse_value("7", DataType::Long), Ok(TSCacheValue::Long(7)));
+    assert_eq!(parse_value("0x0aff", DataType::ByteArray), Ok(TSCacheValue::ByteArray(vec![0x0a, 0xff])));
+    assert!(parse_value("1.5", DataType::Long).is_err());
+}
+
+#[test]
+fn render_formats() {
+    let points = Output::Points(vec![
+        TSPoint { key: 1, value: TSCacheValue::Double(0.5) },
+        TSPoint { key: 2, value: TSCacheValue::String("a,b".into()) },
+    ]);
+    assert_eq!(render(&points, Format::Csv), "key,value\n1,0.5\n2,\"a,b\"");
+    assert_eq!(render(&points, Format::Json), r#"[{"key":1,"value":0.5},{"key":2,"value":"a,b"}]"#);
+    assert_eq!(render(&points, Format::Table), "\
++-----+-------+
+| key | value |
++-----+-------+
+| 1   | 0.5   |
+| 2   | a,b   |
++-----+-------+
+2 rows");
+
+    let items = Output::Items(vec![TSItem::new("cpu", DataType::Long)]);
+    assert_eq!(render(&items, Format::Csv), "name,type,capacity,save\ncpu,Long,-,-");
+    assert_eq!(render(&Output::Done("created cpu".into()), Format::Csv), "");
+}
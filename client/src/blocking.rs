@@ -0,0 +1,156 @@
+//! The same client over std sockets, for programs without a tokio runtime.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use bytes::BytesMut;
+use crate::error::Error;
+use crate::proto::{self, decode_response, encode_request, param, QueryParam, RangeParam, Response};
+use crate::types::{Precision, TSCacheValue, TSItem, TSPoint, TSValue};
+use crate::Options;
+
+struct Connection {
+    stream: TcpStream,
+    buff: BytesMut,
+    next_id: u32,
+}
+
+impl Connection {
+    fn open(addr: &str, options: &Options) -> Result<Connection, Error> {
+        let mut last = None;
+        for resolved in addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&resolved, options.connect_timeout) {
+                Ok(stream) => {
+                    stream.set_nodelay(true)?;
+                    stream.set_read_timeout(Some(options.request_timeout))?;
+                    stream.set_write_timeout(Some(options.request_timeout))?;
+                    return Ok(Connection { stream, buff: BytesMut::with_capacity(4096), next_id: 0 });
+                }
+                Err(e) => last = Some(e),
+            }
+        }
+        Err(last.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} resolves to no address", addr))).into())
+    }
+
+    fn call(&mut self, action: u16, payload: &[u8]) -> Result<Response, Error> {
+        self.next_id = self.next_id.wrapping_add(1);
+        let id = self.next_id;
+        self.stream.write_all(&encode_request(id, action, payload))?;
+        let mut chunk = [0u8; 4096];
+        loop {
+            if let Some(response) = decode_response(&mut self.buff)? {
+                if response.id != id {
+                    return Err(Error::Protocol(format!("response id {} for request {}", response.id, id)));
+                }
+                return Ok(response);
+            }
+            let n = self.stream.read(&mut chunk)?;
+            if n == 0 {
+                return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "server closed the connection")));
+            }
+            self.buff.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+/// Blocking client, cheap to clone, clones share the connection pool.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    addr: String,
+    options: Options,
+    idle: Mutex<Vec<Connection>>,
+}
+
+impl Client {
+    pub fn connect(addr: &str) -> Result<Client, Error> {
+        Client::with_options(addr, Options::default())
+    }
+
+    /// Opens the first connection right away so an unreachable server is reported here.
+    pub fn with_options(addr: &str, options: Options) -> Result<Client, Error> {
+        let connection = Connection::open(addr, &options)?;
+        let idle = Mutex::new(vec![connection]);
+        Ok(Client { inner: Arc::new(Inner { addr: addr.to_string(), options, idle }) })
+    }
+
+    pub fn create(&self, item: &TSItem) -> Result<(), Error> {
+        self.call(proto::CREATE, param(item)?)?.empty()
+    }
+
+    /// Every series, or those whose name starts with `prefix`, sorted by name.
+    pub fn list(&self, prefix: Option<&str>) -> Result<Vec<TSItem>, Error> {
+        let payload = match prefix {
+            Some(prefix) => param(prefix)?,
+            None => vec![],
+        };
+        self.call(proto::LIST, payload)?.value()
+    }
+
+    pub fn drop(&self, name: &str) -> Result<(), Error> {
+        self.call(proto::DROP, param(name)?)?.empty()
+    }
+
+    /// Returns the stored key, a key of 0 is assigned by the server from its clock.
+    pub fn set(&self, value: &TSValue) -> Result<u128, Error> {
+        self.call(proto::SET, param(value)?)?.value_or(value.key)
+    }
+
+    /// Points are written in order, on error the ones before the failing point are kept.
+    /// Returns the stored keys.
+    pub fn set_batch(&self, values: &[TSValue]) -> Result<Vec<u128>, Error> {
+        self.call(proto::SET_BATCH, param(values)?)?.value_or(values.iter().map(|it| it.key).collect())
+    }
+
+    /// The newest value of the series.
+    pub fn get(&self, name: &str) -> Result<TSCacheValue, Error> {
+        self.call(proto::GET, param(name)?)?.value()
+    }
+
+    /// Points with `start <= key <= end`, oldest first, keys in the series' precision.
+    pub fn range(&self, name: &str, start: u128, end: u128) -> Result<Vec<TSPoint>, Error> {
+        self.call(proto::RANGE, param(&RangeParam { name, start, end, precision: None })?)?.value()
+    }
+
+    /// Like `range` with `start`, `end` and the returned keys in `precision`.
+    pub fn range_as(&self, name: &str, start: u128, end: u128, precision: Precision) -> Result<Vec<TSPoint>, Error> {
+        self.call(proto::RANGE, param(&RangeParam { name, start, end, precision: Some(precision) })?)?.value()
+    }
+
+    /// The newest point at or before `time`.
+    pub fn query(&self, name: &str, time: u128) -> Result<TSPoint, Error> {
+        self.call(proto::QUERY, param(&QueryParam { name, time, precision: None })?)?.value()
+    }
+
+    /// Like `query` with `time` and the returned key in `precision`.
+    pub fn query_as(&self, name: &str, time: u128, precision: Precision) -> Result<TSPoint, Error> {
+        self.call(proto::QUERY, param(&QueryParam { name, time, precision: Some(precision) })?)?.value()
+    }
+
+    fn call(&self, action: u16, payload: Vec<u8>) -> Result<Response, Error> {
+        let pooled = self.inner.idle.lock().unwrap().pop();
+        let reused = pooled.is_some();
+        let mut connection = match pooled {
+            Some(connection) => connection,
+            None => Connection::open(&self.inner.addr, &self.inner.options)?,
+        };
+        let result = match connection.call(action, &payload) {
+            // the server closed the pooled connection while it was idle, try once on a new one
+            Err(Error::Io(_)) if reused => {
+                connection = Connection::open(&self.inner.addr, &self.inner.options)?;
+                connection.call(action, &payload)
+            }
+            result => result,
+        };
+        if matches!(result, Ok(_) | Err(Error::Server { .. })) {
+            let mut idle = self.inner.idle.lock().unwrap();
+            if idle.len() < self.inner.options.pool_size {
+                idle.push(connection);
+            }
+        }
+        result
+    }
+}
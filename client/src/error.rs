@@ -0,0 +1,107 @@
+use std::fmt;
+use std::io;
+
+/// What the server rejected a request for, decoded from `Exception.code`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// 4001, the parameters could not be decoded.
+    InvalidParam,
+    /// 4002, the series already exists or does not exist.
+    Name,
+    /// 4003, the series has no value to return.
+    Empty,
+    /// 4004, the key is not greater than the last key of the series.
+    OutOfOrder,
+    /// 4005, the value does not match the series' data type.
+    WrongType,
+    /// 4006
+    AlertRule,
+    /// 4007
+    Notifier,
+    /// 4008, a server limit such as the series count was reached.
+    Limit,
+    /// 4009, the server's write queue is full, the request may be retried later.
+    Busy,
+    /// 4010, the server does not know the method.
+    UnknownMethod,
+    /// 4011, the series name is empty.
+    InvalidName,
+    /// 4012, the series' segments on disk could not be written or read; after a write error
+    /// the series takes no more points.
+    Disk,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn from_code(code: i16) -> ErrorKind {
+        match code {
+            4001 => ErrorKind::InvalidParam,
+            4002 => ErrorKind::Name,
+            4003 => ErrorKind::Empty,
+            4004 => ErrorKind::OutOfOrder,
+            4005 => ErrorKind::WrongType,
+            4006 => ErrorKind::AlertRule,
+            4007 => ErrorKind::Notifier,
+            4008 => ErrorKind::Limit,
+            4009 => ErrorKind::Busy,
+            4010 => ErrorKind::UnknownMethod,
+            4011 => ErrorKind::InvalidName,
+            4012 => ErrorKind::Disk,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Timeout,
+    /// The server sent something that is not a valid response, or closed the connection
+    /// because of a malformed request.
+    Protocol(String),
+    /// The server answered with an exception.
+    Server { kind: ErrorKind, code: i16, message: String },
+}
+
+impl Error {
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Error::Server { kind, .. } => Some(*kind),
+            _ => None,
+        }
+    }
+
+    /// True when sending the same request again may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Error::Io(_) | Error::Timeout | Error::Server { kind: ErrorKind::Busy, .. })
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Timeout => write!(f, "request timed out"),
+            Error::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            Error::Server { code, message, .. } => write!(f, "server error {}: {}", code, message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock) {
+            return Error::Timeout;
+        }
+        Error::Io(e)
+    }
+}
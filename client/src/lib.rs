@@ -0,0 +1,45 @@
+//! Client for the time-cache server.
+//!
+//! [`Client`] runs on tokio, [`blocking::Client`] on plain std sockets. Both keep a small
+//! pool of connections, open a new one when a pooled connection was closed by the server,
+//! and map server exceptions to [`Error::Server`] with an [`ErrorKind`].
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), time_cache_client::Error> {
+//! use time_cache_client::{Client, DataType, TSItem, TSValue, TSCacheValue};
+//!
+//! let client = Client::connect("127.0.0.1:8080").await?;
+//! client.create(&TSItem::new("cpu", DataType::Double)).await?;
+//! client.set(&TSValue::new("cpu", 1, TSCacheValue::Double(0.5))).await?;
+//! let last = client.get("cpu").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod client;
+mod error;
+mod proto;
+mod types;
+pub mod blocking;
+
+pub use client::Client;
+pub use error::{Error, ErrorKind};
+pub use types::{Compression, DataType, Precision, SaveTimePeriod, TSCacheValue, TSItem, TSPoint, TSValue};
+
+use std::time::Duration;
+
+/// Connection settings shared by both clients.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Idle connections kept for reuse, busy connections are not limited.
+    pub pool_size: usize,
+    pub connect_timeout: Duration,
+    /// Time allowed for sending a request and reading its response.
+    pub request_timeout: Duration,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options { pool_size: 8, connect_timeout: Duration::from_secs(5), request_timeout: Duration::from_secs(30) }
+    }
+}
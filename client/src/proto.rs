@@ -0,0 +1,119 @@
+use bytes::{Buf, BufMut, BytesMut};
+use rmp_serde::{from_slice, to_vec_named};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use crate::error::{Error, ErrorKind};
+use crate::types::Precision;
+
+// action codes, see the server's MethodKind
+pub const CREATE: u16 = 101;
+pub const LIST: u16 = 102;
+pub const DROP: u16 = 103;
+pub const SET: u16 = 201;
+pub const SET_BATCH: u16 = 202;
+pub const GET: u16 = 301;
+pub const RANGE: u16 = 302;
+pub const QUERY: u16 = 303;
+
+const MAGIC: u8 = 0xC7;
+const VERSION_2: u8 = 2;
+const RESPONSE_HEADER: usize = 10;
+const STATUS_OK: u8 = 0;
+const STATUS_PROTOCOL_ERROR: u8 = 4;
+// payloads bigger than this are treated as a broken stream
+const MAX_RESPONSE: usize = 256 * 1024 * 1024;
+
+#[derive(Serialize)]
+pub struct RangeParam<'a> {
+    pub name: &'a str,
+    pub start: u128,
+    pub end: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precision: Option<Precision>,
+}
+
+#[derive(Serialize)]
+pub struct QueryParam<'a> {
+    pub name: &'a str,
+    pub time: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precision: Option<Precision>,
+}
+
+#[derive(Deserialize)]
+struct Exception {
+    code: i16,
+    msg: String,
+}
+
+pub fn param<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    to_vec_named(value).map_err(|e| Error::Protocol(format!("cannot encode request: {}", e)))
+}
+
+// [u8 MAGIC][u8 version][u8 flags][u32 request id][u16 action][u32 length][payload]
+pub fn encode_request(id: u32, action: u16, payload: &[u8]) -> Vec<u8> {
+    let mut buff = Vec::with_capacity(13 + payload.len());
+    buff.put_u8(MAGIC);
+    buff.put_u8(VERSION_2);
+    buff.put_u8(0);
+    buff.put_u32(id);
+    buff.put_u16(action);
+    buff.put_u32(payload.len() as u32);
+    buff.put_slice(payload);
+    buff
+}
+
+pub struct Response {
+    pub id: u32,
+    status: u8,
+    payload: Vec<u8>,
+}
+
+// [u32 request id][u8 status][u8 payload type][u32 length][payload]
+pub fn decode_response(buff: &mut BytesMut) -> Result<Option<Response>, Error> {
+    if buff.len() < RESPONSE_HEADER {
+        return Ok(None);
+    }
+    let length = u32::from_be_bytes([buff[6], buff[7], buff[8], buff[9]]) as usize;
+    if length > MAX_RESPONSE {
+        return Err(Error::Protocol(format!("response of {} bytes", length)));
+    }
+    if buff.len() < RESPONSE_HEADER + length {
+        return Ok(None);
+    }
+    let id = buff.get_u32();
+    let status = buff.get_u8();
+    buff.advance(5);
+    let payload = buff.split_to(length).to_vec();
+    Ok(Some(Response { id, status, payload }))
+}
+
+impl Response {
+    fn check(&self) -> Result<(), Error> {
+        if self.status == STATUS_OK {
+            return Ok(());
+        }
+        let e: Exception = from_slice(&self.payload).map_err(|e| Error::Protocol(format!("bad exception payload: {}", e)))?;
+        if self.status == STATUS_PROTOCOL_ERROR {
+            return Err(Error::Protocol(e.msg));
+        }
+        Err(Error::Server { kind: ErrorKind::from_code(e.code), code: e.code, message: e.msg })
+    }
+
+    pub fn empty(self) -> Result<(), Error> {
+        self.check()
+    }
+
+    // `sent` when an older server replies without a payload, e.g. the keys of Set / SetBatch
+    pub fn value_or<T: DeserializeOwned>(self, sent: T) -> Result<T, Error> {
+        if self.status == STATUS_OK && self.payload.is_empty() {
+            return Ok(sent);
+        }
+        self.value()
+    }
+
+    pub fn value<T: DeserializeOwned>(self) -> Result<T, Error> {
+        self.check()?;
+        from_slice(&self.payload).map_err(|e| Error::Protocol(format!("cannot decode response: {}", e)))
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt::Formatter;
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// wire types, serialized exactly like the server's entities
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DataType {
+    Float,
+    Long,
+    Double,
+    Number,
+    String,
+    ByteArray,
+}
+
+impl FromStr for DataType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "float" => Ok(DataType::Float),
+            "long" => Ok(DataType::Long),
+            "double" => Ok(DataType::Double),
+            "number" => Ok(DataType::Number),
+            "string" => Ok(DataType::String),
+            "bytearray" | "bytes" => Ok(DataType::ByteArray),
+            _ => Err(format!("unknown data type `{}`", s)),
+        }
+    }
+}
+
+/// How often the server starts a new segment file, `Nerve` keeps the series in memory only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SaveTimePeriod {
+    Nerve,
+    Minute,
+    TenMinutes,
+    Hour,
+    Day,
+}
+
+impl FromStr for SaveTimePeriod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('_', "").as_str() {
+            "nerve" | "never" => Ok(SaveTimePeriod::Nerve),
+            "minute" => Ok(SaveTimePeriod::Minute),
+            "tenminutes" => Ok(SaveTimePeriod::TenMinutes),
+            "hour" => Ok(SaveTimePeriod::Hour),
+            "day" => Ok(SaveTimePeriod::Day),
+            _ => Err(format!("unknown save period `{}`", s)),
+        }
+    }
+}
+
+/// Unit of a series' keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Precision {
+    #[serde(rename = "s")]
+    Seconds,
+    #[serde(rename = "ms")]
+    Millis,
+    #[serde(rename = "us")]
+    Micros,
+    #[serde(rename = "ns")]
+    Nanos,
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "s" => Ok(Precision::Seconds),
+            "ms" => Ok(Precision::Millis),
+            "us" => Ok(Precision::Micros),
+            "ns" => Ok(Precision::Nanos),
+            _ => Err(format!("unknown precision `{}`", s)),
+        }
+    }
+}
+
+/// How a series' segment files are compressed once sealed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!("unknown compression `{}`", s)),
+        }
+    }
+}
+
+/// A series description, capacity and save period fall back to the server's defaults, precision to milliseconds
+/// and compression to none.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TSItem {
+    #[serde(rename = "tsName")]
+    pub ts_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capacity: Option<usize>,
+    pub datatype: DataType,
+    #[serde(rename = "saveTime", skip_serializing_if = "Option::is_none")]
+    pub save_time: Option<SaveTimePeriod>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precision: Option<Precision>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+}
+
+impl TSItem {
+    pub fn new(ts_name: &str, datatype: DataType) -> TSItem {
+        TSItem { ts_name: ts_name.to_string(), capacity: None, datatype, save_time: None, precision: None, compression: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TSValue {
+    pub name: String,
+    pub key: u128,
+    pub value: TSCacheValue,
+}
+
+impl TSValue {
+    pub fn new(name: &str, key: u128, value: TSCacheValue) -> TSValue {
+        TSValue { name: name.to_string(), key, value }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TSPoint {
+    pub key: u128,
+    pub value: TSCacheValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TSCacheValue {
+    Float(f32),
+    Long(i64),
+    Double(f64),
+    Number(f64),
+    String(String),
+    ByteArray(Vec<u8>),
+}
+
+struct TSCacheValueVisitor;
+impl<'de> serde::de::Visitor<'de> for TSCacheValueVisitor {
+    type Value = TSCacheValue;
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("a number, string or byte array")
+    }
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> { Ok(TSCacheValue::Long(v)) }
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> { Ok(TSCacheValue::Long(v as i64)) }
+    fn visit_f32<E>(self, v: f32) -> Result<Self::Value, E> { Ok(TSCacheValue::Float(v)) }
+    fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E> { Ok(TSCacheValue::Double(v)) }
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> { Ok(TSCacheValue::String(v.to_owned())) }
+    fn visit_string<E>(self, v: String) -> Result<Self::Value, E> { Ok(TSCacheValue::String(v)) }
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E> { Ok(TSCacheValue::ByteArray(v.to_owned())) }
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E> { Ok(TSCacheValue::ByteArray(v)) }
+}
+
+impl<'de> Deserialize<'de> for TSCacheValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(TSCacheValueVisitor)
+    }
+}
+
+impl Serialize for TSCacheValue {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            TSCacheValue::Float(it) => serializer.serialize_f32(*it),
+            TSCacheValue::Long(it) => serializer.serialize_i64(*it),
+            TSCacheValue::Double(it) => serializer.serialize_f64(*it),
+            TSCacheValue::Number(it) => serializer.serialize_f64(*it),
+            TSCacheValue::String(it) => serializer.serialize_str(it),
+            TSCacheValue::ByteArray(it) => serializer.serialize_bytes(it),
+        }
+    }
+}
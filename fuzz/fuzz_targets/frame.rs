@@ -0,0 +1,34 @@
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+
+use time_cache::frame::{decode, decode_response, VERSION_1, VERSION_2};
+
+const MAX: usize = 64 * 1024;
+
+// the input is a stream of requests, fed in two chunks to exercise partial frames
+fuzz_target!(|data: &[u8]| {
+    let split = data.first().map_or(0, |b| *b as usize % (data.len() + 1));
+    let mut buff = BytesMut::from(&data[..split]);
+    let mut rest = &data[split..];
+    loop {
+        let before = buff.len();
+        match decode(&mut buff, MAX) {
+            Ok(Some(request)) => {
+                assert!(request.payload.len() <= MAX);
+                assert!(buff.len() < before);
+            }
+            Ok(None) if !rest.is_empty() => {
+                assert_eq!(buff.len(), before);
+                buff.extend_from_slice(rest);
+                rest = &[];
+            }
+            Ok(None) | Err(_) => break,
+        }
+    }
+    for version in [VERSION_1, VERSION_2] {
+        let mut buff = BytesMut::from(data);
+        while decode_response(&mut buff, version).is_some() {}
+    }
+});
@@ -0,0 +1,33 @@
+#![no_main]
+
+use std::sync::{Arc, OnceLock};
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+
+use time_cache::frame::{Request, VERSION_2};
+use time_cache::handle::process;
+use time_cache::{CacheDb, DataType, SaveTimePeriod, TSItem};
+
+static DB: OnceLock<Arc<CacheDb>> = OnceLock::new();
+
+// one in-memory series so Set/Get/alerts get past the name lookup
+fn db() -> &'static Arc<CacheDb> {
+    DB.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("tc-fuzz-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        time_cache::config::init(time_cache::Config { data_dir: dir.to_string_lossy().to_string(), ..Default::default() });
+        let db = CacheDb::new();
+        db.create(TSItem { tsName: "cpu".to_string(), capacity: 16, datatype: DataType::Long, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision: Default::default(), compression: Default::default() }).unwrap();
+        Arc::new(db)
+    })
+}
+
+// [u16 action][payload], every registered method decoder and unknown codes
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let action = u16::from_be_bytes([data[0], data[1]]);
+    let request = Request { version: VERSION_2, id: 0, flags: 0, action, payload: BytesMut::from(&data[2..]) };
+    let _ = process(&request, db());
+});
@@ -0,0 +1,239 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::Write;
+use std::time::SystemTime;
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use crate::entity::TSCacheValue;
+use crate::io::data_dir;
+use crate::method::{Exception, ExceptionKind};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AlertCondition {
+    // value > threshold
+    Above(f64),
+    // value < threshold
+    Below(f64),
+    // no point received
+    Absent,
+    // |change per second| over `window` seconds > limit
+    Rate { limit: f64, window: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertRule {
+    pub name: String,
+    pub ts_name: String,
+    pub condition: AlertCondition,
+    // seconds the condition must hold before firing
+    pub duration: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AlertState {
+    Inactive,
+    Pending,
+    Firing,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertEvent {
+    pub rule: String,
+    pub ts_name: String,
+    pub state: AlertState,
+    pub value: Option<f64>,
+    pub time: u128,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertStatus {
+    pub rule: AlertRule,
+    pub state: AlertState,
+    pub since: Option<u128>,
+    pub value: Option<f64>,
+}
+
+struct RuleTracker {
+    rule: AlertRule,
+    state: AlertState,
+    since: Option<u128>,
+    value: Option<f64>,
+    last_seen: u128,
+    samples: VecDeque<(u128, f64)>,
+}
+
+impl RuleTracker {
+    fn new(rule: AlertRule, now: u128) -> RuleTracker {
+        RuleTracker { rule, state: AlertState::Inactive, since: None, value: None, last_seen: now, samples: VecDeque::new() }
+    }
+
+    fn holds(&mut self, key: u128, value: f64) -> bool {
+        match self.rule.condition {
+            AlertCondition::Above(threshold) => value > threshold,
+            AlertCondition::Below(threshold) => value < threshold,
+            AlertCondition::Absent => false,
+            AlertCondition::Rate { limit, window } => {
+                // a sample older than the newest one seen would break the window, it is left out
+                if self.samples.back().is_none_or(|last| last.0 <= key) {
+                    self.samples.push_back((key, value));
+                }
+                let newest = self.samples.back().map_or(key, |it| it.0);
+                while let Some(&(time, _)) = self.samples.front() {
+                    if newest.saturating_sub(time) > window as u128 * 1000 { self.samples.pop_front(); } else { break; }
+                }
+                let (first, last) = match (self.samples.front(), self.samples.back()) {
+                    (Some(first), Some(last)) if last.0 > first.0 => (*first, *last),
+                    _ => return false,
+                };
+                let rate = (last.1 - first.1) / ((last.0 - first.0) as f64 / 1000.0);
+                rate.abs() > limit
+            }
+        }
+    }
+
+    // moves the tracker according to whether the condition holds at `time`
+    fn step(&mut self, holds: bool, time: u128) -> Option<AlertEvent> {
+        if !holds {
+            self.since = None;
+            let was_firing = self.state == AlertState::Firing;
+            self.state = AlertState::Inactive;
+            return if was_firing { Some(self.event(time)) } else { None };
+        }
+        let since = *self.since.get_or_insert(time);
+        if self.state == AlertState::Firing {
+            return None;
+        }
+        if time.saturating_sub(since) >= self.rule.duration as u128 * 1000 {
+            self.state = AlertState::Firing;
+            Some(self.event(time))
+        } else {
+            self.state = AlertState::Pending;
+            None
+        }
+    }
+
+    fn event(&self, time: u128) -> AlertEvent {
+        AlertEvent {
+            rule: self.rule.name.clone(),
+            ts_name: self.rule.ts_name.clone(),
+            state: self.state,
+            value: self.value,
+            time,
+        }
+    }
+}
+
+pub struct AlertManager {
+    rules: HashMap<String, RuleTracker>,
+    sender: broadcast::Sender<AlertEvent>,
+    log: Option<File>,
+}
+
+impl Default for AlertManager {
+    fn default() -> Self {
+        AlertManager::new()
+    }
+}
+
+impl AlertManager {
+    pub fn new() -> AlertManager {
+        let (sender, _) = broadcast::channel(1024);
+        AlertManager { rules: HashMap::new(), sender, log: None }
+    }
+
+    pub fn add_rule(&mut self, rule: AlertRule) -> Result<(), Exception> {
+        if self.rules.contains_key(&rule.name) {
+            return Err(Exception::err(ExceptionKind::AlertRuleError, format!("duplicate alert rule {}", rule.name).as_str()));
+        }
+        if let AlertCondition::Rate { window: 0, .. } = rule.condition {
+            return Err(Exception::err(ExceptionKind::AlertRuleError, "rate window must be greater than 0"));
+        }
+        self.rules.insert(rule.name.clone(), RuleTracker::new(rule, now_millis()));
+        Ok(())
+    }
+
+    pub fn remove_rule(&mut self, name: &str) -> Option<AlertRule> {
+        self.rules.remove(name).map(|it| it.rule)
+    }
+
+    pub fn rules(&self) -> Vec<&AlertRule> {
+        self.rules.values().map(|it| &it.rule).collect()
+    }
+
+    pub fn status(&self) -> Vec<AlertStatus> {
+        self.rules.values().map(|it| AlertStatus {
+            rule: it.rule.clone(),
+            state: it.state,
+            since: it.since,
+            value: it.value,
+        }).collect()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<AlertEvent> {
+        self.sender.subscribe()
+    }
+
+    // a point keyed `key` arriving at `now`; `for` durations run on the wall clock as in `tick`,
+    // keys only place the samples of a rate window
+    pub fn on_value(&mut self, ts_name: &str, key: u128, value: &TSCacheValue, now: u128) {
+        let number = value.as_f64();
+        let mut events = vec![];
+        for tracker in self.rules.values_mut().filter(|it| it.rule.ts_name == ts_name) {
+            tracker.last_seen = now;
+            if tracker.rule.condition == AlertCondition::Absent {
+                events.extend(tracker.step(false, now));
+                continue;
+            }
+            let Some(number) = number else { continue };
+            tracker.value = Some(number);
+            let holds = tracker.holds(key, number);
+            events.extend(tracker.step(holds, now));
+        }
+        events.into_iter().for_each(|event| self.publish(event));
+    }
+
+    // timer driven evaluation for rules that can change state without new points
+    pub fn tick(&mut self, now: u128) {
+        let mut events = vec![];
+        for tracker in self.rules.values_mut() {
+            match tracker.rule.condition {
+                AlertCondition::Absent => {
+                    let holds = now.saturating_sub(tracker.last_seen) >= tracker.rule.duration as u128 * 1000;
+                    if holds {
+                        tracker.since.get_or_insert(tracker.last_seen);
+                    }
+                    events.extend(tracker.step(holds, now));
+                }
+                _ if tracker.state == AlertState::Pending => {
+                    events.extend(tracker.step(true, now));
+                }
+                _ => {}
+            }
+        }
+        events.into_iter().for_each(|event| self.publish(event));
+    }
+
+    fn publish(&mut self, event: AlertEvent) {
+        info!("alert {} on {} is {:?}", event.rule, event.ts_name, event.state);
+        self.write_log(&event);
+        let _ = self.sender.send(event);
+    }
+
+    fn write_log(&mut self, event: &AlertEvent) {
+        if self.log.is_none() {
+            let _ = create_dir_all(data_dir());
+            self.log = OpenOptions::new().create(true).append(true).open(format!("{}/alerts.log", data_dir())).ok();
+        }
+        if let Some(ref mut file) = self.log {
+            let line = serde_json::to_string(event).unwrap();
+            if let Err(e) = writeln!(file, "{}", line) {
+                info!("failed to write alert log: {:?}", e);
+            }
+        }
+    }
+}
+
+pub fn now_millis() -> u128 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis()
+}
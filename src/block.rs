@@ -0,0 +1,307 @@
+use crate::entity::{DataType, TSCacheValue};
+
+// a run of points of one series packed Gorilla style: keys as delta-of-delta, doubles and floats
+// XORed with the previous value, longs as zigzag varint deltas, strings and bytes length prefixed.
+// `[u8 datatype][varint count][bits...]`, the same bytes are kept in memory and written to segments
+
+pub fn datatype_tag(datatype: &DataType) -> u8 {
+    match datatype {
+        DataType::Float => 0,
+        DataType::Long => 1,
+        DataType::Double => 2,
+        DataType::Number => 3,
+        DataType::String => 4,
+        DataType::ByteArray => 5,
+    }
+}
+
+fn tag_datatype(tag: u8) -> Option<DataType> {
+    match tag {
+        0 => Some(DataType::Float),
+        1 => Some(DataType::Long),
+        2 => Some(DataType::Double),
+        3 => Some(DataType::Number),
+        4 => Some(DataType::String),
+        5 => Some(DataType::ByteArray),
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    // bits used in the last byte, 0 when it is full
+    used: u32,
+}
+
+impl BitWriter {
+    fn bit(&mut self, bit: bool) {
+        if self.used == 0 {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> self.used;
+        }
+        self.used = (self.used + 1) % 8;
+    }
+
+    // the low `count` bits of `value`, most significant first
+    fn bits(&mut self, value: u64, count: u32) {
+        (0..count).rev().for_each(|i| self.bit(value >> i & 1 == 1));
+    }
+
+    fn varint(&mut self, mut value: u128) {
+        loop {
+            let byte = (value & 0x7f) as u64;
+            value >>= 7;
+            if value == 0 {
+                self.bits(byte, 8);
+                return;
+            }
+            self.bits(byte | 0x80, 8);
+        }
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> Result<bool, String> {
+        let byte = self.bytes.get(self.position / 8).ok_or("block ends early")?;
+        let bit = byte & (0x80 >> (self.position % 8)) != 0;
+        self.position += 1;
+        Ok(bit)
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u64, String> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = value << 1 | self.bit()? as u64;
+        }
+        Ok(value)
+    }
+
+    fn varint(&mut self) -> Result<u128, String> {
+        let mut value = 0u128;
+        for shift in (0..128).step_by(7) {
+            let byte = self.bits(8)?;
+            value |= ((byte & 0x7f) as u128) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("varint longer than 128 bits".to_string())
+    }
+}
+
+fn zigzag(value: i128) -> u128 {
+    ((value << 1) ^ (value >> 127)) as u128
+}
+
+fn unzigzag(value: u128) -> i128 {
+    (value >> 1) as i128 ^ -((value & 1) as i128)
+}
+
+// the Gorilla timestamp buckets: '0', then 7, 9 and 12 bit two's complement ranges, else a varint
+const BUCKETS: [(u64, u32, u32); 3] = [(0b10, 2, 7), (0b110, 3, 9), (0b1110, 4, 12)];
+
+fn float_bits(value: &TSCacheValue) -> u64 {
+    match value {
+        // in the high half so the same leading and trailing zero windows apply
+        TSCacheValue::Float(it) => (it.to_bits() as u64) << 32,
+        TSCacheValue::Double(it) | TSCacheValue::Number(it) => it.to_bits(),
+        _ => 0,
+    }
+}
+
+pub struct Encoder {
+    datatype: DataType,
+    count: usize,
+    out: BitWriter,
+    key: u128,
+    delta: u128,
+    value: u64,
+    long: i64,
+    // leading and trailing zeros of the last XOR window, None before the first
+    window: Option<(u32, u32)>,
+}
+
+impl Encoder {
+    pub fn new(datatype: &DataType) -> Encoder {
+        Encoder { datatype: datatype.clone(), count: 0, out: BitWriter::default(), key: 0, delta: 0, value: 0, long: 0, window: None }
+    }
+
+    // keys must grow and values must be of the datatype, anything else is not decodable
+    pub fn push(&mut self, key: u128, value: &TSCacheValue) {
+        self.push_key(key);
+        match value {
+            TSCacheValue::Float(_) | TSCacheValue::Double(_) | TSCacheValue::Number(_) => self.push_float(float_bits(value)),
+            TSCacheValue::Long(it) => {
+                self.out.varint(zigzag(it.wrapping_sub(self.long) as i128));
+                self.long = *it;
+            }
+            TSCacheValue::String(it) => self.push_bytes(it.as_bytes()),
+            TSCacheValue::ByteArray(it) => self.push_bytes(it),
+        }
+        self.count += 1;
+    }
+
+    fn push_key(&mut self, key: u128) {
+        match self.count {
+            0 => self.out.varint(key),
+            1 => {
+                self.delta = key - self.key;
+                self.out.varint(self.delta);
+            }
+            _ => {
+                let delta = key - self.key;
+                let dod = delta.wrapping_sub(self.delta) as i128;
+                self.delta = delta;
+                if dod == 0 {
+                    self.out.bit(false);
+                } else if let Some((prefix, size, bits)) = BUCKETS.iter().find(|(_, _, bits)| dod > -(1 << (bits - 1)) && dod <= 1 << (bits - 1)) {
+                    self.out.bits(*prefix, *size);
+                    self.out.bits((dod + (1 << (bits - 1)) - 1) as u64, *bits);
+                } else {
+                    self.out.bits(0b1111, 4);
+                    self.out.varint(zigzag(dod));
+                }
+            }
+        }
+        self.key = key;
+    }
+
+    fn push_float(&mut self, bits: u64) {
+        if self.count == 0 {
+            self.out.bits(bits, 64);
+            self.value = bits;
+            return;
+        }
+        let xor = bits ^ self.value;
+        self.value = bits;
+        if xor == 0 {
+            self.out.bit(false);
+            return;
+        }
+        self.out.bit(true);
+        let (leading, trailing) = (xor.leading_zeros().min(31), xor.trailing_zeros());
+        match self.window {
+            Some((last_leading, last_trailing)) if leading >= last_leading && trailing >= last_trailing => {
+                self.out.bit(false);
+                self.out.bits(xor >> last_trailing, 64 - last_leading - last_trailing);
+            }
+            _ => {
+                let length = 64 - leading - trailing;
+                self.out.bit(true);
+                self.out.bits(leading as u64, 5);
+                // 64 meaningful bits do not fit in 6, they are written as 0
+                self.out.bits(length as u64 % 64, 6);
+                self.out.bits(xor >> trailing, length);
+                self.window = Some((leading, trailing));
+            }
+        }
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        self.out.varint(bytes.len() as u128);
+        bytes.iter().for_each(|byte| self.out.bits(*byte as u64, 8));
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let mut head = BitWriter::default();
+        head.bits(datatype_tag(&self.datatype) as u64, 8);
+        head.varint(self.count as u128);
+        head.bytes.extend_from_slice(&self.out.bytes);
+        head.bytes
+    }
+}
+
+// every point of a block, oldest first
+pub fn decode(block: &[u8]) -> Result<Vec<(u128, TSCacheValue)>, String> {
+    let mut reader = BitReader { bytes: block, position: 0 };
+    let tag = reader.bits(8)? as u8;
+    let datatype = tag_datatype(tag).ok_or_else(|| format!("unknown datatype tag {}", tag))?;
+    let count = reader.varint()?;
+    // every point takes at least one bit
+    if count > block.len() as u128 * 8 {
+        return Err(format!("count {} does not fit in {} bytes", count, block.len()));
+    }
+    let mut points = Vec::with_capacity(count as usize);
+    let (mut key, mut delta, mut value, mut long, mut window) = (0u128, 0u128, 0u64, 0i64, (0u32, 0u32));
+    for n in 0..count {
+        key = match n {
+            0 => reader.varint()?,
+            1 => {
+                delta = reader.varint()?;
+                key.checked_add(delta).ok_or("key overflow")?
+            }
+            _ => {
+                let mut dod = 0i128;
+                if reader.bit()? {
+                    let mut bucket = None;
+                    for (_, _, bits) in BUCKETS {
+                        if !reader.bit()? {
+                            bucket = Some(bits);
+                            break;
+                        }
+                    }
+                    dod = match bucket {
+                        Some(bits) => reader.bits(bits)? as i128 - (1 << (bits - 1)) + 1,
+                        None => unzigzag(reader.varint()?),
+                    };
+                }
+                delta = delta.wrapping_add(dod as u128);
+                key.checked_add(delta).ok_or("key overflow")?
+            }
+        };
+        let point = match datatype {
+            DataType::Long => {
+                long = long.wrapping_add(unzigzag(reader.varint()?) as i64);
+                TSCacheValue::Long(long)
+            }
+            DataType::String | DataType::ByteArray => {
+                let length = reader.varint()?;
+                if length > (block.len() - reader.position / 8) as u128 {
+                    return Err(format!("value of {} bytes past the end of the block", length));
+                }
+                let bytes = (0..length).map(|_| reader.bits(8).map(|it| it as u8)).collect::<Result<Vec<u8>, String>>()?;
+                match datatype {
+                    DataType::String => TSCacheValue::String(String::from_utf8(bytes).map_err(|_| "string is not utf-8")?),
+                    _ => TSCacheValue::ByteArray(bytes),
+                }
+            }
+            _ => {
+                if n == 0 {
+                    value = reader.bits(64)?;
+                } else if reader.bit()? {
+                    if reader.bit()? {
+                        let leading = reader.bits(5)? as u32;
+                        let length = match reader.bits(6)? as u32 { 0 => 64, it => it };
+                        let trailing = 64u32.checked_sub(leading + length).ok_or("bad xor window")?;
+                        window = (leading, trailing);
+                    }
+                    let (leading, trailing) = window;
+                    value ^= reader.bits(64 - leading - trailing)? << trailing;
+                }
+                match datatype {
+                    DataType::Float => TSCacheValue::Float(f32::from_bits((value >> 32) as u32)),
+                    DataType::Double => TSCacheValue::Double(f64::from_bits(value)),
+                    _ => TSCacheValue::Number(f64::from_bits(value)),
+                }
+            }
+        };
+        points.push((key, point));
+    }
+    Ok(points)
+}
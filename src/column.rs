@@ -0,0 +1,142 @@
+use std::mem::size_of;
+use crate::entity::{DataType, TSCacheValue};
+
+// values of one series in a single allocation per type, slots are addressed like the queue's keys
+pub enum Column {
+    Float(Vec<f32>),
+    Long(Vec<i64>),
+    Double(Vec<f64>),
+    Number(Vec<f64>),
+    String(Arena),
+    ByteArray(Arena),
+}
+
+// strings or bytes back to back in one buffer; slots are replaced oldest first, so the bytes of
+// evicted slots always sit at the front of the buffer and are dropped once they are half of it
+#[derive(Default)]
+pub struct Arena {
+    data: Vec<u8>,
+    // absolute offset of data[0]
+    offset: u64,
+    // absolute end of the newest evicted value
+    evicted: u64,
+    spans: Vec<(u64, u32)>,
+}
+
+impl Arena {
+    fn get(&self, slot: usize) -> &[u8] {
+        let (start, len) = self.spans[slot];
+        let start = (start - self.offset) as usize;
+        &self.data[start..start + len as usize]
+    }
+
+    fn append(&mut self, bytes: &[u8]) -> (u64, u32) {
+        let span = (self.offset + self.data.len() as u64, bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+        span
+    }
+
+    fn push(&mut self, bytes: &[u8], limit: usize) {
+        let span = self.append(bytes);
+        push_bounded(&mut self.spans, span, limit);
+    }
+
+    fn replace(&mut self, slot: usize, bytes: &[u8]) {
+        let (start, len) = self.spans[slot];
+        self.evicted = start + len as u64;
+        self.spans[slot] = self.append(bytes);
+        let dead = (self.evicted - self.offset) as usize;
+        if dead * 2 > self.data.len() {
+            self.data.drain(..dead);
+            self.offset = self.evicted;
+            self.data.shrink_to(self.data.len() * 2);
+        }
+    }
+
+    fn heap_bytes(&self) -> usize {
+        self.data.capacity() + self.spans.capacity() * size_of::<(u64, u32)>()
+    }
+}
+
+// grows in steps instead of doubling so a full queue holds no more than `limit` slots
+pub(crate) fn push_bounded<T>(vec: &mut Vec<T>, item: T, limit: usize) {
+    if vec.len() == vec.capacity() {
+        vec.reserve_exact(vec.len().clamp(16, 4096).min(limit - vec.len()));
+    }
+    vec.push(item);
+}
+
+impl Column {
+    pub fn new(datatype: &DataType) -> Column {
+        match datatype {
+            DataType::Float => Column::Float(vec![]),
+            DataType::Long => Column::Long(vec![]),
+            DataType::Double => Column::Double(vec![]),
+            DataType::Number => Column::Number(vec![]),
+            DataType::String => Column::String(Arena::default()),
+            DataType::ByteArray => Column::ByteArray(Arena::default()),
+        }
+    }
+
+    pub fn get(&self, slot: usize) -> TSCacheValue {
+        match self {
+            Column::Float(values) => TSCacheValue::Float(values[slot]),
+            Column::Long(values) => TSCacheValue::Long(values[slot]),
+            Column::Double(values) => TSCacheValue::Double(values[slot]),
+            Column::Number(values) => TSCacheValue::Number(values[slot]),
+            Column::String(arena) => TSCacheValue::String(String::from_utf8_lossy(arena.get(slot)).into_owned()),
+            Column::ByteArray(arena) => TSCacheValue::ByteArray(arena.get(slot).to_vec()),
+        }
+    }
+
+    // appends when `slot` is the next free one, otherwise overwrites it; false when the value
+    // is not of the column's type
+    pub fn put(&mut self, slot: usize, value: &TSCacheValue, limit: usize) -> bool {
+        fn put<T>(values: &mut Vec<T>, slot: usize, value: T, limit: usize) {
+            if slot == values.len() { push_bounded(values, value, limit) } else { values[slot] = value }
+        }
+        fn put_bytes(arena: &mut Arena, slot: usize, bytes: &[u8], limit: usize) {
+            if slot == arena.spans.len() { arena.push(bytes, limit) } else { arena.replace(slot, bytes) }
+        }
+        match (self, value) {
+            (Column::Float(values), TSCacheValue::Float(it)) => put(values, slot, *it, limit),
+            (Column::Long(values), TSCacheValue::Long(it)) => put(values, slot, *it, limit),
+            (Column::Double(values), TSCacheValue::Double(it)) => put(values, slot, *it, limit),
+            (Column::Number(values), TSCacheValue::Number(it)) => put(values, slot, *it, limit),
+            (Column::String(arena), TSCacheValue::String(it)) => put_bytes(arena, slot, it.as_bytes(), limit),
+            (Column::ByteArray(arena), TSCacheValue::ByteArray(it)) => put_bytes(arena, slot, it, limit),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn clear(&mut self) {
+        match self {
+            Column::Float(values) => values.clear(),
+            Column::Long(values) => values.clear(),
+            Column::Double(values) | Column::Number(values) => values.clear(),
+            Column::String(arena) | Column::ByteArray(arena) => *arena = Arena::default(),
+        }
+    }
+
+    // bytes taken by the values held, without unused capacity
+    pub fn used_bytes(&self) -> usize {
+        match self {
+            Column::Float(values) => values.len() * size_of::<f32>(),
+            Column::Long(values) => values.len() * size_of::<i64>(),
+            Column::Double(values) | Column::Number(values) => values.len() * size_of::<f64>(),
+            Column::String(arena) | Column::ByteArray(arena) => {
+                arena.data.len() - (arena.evicted - arena.offset) as usize + arena.spans.len() * size_of::<(u64, u32)>()
+            }
+        }
+    }
+
+    pub fn heap_bytes(&self) -> usize {
+        match self {
+            Column::Float(values) => values.capacity() * size_of::<f32>(),
+            Column::Long(values) => values.capacity() * size_of::<i64>(),
+            Column::Double(values) | Column::Number(values) => values.capacity() * size_of::<f64>(),
+            Column::String(arena) | Column::ByteArray(arena) => arena.heap_bytes(),
+        }
+    }
+}
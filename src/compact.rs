@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{read_dir, remove_file};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use chrono::{Local, NaiveDate};
+use log::info;
+use tokio::sync::watch;
+use tokio::time::{interval_at, Instant};
+use crate::config::{self, CompactionWindow};
+use crate::db::CacheDb;
+use crate::entity::{SaveTimePeriod, TSCacheValue, TSItem, TSValue};
+use crate::io::{compacted_inputs, series_dir, read_segment, read_segment_range, write_compacted};
+
+const HOUR_MILLIS: u128 = 3_600_000;
+const DAY_MILLIS: u128 = 24 * HOUR_MILLIS;
+
+// a file under `{data_dir}/{name}/{date}/` and the wall clock span, in milliseconds, of the segments in it
+struct Segment {
+    path: PathBuf,
+    first: u128,
+    last: u128,
+    merged: bool,
+}
+
+// `{ms}.tc` as created by a writer or `{first}-{last}.tcx` by a merge, other files are not segments
+fn segment(path: PathBuf) -> Option<Segment> {
+    let stem = path.file_stem()?.to_str()?;
+    let (first, last, merged) = match path.extension()?.to_str()? {
+        "tc" => {
+            let time = stem.parse().ok()?;
+            (time, time, false)
+        }
+        "tcx" => {
+            let (first, last) = stem.split_once('-')?;
+            (first.parse().ok()?, last.parse().ok()?, true)
+        }
+        _ => return None,
+    };
+    Some(Segment { path, first, last, merged })
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|it| it.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+// the segments of a day, oldest first, and the ones a merged file names as its inputs: a merge is committed
+// by renaming its file into place, inputs still around were not removed before a crash and are read from the merge
+fn day_segments(dir: &Path) -> std::io::Result<(Vec<Segment>, Vec<Segment>)> {
+    let mut segments = vec![];
+    for entry in read_dir(dir)? {
+        segments.extend(segment(entry?.path()));
+    }
+    segments.sort_by_key(|it| (it.first, it.last));
+    let mut inputs = HashSet::new();
+    for merged in segments.iter().filter(|it| it.merged) {
+        let own = file_name(&merged.path);
+        inputs.extend(compacted_inputs(&merged.path)?.into_iter().filter(|it| *it != own));
+    }
+    Ok(segments.into_iter().partition(|it| !inputs.contains(&file_name(&it.path))))
+}
+
+fn day_dirs(name: &str) -> std::io::Result<Vec<PathBuf>> {
+    let dir = series_dir(name);
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut days = vec![];
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            days.push(path);
+        }
+    }
+    days.sort();
+    Ok(days)
+}
+
+// merges the segments of every finished day or hour of a series into one file each and returns how many
+// files were merged away; the newest segment is left alone since its writer may still be appending to it
+pub fn compact_series(item: &TSItem, window: CompactionWindow) -> std::io::Result<usize> {
+    let today = Local::now().format("%Y-%m-%d").to_string();
+    let hour = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() / HOUR_MILLIS;
+    let mut days = vec![];
+    for dir in day_dirs(&item.tsName)? {
+        let (live, stale) = day_segments(&dir)?;
+        for segment in stale {
+            remove_file(&segment.path)?;
+        }
+        days.push((dir, live));
+    }
+    let newest = days.iter().flat_map(|(_, live)| live).filter(|it| !it.merged).max_by_key(|it| it.first).map(|it| it.path.clone());
+    let mut merged = 0;
+    for (dir, live) in days {
+        let finished = dir.file_name().is_some_and(|it| *it.to_string_lossy() < *today);
+        let mut windows: BTreeMap<u128, Vec<Segment>> = BTreeMap::new();
+        for segment in live.into_iter().filter(|it| Some(&it.path) != newest.as_ref()) {
+            match window {
+                CompactionWindow::Day if finished => windows.entry(0).or_default().push(segment),
+                CompactionWindow::Hour if segment.first / HOUR_MILLIS < hour => windows.entry(segment.first / HOUR_MILLIS).or_default().push(segment),
+                _ => {}
+            }
+        }
+        for segments in windows.into_values().filter(|it| it.len() > 1) {
+            merge(item, &dir, &segments)?;
+            merged += segments.len();
+        }
+    }
+    Ok(merged)
+}
+
+fn merge(item: &TSItem, dir: &Path, segments: &[Segment]) -> std::io::Result<()> {
+    let mut points: Vec<(u128, TSCacheValue)> = vec![];
+    for segment in segments {
+        points.extend(read_segment(&segment.path)?.into_iter().map(|it| (it.key, it.value)));
+    }
+    // keys only grow within a run of the server, a restart may go back in time
+    points.sort_by_key(|it| it.0);
+    let first = segments.iter().map(|it| it.first).min().unwrap_or_default();
+    let last = segments.iter().map(|it| it.last).max().unwrap_or_default();
+    let path = dir.join(format!("{}-{}.tcx", first, last));
+    let inputs: Vec<String> = segments.iter().map(|it| file_name(&it.path)).collect();
+    write_compacted(&path, &item.datatype, &points, item.compression, &inputs)?;
+    // a merged file spanning the whole window was just replaced by the new one
+    for segment in segments.iter().filter(|it| it.path != path) {
+        remove_file(&segment.path)?;
+    }
+    Ok(())
+}
+
+// one pass over every series written to disk
+pub fn compact_all(db: &CacheDb, window: CompactionWindow) -> usize {
+    let mut merged = 0;
+    for item in db.items().iter().filter(|it| it.saveTime != SaveTimePeriod::Nerve) {
+        match compact_series(item, window) {
+            Ok(0) => {}
+            Ok(n) => {
+                info!("compacted {} segments of {}", n, item.tsName);
+                merged += n;
+            }
+            Err(e) => info!("failed to compact {}: {:?}", item.tsName, e),
+        }
+    }
+    merged
+}
+
+pub async fn run(db: Arc<CacheDb>, mut shutdown: watch::Receiver<bool>) {
+    let conf = &config::get().compaction;
+    if conf.interval_secs == 0 {
+        return;
+    }
+    let period = Duration::from_secs(conf.interval_secs);
+    let mut interval = interval_at(Instant::now() + period, period);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.wait_for(|stop| *stop) => return,
+        }
+        let db = db.clone();
+        let window = conf.window;
+        let _ = tokio::task::spawn_blocking(move || compact_all(&db, window)).await;
+    }
+}
+
+// the points of a series on disk with `start <= key <= end`, key ordered; merged files only read the blocks in range.
+// Reading more than `limit` points fails with `QuotaExceeded`
+pub fn history(item: &TSItem, start: u128, end: u128, limit: usize) -> std::io::Result<Vec<TSValue>> {
+    // a merge running meanwhile removes files after they were listed, they are listed again
+    let mut attempts = 3;
+    loop {
+        match read_history(item, start, end, limit) {
+            Err(e) if e.kind() == ErrorKind::NotFound && attempts > 1 => attempts -= 1,
+            other => return other,
+        }
+    }
+}
+
+fn read_history(item: &TSItem, start: u128, end: u128, limit: usize) -> std::io::Result<Vec<TSValue>> {
+    let days = day_dirs(&item.tsName)?;
+    let start_millis = item.precision.to_millis(start);
+    let mut values = vec![];
+    for (n, dir) in days.iter().enumerate() {
+        // a day's segments are written to until the next day's first segment is created, keys being
+        // times no later than their write skip the days that ended before `start`
+        let written_until = days.get(n + 1).and_then(|next| day_millis(next)).map(|it| it + 2 * DAY_MILLIS);
+        if written_until.is_some_and(|it| it < start_millis) {
+            continue;
+        }
+        for segment in day_segments(dir)?.0 {
+            values.extend(read_segment_range(&segment.path, start, end)?);
+            if values.len() > limit {
+                return Err(std::io::Error::new(ErrorKind::QuotaExceeded, format!("{} has more than {} points on disk in range", item.tsName, limit)));
+            }
+        }
+    }
+    values.sort_by_key(|it| it.key);
+    Ok(values)
+}
+
+// midnight UTC of a `{date}` directory's day, the local day it names starts within 14 hours of it
+fn day_millis(dir: &Path) -> Option<u128> {
+    let day = NaiveDate::parse_from_str(dir.file_name()?.to_str()?, "%Y-%m-%d").ok()?;
+    u128::try_from(day.and_hms_opt(0, 0, 0)?.and_utc().timestamp_millis()).ok()
+}
@@ -0,0 +1,421 @@
+use std::fs::{create_dir_all, read_to_string};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use clap::Parser;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use crate::entity::{Compression, Precision, SaveTimePeriod};
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: String,
+    pub http_listen: Option<String>,
+    pub resp_listen: Option<String>,
+    pub data_dir: String,
+    pub log_config: String,
+    pub default_capacity: usize,
+    pub default_save_time: SaveTimePeriod,
+    pub shutdown_timeout_secs: u64,
+    pub read_timeout_secs: u64,
+    pub idle_timeout_secs: u64,
+    pub writer_threads: usize,
+    pub write_queue_size: usize,
+    pub limits: Limits,
+    pub auto_create: AutoCreate,
+    pub influx: Influx,
+    pub prometheus: Prometheus,
+    pub graphite: Graphite,
+    pub statsd: Statsd,
+    pub compaction: Compaction,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_series: usize,
+    pub max_capacity: usize,
+    pub max_connections: usize,
+    pub max_frame_size: usize,
+    // points one range query may read from the segments on disk
+    pub max_history_points: usize,
+}
+
+// series created by their first Set / SetBatch point, typed after the value, instead of failing with 4002
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoCreate {
+    // every unknown name, otherwise only names matching a template
+    pub enabled: bool,
+    pub templates: Vec<Template>,
+}
+
+// settings of auto-created series whose name starts with `prefix`, unset ones use the defaults;
+// also applies to series created by the line protocol, Prometheus, Graphite and StatsD receivers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    pub prefix: String,
+    // points kept, the series' retention
+    #[serde(default)]
+    pub capacity: Option<usize>,
+    #[serde(default)]
+    pub save_time: Option<SaveTimePeriod>,
+    #[serde(default)]
+    pub precision: Option<Precision>,
+    #[serde(default)]
+    pub compression: Option<Compression>,
+}
+
+impl AutoCreate {
+    // the template with the longest prefix of `name`
+    pub fn template(&self, name: &str) -> Option<&Template> {
+        self.templates.iter().filter(|it| name.starts_with(&it.prefix)).max_by_key(|it| it.prefix.len())
+    }
+
+    pub fn allows(&self, name: &str) -> bool {
+        self.enabled || self.template(name).is_some()
+    }
+}
+
+// line protocol ingestion, HTTP writes go through the gateway at /write
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Influx {
+    pub tcp_listen: Option<String>,
+    pub udp_listen: Option<String>,
+    // create missing series with the type of the first value written
+    pub auto_create: bool,
+    // sorted tags become part of the series name, `cpu.usage;host=a`, otherwise only labels
+    pub tags_in_name: bool,
+    // used when an HTTP write has no precision parameter and for TCP and UDP
+    pub precision: Precision,
+}
+
+// remote_write and remote_read at /api/v1/write and /api/v1/read on the HTTP gateway
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Prometheus {
+    // create missing series as Double with the sample's labels
+    pub auto_create: bool,
+    // regexes on the whole series name choosing what GET /metrics exposes, an empty include list exposes all
+    pub metrics_include: Vec<String>,
+    pub metrics_exclude: Vec<String>,
+}
+
+// Graphite plaintext `path value timestamp` lines over TCP
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Graphite {
+    pub tcp_listen: Option<String>,
+    // create missing series as Double, tagged paths keep their tags as labels
+    pub auto_create: bool,
+}
+
+// StatsD datagrams, aggregated in memory and written once per flush interval
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Statsd {
+    pub udp_listen: Option<String>,
+    pub flush_interval_secs: u64,
+    // timers get a `name.pNN` series for each of these
+    pub percentiles: Vec<f64>,
+    // create missing series as Double
+    pub auto_create: bool,
+}
+
+// merges the sealed segments of each finished day or hour of a series into one indexed file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Compaction {
+    // seconds between passes over all series, 0 turns the compactor off
+    pub interval_secs: u64,
+    pub window: CompactionWindow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompactionWindow {
+    Day,
+    Hour,
+}
+
+impl FromStr for CompactionWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "day" => Ok(CompactionWindow::Day),
+            "hour" => Ok(CompactionWindow::Hour),
+            _ => Err(format!("unknown compaction window `{}`", s)),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: "127.0.0.1:8080".to_string(),
+            http_listen: None,
+            resp_listen: None,
+            data_dir: "./data".to_string(),
+            log_config: "log4rs.yaml".to_string(),
+            default_capacity: 1000,
+            default_save_time: SaveTimePeriod::Minute,
+            shutdown_timeout_secs: 30,
+            read_timeout_secs: 30,
+            idle_timeout_secs: 0,
+            writer_threads: 2,
+            write_queue_size: 10000,
+            limits: Limits::default(),
+            auto_create: AutoCreate::default(),
+            influx: Influx::default(),
+            prometheus: Prometheus::default(),
+            graphite: Graphite::default(),
+            statsd: Statsd::default(),
+            compaction: Compaction::default(),
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits { max_series: 10000, max_capacity: 10_000_000, max_connections: 1024, max_frame_size: 16 * 1024 * 1024, max_history_points: 1_000_000 }
+    }
+}
+
+impl Default for Influx {
+    fn default() -> Self {
+        Influx { tcp_listen: None, udp_listen: None, auto_create: true, tags_in_name: true, precision: Precision::Nanos }
+    }
+}
+
+impl Default for Prometheus {
+    fn default() -> Self {
+        Prometheus { auto_create: true, metrics_include: vec![], metrics_exclude: vec![] }
+    }
+}
+
+impl Default for Graphite {
+    fn default() -> Self {
+        Graphite { tcp_listen: None, auto_create: true }
+    }
+}
+
+impl Default for Statsd {
+    fn default() -> Self {
+        Statsd { udp_listen: None, flush_interval_secs: 10, percentiles: vec![95.0], auto_create: true }
+    }
+}
+
+impl Default for Compaction {
+    fn default() -> Self {
+        Compaction { interval_secs: 3600, window: CompactionWindow::Day }
+    }
+}
+
+// command line flags, each one can also come from a TC_* environment variable
+#[derive(Debug, Parser)]
+#[command(name = "time-cache", version, about = "time series memory cache server")]
+pub struct Args {
+    #[arg(short, long, env = "TC_CONFIG", default_value = "time-cache.toml")]
+    pub config: String,
+    #[arg(long, env = "TC_LISTEN")]
+    pub listen: Option<String>,
+    #[arg(long, env = "TC_HTTP_LISTEN")]
+    pub http_listen: Option<String>,
+    #[arg(long, env = "TC_RESP_LISTEN")]
+    pub resp_listen: Option<String>,
+    #[arg(long, env = "TC_DATA_DIR")]
+    pub data_dir: Option<String>,
+    #[arg(long, env = "TC_LOG_CONFIG")]
+    pub log_config: Option<String>,
+    #[arg(long, env = "TC_DEFAULT_CAPACITY")]
+    pub default_capacity: Option<usize>,
+    #[arg(long, env = "TC_DEFAULT_SAVE_TIME")]
+    pub default_save_time: Option<SaveTimePeriod>,
+    #[arg(long, env = "TC_SHUTDOWN_TIMEOUT_SECS")]
+    pub shutdown_timeout_secs: Option<u64>,
+    #[arg(long, env = "TC_READ_TIMEOUT_SECS")]
+    pub read_timeout_secs: Option<u64>,
+    #[arg(long, env = "TC_IDLE_TIMEOUT_SECS")]
+    pub idle_timeout_secs: Option<u64>,
+    #[arg(long, env = "TC_WRITER_THREADS")]
+    pub writer_threads: Option<usize>,
+    #[arg(long, env = "TC_WRITE_QUEUE_SIZE")]
+    pub write_queue_size: Option<usize>,
+    #[arg(long, env = "TC_MAX_SERIES")]
+    pub max_series: Option<usize>,
+    #[arg(long, env = "TC_MAX_CAPACITY")]
+    pub max_capacity: Option<usize>,
+    #[arg(long, env = "TC_MAX_CONNECTIONS")]
+    pub max_connections: Option<usize>,
+    #[arg(long, env = "TC_MAX_FRAME_SIZE")]
+    pub max_frame_size: Option<usize>,
+    #[arg(long, env = "TC_MAX_HISTORY_POINTS")]
+    pub max_history_points: Option<usize>,
+    #[arg(long, env = "TC_AUTO_CREATE")]
+    pub auto_create: Option<bool>,
+    #[arg(long, env = "TC_INFLUX_TCP_LISTEN")]
+    pub influx_tcp_listen: Option<String>,
+    #[arg(long, env = "TC_INFLUX_UDP_LISTEN")]
+    pub influx_udp_listen: Option<String>,
+    #[arg(long, env = "TC_INFLUX_AUTO_CREATE")]
+    pub influx_auto_create: Option<bool>,
+    #[arg(long, env = "TC_INFLUX_TAGS_IN_NAME")]
+    pub influx_tags_in_name: Option<bool>,
+    #[arg(long, env = "TC_INFLUX_PRECISION")]
+    pub influx_precision: Option<Precision>,
+    #[arg(long, env = "TC_PROMETHEUS_AUTO_CREATE")]
+    pub prometheus_auto_create: Option<bool>,
+    #[arg(long, env = "TC_GRAPHITE_TCP_LISTEN")]
+    pub graphite_tcp_listen: Option<String>,
+    #[arg(long, env = "TC_GRAPHITE_AUTO_CREATE")]
+    pub graphite_auto_create: Option<bool>,
+    #[arg(long, env = "TC_STATSD_UDP_LISTEN")]
+    pub statsd_udp_listen: Option<String>,
+    #[arg(long, env = "TC_STATSD_FLUSH_INTERVAL_SECS")]
+    pub statsd_flush_interval_secs: Option<u64>,
+    #[arg(long, env = "TC_STATSD_AUTO_CREATE")]
+    pub statsd_auto_create: Option<bool>,
+    #[arg(long, env = "TC_COMPACTION_INTERVAL_SECS")]
+    pub compaction_interval_secs: Option<u64>,
+    #[arg(long, env = "TC_COMPACTION_WINDOW")]
+    pub compaction_window: Option<CompactionWindow>,
+}
+
+impl Config {
+    // file < environment < command line, the file may be missing unless given explicitly
+    pub fn load(args: &Args) -> Result<Config, String> {
+        let path = Path::new(&args.config);
+        let mut config = if path.exists() {
+            let text = read_to_string(path).map_err(|e| format!("cannot read config {}: {}", args.config, e))?;
+            Config::parse(&text).map_err(|e| format!("invalid config {}: {}", args.config, e))?
+        } else if args.config != "time-cache.toml" {
+            return Err(format!("config file {} not found", args.config));
+        } else {
+            Config::default()
+        };
+        config.apply(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    fn apply(&mut self, args: &Args) {
+        if let Some(ref v) = args.listen { self.listen = v.clone(); }
+        if let Some(ref v) = args.http_listen { self.http_listen = Some(v.clone()); }
+        if let Some(ref v) = args.resp_listen { self.resp_listen = Some(v.clone()); }
+        if let Some(ref v) = args.data_dir { self.data_dir = v.clone(); }
+        if let Some(ref v) = args.log_config { self.log_config = v.clone(); }
+        if let Some(v) = args.default_capacity { self.default_capacity = v; }
+        if let Some(ref v) = args.default_save_time { self.default_save_time = v.clone(); }
+        if let Some(v) = args.shutdown_timeout_secs { self.shutdown_timeout_secs = v; }
+        if let Some(v) = args.read_timeout_secs { self.read_timeout_secs = v; }
+        if let Some(v) = args.idle_timeout_secs { self.idle_timeout_secs = v; }
+        if let Some(v) = args.writer_threads { self.writer_threads = v; }
+        if let Some(v) = args.write_queue_size { self.write_queue_size = v; }
+        if let Some(v) = args.max_series { self.limits.max_series = v; }
+        if let Some(v) = args.max_capacity { self.limits.max_capacity = v; }
+        if let Some(v) = args.max_connections { self.limits.max_connections = v; }
+        if let Some(v) = args.max_frame_size { self.limits.max_frame_size = v; }
+        if let Some(v) = args.max_history_points { self.limits.max_history_points = v; }
+        if let Some(v) = args.auto_create { self.auto_create.enabled = v; }
+        if let Some(ref v) = args.influx_tcp_listen { self.influx.tcp_listen = Some(v.clone()); }
+        if let Some(ref v) = args.influx_udp_listen { self.influx.udp_listen = Some(v.clone()); }
+        if let Some(v) = args.influx_auto_create { self.influx.auto_create = v; }
+        if let Some(v) = args.influx_tags_in_name { self.influx.tags_in_name = v; }
+        if let Some(v) = args.influx_precision { self.influx.precision = v; }
+        if let Some(v) = args.prometheus_auto_create { self.prometheus.auto_create = v; }
+        if let Some(ref v) = args.graphite_tcp_listen { self.graphite.tcp_listen = Some(v.clone()); }
+        if let Some(v) = args.graphite_auto_create { self.graphite.auto_create = v; }
+        if let Some(ref v) = args.statsd_udp_listen { self.statsd.udp_listen = Some(v.clone()); }
+        if let Some(v) = args.statsd_flush_interval_secs { self.statsd.flush_interval_secs = v; }
+        if let Some(v) = args.statsd_auto_create { self.statsd.auto_create = v; }
+        if let Some(v) = args.compaction_interval_secs { self.compaction.interval_secs = v; }
+        if let Some(v) = args.compaction_window { self.compaction.window = v; }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Err(e) = self.listen.parse::<SocketAddr>() {
+            return Err(format!("listen `{}` is not a socket address: {}", self.listen, e));
+        }
+        let optional = [
+            ("http_listen", &self.http_listen),
+            ("resp_listen", &self.resp_listen),
+            ("influx.tcp_listen", &self.influx.tcp_listen),
+            ("influx.udp_listen", &self.influx.udp_listen),
+            ("graphite.tcp_listen", &self.graphite.tcp_listen),
+            ("statsd.udp_listen", &self.statsd.udp_listen),
+        ];
+        for (key, addr) in optional {
+            if let Some(addr) = addr {
+                if let Err(e) = addr.parse::<SocketAddr>() {
+                    return Err(format!("{} `{}` is not a socket address: {}", key, addr, e));
+                }
+            }
+        }
+        for pattern in self.prometheus.metrics_include.iter().chain(&self.prometheus.metrics_exclude) {
+            if let Err(e) = Regex::new(pattern) {
+                return Err(format!("metrics pattern `{}` is not a valid regex: {}", pattern, e));
+            }
+        }
+        for template in &self.auto_create.templates {
+            if let Some(capacity) = template.capacity.filter(|it| *it == 0 || *it > self.limits.max_capacity) {
+                return Err(format!("auto_create template `{}` capacity {} must be in 1..={}", template.prefix, capacity, self.limits.max_capacity));
+            }
+        }
+        if self.statsd.flush_interval_secs == 0 {
+            return Err("statsd.flush_interval_secs must be greater than 0".to_string());
+        }
+        if let Some(p) = self.statsd.percentiles.iter().find(|p| !(**p > 0.0 && **p <= 100.0)) {
+            return Err(format!("statsd percentile {} must be in (0, 100]", p));
+        }
+        if self.data_dir.is_empty() {
+            return Err("data_dir must not be empty".to_string());
+        }
+        if let Err(e) = create_dir_all(&self.data_dir) {
+            return Err(format!("data_dir `{}` cannot be created: {}", self.data_dir, e));
+        }
+        if !Path::new(&self.log_config).is_file() {
+            return Err(format!("log_config `{}` does not exist", self.log_config));
+        }
+        if self.writer_threads == 0 || self.write_queue_size == 0 {
+            return Err("writer_threads and write_queue_size must be greater than 0".to_string());
+        }
+        let limits = &self.limits;
+        if limits.max_capacity == 0 || limits.max_series == 0 || limits.max_connections == 0 || limits.max_frame_size == 0 || limits.max_history_points == 0 {
+            return Err("limits must be greater than 0".to_string());
+        }
+        if self.default_capacity == 0 || self.default_capacity > self.limits.max_capacity {
+            return Err(format!("default_capacity {} must be in 1..={}", self.default_capacity, self.limits.max_capacity));
+        }
+        Ok(())
+    }
+}
+
+pub fn init(config: Config) {
+    if CONFIG.set(config).is_err() {
+        panic!("config already initialized");
+    }
+}
+
+// falls back to the defaults when `init` was never called, e.g. in tests
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+pub fn default_capacity() -> usize {
+    get().default_capacity
+}
+
+pub fn default_save_time() -> SaveTimePeriod {
+    get().default_save_time.clone()
+}
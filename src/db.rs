@@ -1,71 +1,421 @@
 use std::collections::HashMap;
 use std::mem;
-use chrono::format::Item;
-use crate::entity::{TSCacheValue, TSItem, TSValue};
-use crate::io::{read_all_items, write_all_items, FileIOCache};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use log::info;
+use crate::alert::{now_millis, AlertManager, AlertRule};
+use crate::{compact, config};
+use serde::{Deserialize, Serialize};
+use crate::entity::{DataType, Precision, SaveTimePeriod, TSCacheValue, TSItem, TSPoint, TSValue};
+use crate::io::{read_all_items, read_all_notifiers, read_all_rules, write_all_items, write_all_notifiers, write_all_rules};
 use crate::method::{Exception, ExceptionKind, TSQueue};
+use crate::notify::{build, NotifierConfig};
+use crate::writer::SegmentWriter;
+
+// one time series, writers of different series never wait on each other
+pub struct Series {
+    pub item: TSItem,
+    queue: RwLock<TSQueue>,
+    writer: Arc<SegmentWriter>,
+    // alert rules bound to the series, inserts skip the alerts lock while there are none
+    rules: AtomicUsize,
+}
+
+impl Series {
+    fn new(item: TSItem, queue: TSQueue, rules: usize) -> Series {
+        let writer = SegmentWriter::new(item.clone(), config::get().write_queue_size);
+        Series { item, queue: RwLock::new(queue), writer, rules: AtomicUsize::new(rules) }
+    }
+
+    pub fn queue(&self) -> RwLockReadGuard<'_, TSQueue> {
+        self.queue.read().unwrap()
+    }
+}
+
+// what one series' queue holds in memory, see CacheDb::memory
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MemoryUsage {
+    pub name: String,
+    pub datatype: DataType,
+    pub points: usize,
+    pub capacity: usize,
+    pub bytes: usize,
+    #[serde(rename = "compressionRatio")]
+    pub compression_ratio: f64,
+}
+
+// a series as List describes it, the item and how well its queue packs the points
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeriesInfo {
+    #[serde(flatten)]
+    pub item: TSItem,
+    #[serde(rename = "compressionRatio")]
+    pub compression_ratio: f64,
+}
+
+// the catalog is read-mostly, it is only write locked to add series
 pub struct CacheDb {
-    cache: HashMap<String, TSQueue>,
-    items: HashMap<String, TSItem>,
-    ios: HashMap<String, FileIOCache>,
+    series: RwLock<HashMap<String, Arc<Series>>>,
+    alerts: Mutex<AlertManager>,
+    notifiers: RwLock<Vec<NotifierConfig>>,
+    // orders the catalog writes made after the series lock is released
+    catalog: Mutex<()>,
+}
+
+impl Default for CacheDb {
+    fn default() -> Self {
+        CacheDb::new()
+    }
 }
 
 impl CacheDb {
     pub fn new() -> CacheDb {
-        CacheDb { cache: HashMap::new(), items: HashMap::new(), ios: HashMap::new() }
+        CacheDb { series: RwLock::new(HashMap::new()), alerts: Mutex::new(AlertManager::new()), notifiers: RwLock::new(vec![]), catalog: Mutex::new(()) }
+    }
+
+    // a database loaded from the catalogs in the configured data dir
+    pub fn open() -> Result<CacheDb, Exception> {
+        let mut db = CacheDb::new();
+        db.init()?;
+        Ok(db)
     }
 
     pub fn contains_key(&self, key: &str) -> bool {
-        self.cache.contains_key(key)
+        self.series.read().unwrap().contains_key(key)
     }
 
-    pub fn init(&mut self) {
+    pub fn len(&self) -> usize {
+        self.series.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.series.read().unwrap().is_empty()
+    }
+
+    pub fn init(&mut self) -> Result<(), Exception> {
         let mut values = vec![];
-        read_all_items(&mut values);
-        values.iter().for_each(|item| {
-            self.create_item(item.clone());
+        read_all_items(&mut values).map_err(|e| disk_error("series catalog", e))?;
+        let series = self.series.get_mut().unwrap();
+        values.into_iter().for_each(|item| {
+            let queue = TSQueue::new(Box::new(item.clone()), item.capacity);
+            series.insert(item.tsName.clone(), Arc::new(Series::new(item, queue, 0)));
         });
+        let mut rules = vec![];
+        read_all_rules(&mut rules).map_err(|e| disk_error("alert rules", e))?;
+        let alerts = self.alerts.get_mut().unwrap();
+        rules.into_iter().for_each(|rule| {
+            let bound = series.get(&rule.ts_name).cloned();
+            match alerts.add_rule(rule) {
+                Ok(()) => if let Some(it) = bound { it.rules.fetch_add(1, Ordering::Relaxed); },
+                Err(e) => info!("skip alert rule: {}", e.msg),
+            }
+        });
+        read_all_notifiers(self.notifiers.get_mut().unwrap()).map_err(|e| disk_error("notifiers", e))
     }
 
-    fn create_item(&mut self, item: TSItem) {
-        let new = item.clone();
-        let name = item.tsName;
+    pub fn create(&self, item: TSItem) -> Result<(), Exception> {
         let cap = item.capacity;
-        self.cache.insert(name.clone(), TSQueue::new(Box::new(new.clone()), cap));
-        self.items.insert(name.clone(), new.clone());
-        self.ios.insert(name, FileIOCache::new(Box::new(new)));
+        let max_capacity = config::get().limits.max_capacity;
+        if cap == 0 || cap > max_capacity {
+            return Err(Exception::err(ExceptionKind::LimitError, format!("capacity must be in 1..={}", max_capacity).as_str()));
+        }
+        let queue = TSQueue::new(Box::new(item.clone()), cap);
+        self.create_new_item(item, queue)
     }
 
-    pub fn create_new_item(&mut self, item: TSItem, queue: TSQueue) {
-        let new = item.clone();
-        let new_item = item.clone();
-        let name = item.tsName;
-        self.cache.insert(name.clone(), queue);
-        self.items.insert(name.clone(), new);
-        self.ios.insert(name, FileIOCache::new(Box::new(new_item)));
+    pub fn create_new_item(&self, item: TSItem, queue: TSQueue) -> Result<(), Exception> {
+        check_name(&item.tsName)?;
+        // rules loaded for a series that did not exist yet, counted before the series lock is taken
+        let rules = self.alerts().rules().iter().filter(|it| it.ts_name == item.tsName).count();
+        let mut series = self.series.write().unwrap();
+        if series.contains_key(&item.tsName) {
+            return Err(Exception::err(ExceptionKind::TSNameExistsError, format!("duplicate TSName {}", item.tsName).as_str()));
+        }
+        let max_series = config::get().limits.max_series;
+        if series.len() >= max_series {
+            return Err(Exception::err(ExceptionKind::LimitError, format!("series count reached limit {}", max_series).as_str()));
+        }
+        let name = item.tsName.clone();
+        let created = Arc::new(Series::new(item, queue, rules));
+        series.insert(name.clone(), created.clone());
+        if let Err(e) = self.write_items(series) {
+            // a series missing from the catalog would be lost on restart
+            let mut series = self.series.write().unwrap();
+            if series.get(&name).is_some_and(|it| Arc::ptr_eq(it, &created)) {
+                series.remove(&name);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
 
-        let mut values = vec![];
-        self.items.iter().for_each(|(_, io)| {
-            values.push(io);
+    // snapshots the catalog and writes it once the series lock is released
+    fn write_items(&self, series: RwLockWriteGuard<'_, HashMap<String, Arc<Series>>>) -> Result<(), Exception> {
+        let items: Vec<TSItem> = series.values().map(|it| it.item.clone()).collect();
+        let _catalog = self.catalog.lock().unwrap();
+        drop(series);
+        write_all_items(&items.iter().collect()).map_err(|e| disk_error("series catalog", e))
+    }
+
+    pub fn get(&self, key: &str) -> Option<Arc<Series>> {
+        self.series.read().unwrap().get(key).cloned()
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<Series>, Exception> {
+        match self.get(name) {
+            Some(series) => Ok(series),
+            None => Err(Exception::err(ExceptionKind::TSNameExistsError, format!("TSName {} not exist", name).as_str())),
+        }
+    }
+
+    pub fn set(&self, mut value: TSValue) -> Result<u128, Exception> {
+        self.insert_new_value(&mut value)
+    }
+
+    // the newest value of the series
+    pub fn last(&self, name: &str) -> Result<TSCacheValue, Exception> {
+        let series = self.find(name)?;
+        let queue = series.queue();
+        match queue.query_last() {
+            Some(v) => Ok(v),
+            None => Err(Exception::err(ExceptionKind::QueueIsNullError, format!("Queue is empty:{}", name).as_str())),
+        }
+    }
+
+    // every point with start <= key <= end, oldest first; keys older than the oldest one still in the
+    // queue are read from the series' segments on disk
+    pub fn range(&self, name: &str, start: u128, end: u128) -> Result<Vec<TSPoint>, Exception> {
+        let series = self.find(name)?;
+        let (first, points) = {
+            let queue = series.queue();
+            (queue.query_first().map(|it| it.0), queue.query_times(start, end))
+        };
+        let points = points.into_iter().map(|(key, value)| TSPoint { key, value });
+        let older = match first {
+            Some(first) => first.checked_sub(1).map(|it| it.min(end)),
+            None => Some(end),
+        };
+        let older = match older {
+            Some(older) if start <= older && series.item.saveTime != SaveTimePeriod::Nerve => older,
+            _ => return Ok(points.collect()),
+        };
+        let limit = config::get().limits.max_history_points;
+        let read = || compact::history(&series.item, start, older, limit);
+        let history = blocking(|| match read() {
+            // a batch was being appended to the open segment, read it again once the writer is done
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => series.writer.read(read),
+            other => other,
         });
-        write_all_items(&values);
+        let history = history.map_err(|e| match e.kind() {
+            std::io::ErrorKind::QuotaExceeded => Exception::err(ExceptionKind::LimitError, format!("{}, narrow the range", e).as_str()),
+            _ => Exception::err(ExceptionKind::DiskError, format!("segments of {} cannot be read: {}", name, e).as_str()),
+        })?;
+        Ok(history.into_iter().map(|it| TSPoint { key: it.key, value: it.value }).chain(points).collect())
     }
 
-    pub fn insert_new_value(&mut self, value: &mut TSValue) -> Result<(), Exception> {
+    // `range` with start, end and the returned keys in `unit` instead of the series' precision
+    pub fn range_as(&self, name: &str, start: u128, end: u128, unit: Precision) -> Result<Vec<TSPoint>, Exception> {
+        let precision = self.find(name)?.item.precision;
+        let points = self.range(name, unit.convert(start, precision), unit.convert_end(end, precision))?;
+        Ok(points.into_iter().map(|it| TSPoint { key: precision.convert(it.key, unit), value: it.value }).collect())
+    }
+
+    // the newest point at or before `time`
+    pub fn query(&self, name: &str, time: u128) -> Result<TSPoint, Exception> {
+        let series = self.find(name)?;
+        let queue = series.queue();
+        match queue.query_time(time) {
+            Some((key, value)) => Ok(TSPoint { key, value }),
+            None => Err(Exception::err(ExceptionKind::QueueIsNullError, format!("no value of {} at or before {}", name, time).as_str())),
+        }
+    }
+
+    // `query` with the time and the returned key in `unit`
+    pub fn query_as(&self, name: &str, time: u128, unit: Precision) -> Result<TSPoint, Exception> {
+        let precision = self.find(name)?.item.precision;
+        let point = self.query(name, unit.convert_end(time, precision))?;
+        Ok(TSPoint { key: precision.convert(point.key, unit), value: point.value })
+    }
+
+    pub fn items(&self) -> Vec<TSItem> {
+        self.series.read().unwrap().values().map(|it| it.item.clone()).collect()
+    }
+
+    pub fn describe(&self) -> Vec<SeriesInfo> {
+        self.series.read().unwrap().values().map(|it| SeriesInfo { item: it.item.clone(), compression_ratio: it.queue().compression_ratio() }).collect()
+    }
+
+    // memory held by every series, largest first
+    pub fn memory(&self) -> Vec<MemoryUsage> {
+        let mut usage: Vec<MemoryUsage> = self.series.read().unwrap().values().map(|series| {
+            let queue = series.queue();
+            MemoryUsage {
+                name: series.item.tsName.clone(),
+                datatype: series.item.datatype.clone(),
+                points: queue.count(),
+                capacity: series.item.capacity,
+                bytes: queue.memory_usage(),
+                compression_ratio: queue.compression_ratio(),
+            }
+        }).collect();
+        usage.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+        usage
+    }
+
+    // the series' segment files stay on disk, alert rules on it are removed
+    pub fn drop_item(&self, name: &str) -> Result<(), Exception> {
+        let removed = {
+            let mut series = self.series.write().unwrap();
+            let removed = match series.remove(name) {
+                Some(removed) => removed,
+                None => return Err(Exception::err(ExceptionKind::TSNameExistsError, format!("TSName {} not exist", name).as_str())),
+            };
+            (removed, self.write_items(series))
+        };
+        let (removed, saved) = removed;
+        if let Err(e) = removed.writer.sync() {
+            info!("failed to sync {}: {:?}", name, e);
+        }
+        let mut alerts = self.alerts();
+        let bound: Vec<String> = alerts.rules().into_iter().filter(|it| it.ts_name == name).map(|it| it.name.clone()).collect();
+        if !bound.is_empty() {
+            bound.iter().for_each(|rule| { alerts.remove_rule(rule); });
+            write_all_rules(&alerts.rules()).map_err(|e| disk_error("alert rules", e))?;
+        }
+        saved
+    }
+
+    // stores the point and returns its key, a key of 0 is assigned by the server
+    pub fn insert_new_value(&self, value: &mut TSValue) -> Result<u128, Exception> {
         let mut v = mem::take(value);
-        let item = self.items.get_mut(v.name.as_str()).unwrap();
-        if !item.datatype.equal(&v.value) {
-            return Err(Exception::err(ExceptionKind::SaveTypeError, format!("except type:{:?},but input type:{:?}", item.datatype, v.value).as_str()));
+        let series = self.find(v.name.as_str())?;
+        if !series.item.datatype.equal(&v.value) {
+            return Err(Exception::err(ExceptionKind::SaveTypeError, format!("except type:{:?},but input type:{:?}", series.item.datatype, v.value).as_str()));
+        }
+        // the queue lock is held while submitting so the segment keeps key order, and while
+        // evaluating rules so they see the keys in that order too
+        let mut queue = series.queue.write().unwrap();
+        series.writer.check()?;
+        if v.key == 0 {
+            v.key = assign_key(queue.last_key(), series.item.precision);
         }
-        let io = self.ios.get_mut(v.name.as_str()).unwrap();
-        io.append(&v);
-        let queue = self.cache.get_mut(v.name.as_str()).unwrap();
-        let cache = v.value;
-        let box_value = Box::new(cache);
-        queue.insert(v.key, box_value)
+        queue.insert(v.key, v.value.clone())?;
+        if series.rules.load(Ordering::Relaxed) > 0 {
+            // rules work in milliseconds whatever the series' precision
+            self.alerts().on_value(v.name.as_str(), series.item.precision.to_millis(v.key), &v.value, now_millis());
+        }
+        let key = v.key;
+        series.writer.submit(v);
+        Ok(key)
+    }
+
+    pub fn alerts(&self) -> MutexGuard<'_, AlertManager> {
+        self.alerts.lock().unwrap()
     }
-    pub fn get_mut(&mut self, key: &str) -> Option<&mut TSQueue> {
-        self.cache.get_mut(key)
+
+    pub fn create_alert(&self, rule: AlertRule) -> Result<(), Exception> {
+        let series = self.find(rule.ts_name.as_str())?;
+        let name = rule.name.clone();
+        let mut alerts = self.alerts();
+        alerts.add_rule(rule)?;
+        if let Err(e) = write_all_rules(&alerts.rules()) {
+            alerts.remove_rule(&name);
+            return Err(disk_error("alert rules", e));
+        }
+        series.rules.fetch_add(1, Ordering::Relaxed);
+        Ok(())
     }
+
+    pub fn drop_alert(&self, name: &str) -> Result<(), Exception> {
+        let (rule, saved) = {
+            let mut alerts = self.alerts();
+            let rule = match alerts.remove_rule(name) {
+                Some(rule) => rule,
+                None => return Err(Exception::err(ExceptionKind::AlertRuleError, format!("alert rule {} not exist", name).as_str())),
+            };
+            (rule, write_all_rules(&alerts.rules()).map_err(|e| disk_error("alert rules", e)))
+        };
+        if let Some(series) = self.get(&rule.ts_name) {
+            let _ = series.rules.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |it| it.checked_sub(1));
+        }
+        saved
+    }
+
+    pub fn check_alerts(&self) {
+        self.alerts().tick(now_millis());
+    }
+
+    pub fn notifiers(&self) -> Vec<NotifierConfig> {
+        self.notifiers.read().unwrap().clone()
+    }
+
+    pub fn create_notifier(&self, notifier: NotifierConfig) -> Result<(), Exception> {
+        let mut notifiers = self.notifiers.write().unwrap();
+        if notifiers.iter().any(|it| it.name == notifier.name) {
+            return Err(Exception::err(ExceptionKind::NotifyError, format!("duplicate notifier {}", notifier.name).as_str()));
+        }
+        build(&notifier.kind)?;
+        notifiers.push(notifier);
+        if let Err(e) = write_all_notifiers(&notifiers) {
+            notifiers.pop();
+            return Err(disk_error("notifiers", e));
+        }
+        Ok(())
+    }
+
+    pub fn drop_notifier(&self, name: &str) -> Result<(), Exception> {
+        let mut notifiers = self.notifiers.write().unwrap();
+        let len = notifiers.len();
+        notifiers.retain(|it| it.name != name);
+        if notifiers.len() == len {
+            return Err(Exception::err(ExceptionKind::NotifyError, format!("notifier {} not exist", name).as_str()));
+        }
+        write_all_notifiers(&notifiers).map_err(|e| disk_error("notifiers", e))
+    }
+
+    // flushes every series writer and rewrites the catalogs before exit
+    pub fn shutdown(&self) {
+        let series = self.series.read().unwrap();
+        for (name, it) in series.iter() {
+            if let Err(e) = it.writer.close() {
+                info!("failed to sync {}: {:?}", name, e);
+            }
+        }
+        let saved = write_all_items(&series.values().map(|it| &it.item).collect::<Vec<_>>())
+            .and_then(|_| write_all_rules(&self.alerts().rules()))
+            .and_then(|_| write_all_notifiers(&self.notifiers.read().unwrap()));
+        if let Err(e) = saved {
+            info!("failed to write the catalogs: {:?}", e);
+        }
+    }
+}
+
+// reads files on a runtime worker after handing its other tasks to the remaining workers
+fn blocking<T>(read: impl FnOnce() -> T) -> T {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => tokio::task::block_in_place(read),
+        _ => read(),
+    }
+}
+
+fn disk_error(catalog: &str, e: std::io::Error) -> Exception {
+    Exception::err(ExceptionKind::DiskError, format!("{} cannot be read or written: {}", catalog, e).as_str())
 }
 
+// any other name is encoded into its directory by `io::series_dir`
+fn check_name(name: &str) -> Result<(), Exception> {
+    if name.is_empty() {
+        return Err(Exception::err(ExceptionKind::InvalidNameError, format!("invalid TSName {:?}", name).as_str()));
+    }
+    Ok(())
+}
+
+// the server clock in the series' precision, moved past the newest key when the clock
+// is behind it so a stamped point is never rejected as out of order
+fn assign_key(last: Option<u128>, precision: Precision) -> u128 {
+    let now = precision.now();
+    match last {
+        Some(last) if last >= now => last + 1,
+        _ => now,
+    }
+}
@@ -1,5 +1,8 @@
 use std::cmp::PartialEq;
+use std::collections::BTreeMap;
 use std::fmt::Formatter;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde::de::{Error};
 use crate::entity::TSCacheValue::Float;
@@ -29,6 +32,18 @@ impl DataType {
             DataType::ByteArray => 0,
         }
     }
+    // the type a value is stored as
+    pub fn of(value: &TSCacheValue) -> DataType {
+        match value {
+            Float(_) => DataType::Float,
+            TSCacheValue::Long(_) => DataType::Long,
+            TSCacheValue::Double(_) => DataType::Double,
+            TSCacheValue::Number(_) => DataType::Number,
+            TSCacheValue::String(_) => DataType::String,
+            TSCacheValue::ByteArray(_) => DataType::ByteArray,
+        }
+    }
+
     pub fn equal(&self, value: &TSCacheValue) -> bool {
         match value {
             Float(_) => if let DataType::Float = self { true }else { false },
@@ -52,6 +67,21 @@ pub enum SaveTimePeriod {
 }
 
 
+impl FromStr for SaveTimePeriod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('_', "").as_str() {
+            "nerve" | "never" => Ok(SaveTimePeriod::Nerve),
+            "minute" => Ok(SaveTimePeriod::Minute),
+            "tenminutes" => Ok(SaveTimePeriod::TenMinutes),
+            "hour" => Ok(SaveTimePeriod::Hour),
+            "day" => Ok(SaveTimePeriod::Day),
+            _ => Err(format!("unknown save period `{}`", s)),
+        }
+    }
+}
+
 impl SaveTimePeriod {
     pub fn as_period(&self) -> u128 {
         match self {
@@ -63,21 +93,147 @@ impl SaveTimePeriod {
         }
     }
 }
+// unit of a series' keys and of incoming timestamps
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Precision {
+    #[serde(rename = "s")]
+    Seconds,
+    // keys had no unit before series had a precision, they were milliseconds
+    #[default]
+    #[serde(rename = "ms")]
+    Millis,
+    #[serde(rename = "us")]
+    Micros,
+    #[serde(rename = "ns")]
+    Nanos,
+}
+
+impl std::fmt::Display for Precision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Precision::Seconds => "s",
+            Precision::Millis => "ms",
+            Precision::Micros => "us",
+            Precision::Nanos => "ns",
+        })
+    }
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    // accepts both the InfluxDB 1.x (n, u) and 2.x (ns, us) spellings
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "s" => Ok(Precision::Seconds),
+            "ms" => Ok(Precision::Millis),
+            "us" | "u" => Ok(Precision::Micros),
+            "ns" | "n" => Ok(Precision::Nanos),
+            _ => Err(format!("unknown precision `{}`", s)),
+        }
+    }
+}
+
+impl Precision {
+    fn per_second(&self) -> u128 {
+        match self {
+            Precision::Seconds => 1,
+            Precision::Millis => 1_000,
+            Precision::Micros => 1_000_000,
+            Precision::Nanos => 1_000_000_000,
+        }
+    }
+
+    // `time` in this unit as a time in `to`, truncated when `to` is coarser
+    pub fn convert(&self, time: u128, to: Precision) -> u128 {
+        let (from, to) = (self.per_second(), to.per_second());
+        if to >= from { time.saturating_mul(to / from) } else { time / (from / to) }
+    }
+
+    // the last time in `to` that still falls within `time`, for inclusive upper bounds
+    pub fn convert_end(&self, time: u128, to: Precision) -> u128 {
+        let (from, to) = (self.per_second(), to.per_second());
+        if to > from { time.saturating_add(1).saturating_mul(to / from) - 1 } else { time / (from / to) }
+    }
+
+    pub fn to_millis(&self, time: u128) -> u128 {
+        self.convert(time, Precision::Millis)
+    }
+
+    // the server clock in this unit
+    pub fn now(&self) -> u128 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|it| it.as_nanos()).unwrap_or_default();
+        Precision::Nanos.convert(now, *self)
+    }
+}
+
+// applied to a segment file once it is sealed, the blocks inside are compressed either way
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Compression::None => "none",
+            Compression::Lz4 => "lz4",
+            Compression::Zstd => "zstd",
+        })
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!("unknown compression `{}`", s)),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct TSItem {
     pub tsName: String,
+    #[serde(default = "crate::config::default_capacity")]
     pub capacity: usize,
     pub datatype: DataType,
+    #[serde(default = "crate::config::default_save_time")]
     pub saveTime: SaveTimePeriod,
+    // tags of ingested series, e.g. host=a from line protocol
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    // unit of the keys
+    #[serde(default)]
+    pub precision: Precision,
+    // of sealed segments
+    #[serde(default)]
+    pub compression: Compression,
 }
 
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct TSValue {
     pub name: String,
+    // 0 or missing: the server assigns the key
+    #[serde(default)]
     pub key: u128,
     pub value: TSCacheValue,
 }
 
+// one stored point as returned by Range and Query
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct TSPoint {
+    pub key: u128,
+    pub value: TSCacheValue,
+}
 
 #[derive(Debug, Clone,PartialEq)]
 pub enum TSCacheValue {
@@ -90,6 +246,18 @@ pub enum TSCacheValue {
 }
 
 
+impl TSCacheValue {
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            TSCacheValue::Float(it) => Some(*it as f64),
+            TSCacheValue::Long(it) => Some(*it as f64),
+            TSCacheValue::Double(it) => Some(*it),
+            TSCacheValue::Number(it) => Some(*it),
+            TSCacheValue::String(_) | TSCacheValue::ByteArray(_) => None,
+        }
+    }
+}
+
 impl Default for TSCacheValue {
     fn default() -> Self {
         Float(0.0)
@@ -0,0 +1,186 @@
+use bytes::{Buf, BufMut, BytesMut};
+use rmp_serde::to_vec_named;
+use crate::method::Exception;
+
+// version 1 request: [u16 action][u32 length][payload]
+// version 1 response: [u8 status][u8 payload type][u32 length][payload]
+// version 2 request: [u8 MAGIC][u8 version][u8 flags][u32 request id][u16 action][u32 length][payload]
+// version 2 response: [u32 request id][u8 status][u8 payload type][u32 length][payload]
+// action codes stay below 0x8000 so the first byte tells the versions apart
+pub const MAGIC: u8 = 0xC7;
+pub const VERSION_1: u8 = 1;
+pub const VERSION_2: u8 = 2;
+pub const V1_HEADER: usize = 6;
+pub const V2_HEADER: usize = 13;
+
+// the server sends no response for this request
+pub const FLAG_NO_REPLY: u8 = 0x01;
+const KNOWN_FLAGS: u8 = FLAG_NO_REPLY;
+
+pub const V1_RESPONSE_HEADER: usize = 6;
+pub const V2_RESPONSE_HEADER: usize = 10;
+
+// response status, every status but OK carries an Exception payload
+pub const STATUS_OK: u8 = 0;
+// the request was rejected, sending it again will fail the same way
+pub const STATUS_CLIENT_ERROR: u8 = 1;
+// the server is overloaded, the same request may succeed later
+pub const STATUS_BUSY: u8 = 2;
+pub const STATUS_SERVER_ERROR: u8 = 3;
+// the frame could not be read, the server closes the connection
+pub const STATUS_PROTOCOL_ERROR: u8 = 4;
+
+// what the payload of a response holds
+pub const PAYLOAD_EMPTY: u8 = 0;
+pub const PAYLOAD_VALUE: u8 = 1;
+pub const PAYLOAD_MSGPACK: u8 = 2;
+pub const PAYLOAD_EXCEPTION: u8 = 3;
+pub const PAYLOAD_EVENT: u8 = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub version: u8,
+    pub id: u32,
+    pub flags: u8,
+    pub action: u16,
+    pub payload: BytesMut,
+}
+
+impl Request {
+    pub fn no_reply(&self) -> bool {
+        self.flags & FLAG_NO_REPLY != 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameError {
+    UnsupportedVersion(u8),
+    UnknownFlags(u8),
+    TooLarge(usize),
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::UnsupportedVersion(v) => write!(f, "unsupported protocol version {}", v),
+            FrameError::UnknownFlags(flags) => write!(f, "unknown flags {:#04x}", flags),
+            FrameError::TooLarge(length) => write!(f, "payload of {} bytes exceeds the frame size limit", length),
+        }
+    }
+}
+
+// takes one complete request off the front of `buff`, Ok(None) means more bytes are needed.
+// The header is checked as soon as it is buffered so a bad frame fails before its payload arrives.
+pub fn decode(buff: &mut BytesMut, max_payload: usize) -> Result<Option<Request>, FrameError> {
+    if buff.is_empty() {
+        return Ok(None);
+    }
+    if buff[0] != MAGIC {
+        if buff.len() < V1_HEADER {
+            return Ok(None);
+        }
+        let length = u32::from_be_bytes([buff[2], buff[3], buff[4], buff[5]]) as usize;
+        if length > max_payload {
+            return Err(FrameError::TooLarge(length));
+        }
+        if buff.len() < V1_HEADER + length {
+            return Ok(None);
+        }
+        let action = buff.get_u16();
+        buff.advance(4);
+        let payload = buff.split_to(length);
+        return Ok(Some(Request { version: VERSION_1, id: 0, flags: 0, action, payload }));
+    }
+    if buff.len() >= 2 && buff[1] != VERSION_2 {
+        return Err(FrameError::UnsupportedVersion(buff[1]));
+    }
+    if buff.len() >= 3 && buff[2] & !KNOWN_FLAGS != 0 {
+        return Err(FrameError::UnknownFlags(buff[2]));
+    }
+    if buff.len() < V2_HEADER {
+        return Ok(None);
+    }
+    let length = u32::from_be_bytes([buff[9], buff[10], buff[11], buff[12]]) as usize;
+    if length > max_payload {
+        return Err(FrameError::TooLarge(length));
+    }
+    if buff.len() < V2_HEADER + length {
+        return Ok(None);
+    }
+    buff.advance(2);
+    let flags = buff.get_u8();
+    let id = buff.get_u32();
+    let action = buff.get_u16();
+    buff.advance(4);
+    let payload = buff.split_to(length);
+    Ok(Some(Request { version: VERSION_2, id, flags, action, payload }))
+}
+
+pub fn encode_request(id: u32, flags: u8, action: u16, payload: &[u8]) -> Vec<u8> {
+    let mut buff = Vec::with_capacity(V2_HEADER + payload.len());
+    buff.put_u8(MAGIC);
+    buff.put_u8(VERSION_2);
+    buff.put_u8(flags);
+    buff.put_u32(id);
+    buff.put_u16(action);
+    buff.put_u32(payload.len() as u32);
+    buff.put_slice(payload);
+    buff
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u8,
+    pub payload_type: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Response {
+    pub fn ok(payload_type: u8, payload: Vec<u8>) -> Response {
+        if payload.is_empty() {
+            return Response { status: STATUS_OK, payload_type: PAYLOAD_EMPTY, payload };
+        }
+        Response { status: STATUS_OK, payload_type, payload }
+    }
+
+    pub fn error(e: &Exception) -> Response {
+        let status = match e.code {
+            -1 => STATUS_PROTOCOL_ERROR,
+            4009 => STATUS_BUSY,
+            4000..=4999 => STATUS_CLIENT_ERROR,
+            _ => STATUS_SERVER_ERROR,
+        };
+        Response { status, payload_type: PAYLOAD_EXCEPTION, payload: to_vec_named(e).unwrap() }
+    }
+
+    pub fn encode(&self, version: u8, id: u32) -> Vec<u8> {
+        let mut buff = Vec::with_capacity(V2_RESPONSE_HEADER + self.payload.len());
+        if version != VERSION_1 {
+            buff.put_u32(id);
+        }
+        buff.put_u8(self.status);
+        buff.put_u8(self.payload_type);
+        buff.put_u32(self.payload.len() as u32);
+        buff.put_slice(&self.payload);
+        buff
+    }
+}
+
+// client side: takes one complete response off `buff` with the request id (0 for version 1)
+pub fn decode_response(buff: &mut BytesMut, version: u8) -> Option<(u32, Response)> {
+    let header = if version == VERSION_1 { V1_RESPONSE_HEADER } else { V2_RESPONSE_HEADER };
+    if buff.len() < header {
+        return None;
+    }
+    let at = header - 4;
+    let length = u32::from_be_bytes([buff[at], buff[at + 1], buff[at + 2], buff[at + 3]]) as usize;
+    if buff.len() < header + length {
+        return None;
+    }
+    let id = if version == VERSION_1 { 0 } else { buff.get_u32() };
+    let status = buff.get_u8();
+    let payload_type = buff.get_u8();
+    buff.advance(4);
+    let payload = buff.split_to(length).to_vec();
+    Some((id, Response { status, payload_type, payload }))
+}
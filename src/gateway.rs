@@ -0,0 +1,296 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bytes::BytesMut;
+use rmp_serde::{from_slice, to_vec_named};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::config;
+use crate::db::{CacheDb, SeriesInfo};
+use crate::entity::{DataType, Precision, TSCacheValue, TSItem, TSPoint, TSValue};
+use crate::frame::{Request, VERSION_2};
+use crate::handle::process;
+use crate::influx;
+use crate::ingest;
+use crate::prometheus;
+use crate::http::{HttpRequest, HttpResponse};
+use crate::method::{Exception, ExceptionKind, MethodKind, RangeParam};
+
+type Db = Arc<CacheDb>;
+
+// REST endpoints, each one runs the same Method as the binary protocol
+pub fn route(request: &HttpRequest, db: &Db) -> HttpResponse {
+    let segments = request.segments();
+    let segments: Vec<&str> = segments.iter().map(|it| it.as_str()).collect();
+    let result = match (request.method.as_str(), segments.as_slice()) {
+        ("POST", ["series"]) => create(request, db),
+        ("GET", ["series"]) => list(request, db),
+        ("DELETE", ["series", name]) => drop(name, db),
+        ("POST", ["series", name, "points"]) => set(name, request, db),
+        ("GET", ["series", name, "last"]) => last(name, db),
+        ("GET", ["series", name, "range"]) => range(name, request, db),
+        ("POST", ["write"]) | ("POST", ["api", "v2", "write"]) => return write(request, db),
+        ("POST", ["api", "v1", "write"]) => return remote_write(request, db),
+        ("POST", ["api", "v1", "read"]) => return remote_read(request, db),
+        ("GET", ["metrics"]) => return metrics(request, db),
+        ("GET", ["memory"]) => return memory(db),
+        (_, ["series", ..]) => return HttpResponse::text(405, "method not allowed"),
+        _ => return HttpResponse::text(404, "not found"),
+    };
+    match result {
+        Ok(response) => response,
+        Err(e) => error(&e, request.method == "POST" && segments == ["series"]),
+    }
+}
+
+// 4002 means the name exists when creating and that it does not exist everywhere else
+fn error(e: &Exception, creating: bool) -> HttpResponse {
+    let status = match e.kind() {
+        Some(ExceptionKind::ParamParseError) => 400,
+        Some(ExceptionKind::TSNameExistsError) if creating => 409,
+        Some(ExceptionKind::TSNameExistsError) => 404,
+        Some(ExceptionKind::QueueIsNullError) => 404,
+        Some(ExceptionKind::TimeSerieError) => 409,
+        Some(ExceptionKind::SaveTypeError) => 422,
+        Some(ExceptionKind::AlertRuleError) | Some(ExceptionKind::NotifyError) => 400,
+        Some(ExceptionKind::LimitError) => 422,
+        Some(ExceptionKind::BackpressureError) => 503,
+        Some(ExceptionKind::UnknownMethodError) => 404,
+        Some(ExceptionKind::InvalidNameError) => 400,
+        Some(ExceptionKind::DiskError) => 500,
+        None => 500,
+    };
+    let response = HttpResponse::json(status, e);
+    if status == 503 {
+        return response.header("Retry-After", "1");
+    }
+    response
+}
+
+fn call(db: &Db, kind: MethodKind, payload: Vec<u8>) -> Result<Vec<u8>, Exception> {
+    let request = Request { version: VERSION_2, id: 0, flags: 0, action: kind.as_code(), payload: BytesMut::from(&payload[..]) };
+    Ok(process(&request, db)?.payload)
+}
+
+fn param<T: Serialize + ?Sized>(value: &T) -> Vec<u8> {
+    to_vec_named(value).unwrap()
+}
+
+fn result<T: DeserializeOwned>(payload: &[u8]) -> T {
+    from_slice(payload).unwrap()
+}
+
+fn parse_error(msg: String) -> Exception {
+    Exception::err(ExceptionKind::ParamParseError, msg.as_str())
+}
+
+fn body<T: DeserializeOwned>(request: &HttpRequest) -> Result<T, Exception> {
+    serde_json::from_slice(&request.body).map_err(|e| parse_error(format!("parse json error:{}", e)))
+}
+
+// ByteArray values travel as base64 strings
+pub fn value_json(value: &TSCacheValue) -> Value {
+    match value {
+        TSCacheValue::Float(it) => json!(it),
+        TSCacheValue::Long(it) => json!(it),
+        TSCacheValue::Double(it) | TSCacheValue::Number(it) => json!(it),
+        TSCacheValue::String(it) => json!(it),
+        TSCacheValue::ByteArray(it) => json!(BASE64.encode(it)),
+    }
+}
+
+// JSON numbers carry no width, the series' type decides the variant
+pub fn value_from_json(value: &Value, datatype: &DataType) -> Result<TSCacheValue, Exception> {
+    let converted = match datatype {
+        DataType::Float => value.as_f64().map(|it| TSCacheValue::Float(it as f32)),
+        DataType::Long => value.as_i64().map(TSCacheValue::Long),
+        DataType::Double => value.as_f64().map(TSCacheValue::Double),
+        DataType::Number => value.as_f64().map(TSCacheValue::Number),
+        DataType::String => value.as_str().map(|it| TSCacheValue::String(it.to_string())),
+        DataType::ByteArray => value.as_str().and_then(|it| BASE64.decode(it).ok()).map(TSCacheValue::ByteArray),
+    };
+    converted.ok_or_else(|| Exception::err(ExceptionKind::SaveTypeError, format!("except type:{:?},but input value:{}", datatype, value).as_str()))
+}
+
+// integers make a Long series, other numbers a Double one and strings a String one
+fn json_datatype(value: &Value) -> Result<DataType, Exception> {
+    match value {
+        Value::Number(number) if number.is_i64() => Ok(DataType::Long),
+        Value::Number(_) => Ok(DataType::Double),
+        Value::String(_) => Ok(DataType::String),
+        _ => Err(Exception::err(ExceptionKind::SaveTypeError, format!("no series type for value:{}", value).as_str())),
+    }
+}
+
+// a number while it fits in u64, JSON parsers lose wider integers, a decimal string beyond
+fn key_json(key: u128) -> Value {
+    u64::try_from(key).map(Value::from).unwrap_or_else(|_| Value::String(key.to_string()))
+}
+
+fn point_json(point: &TSPoint) -> Value {
+    json!({ "key": key_json(point.key), "value": value_json(&point.value) })
+}
+
+fn create(request: &HttpRequest, db: &Db) -> Result<HttpResponse, Exception> {
+    let item: TSItem = body(request)?;
+    let location = format!("/series/{}", item.tsName);
+    call(db, MethodKind::Create, param(&item))?;
+    Ok(HttpResponse::new(201).header("Location", &location))
+}
+
+fn list(request: &HttpRequest, db: &Db) -> Result<HttpResponse, Exception> {
+    let payload = match request.query.get("prefix") {
+        Some(prefix) => param(prefix),
+        None => vec![],
+    };
+    let items: Vec<SeriesInfo> = result(&call(db, MethodKind::List, payload)?);
+    Ok(HttpResponse::json(200, &items))
+}
+
+fn drop(name: &str, db: &Db) -> Result<HttpResponse, Exception> {
+    call(db, MethodKind::Drop, param(name))?;
+    Ok(HttpResponse::new(204))
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Points {
+    One(JsonPoint),
+    Many(Vec<JsonPoint>),
+}
+
+#[derive(Deserialize)]
+struct JsonPoint {
+    // 0 or left out, the server assigns the key
+    #[serde(default)]
+    key: u64,
+    value: Value,
+}
+
+// one {"key", "value"} object is a Set answered with {"key"}, an array of them a SetBatch answered with
+// {"keys"}, the stored keys
+fn set(name: &str, request: &HttpRequest, db: &Db) -> Result<HttpResponse, Exception> {
+    let points: Points = body(request)?;
+    let series = match db.get(name) {
+        Some(series) => series,
+        // created under the auto_create policy, typed after the first value
+        None => {
+            let first = match &points {
+                Points::One(point) => Some(point),
+                Points::Many(points) => points.first(),
+            };
+            let datatype = match first {
+                Some(point) => json_datatype(&point.value)?,
+                None => return Err(Exception::err(ExceptionKind::TSNameExistsError, format!("TSName {} not exist", name).as_str())),
+            };
+            ingest::series(db, name, datatype, BTreeMap::new, config::get().auto_create.allows(name))?
+        }
+    };
+    let datatype = series.item.datatype.clone();
+    let value = |point: &JsonPoint| -> Result<TSValue, Exception> {
+        Ok(TSValue { name: name.to_string(), key: point.key as u128, value: value_from_json(&point.value, &datatype)? })
+    };
+    let stored = match points {
+        Points::One(point) => {
+            let key: u128 = result(&call(db, MethodKind::Set, param(&value(&point)?))?);
+            json!({ "key": key_json(key) })
+        }
+        Points::Many(points) => {
+            let values = points.iter().map(value).collect::<Result<Vec<_>, _>>()?;
+            let keys: Vec<u128> = result(&call(db, MethodKind::SetBatch, param(&values))?);
+            json!({ "keys": keys.into_iter().map(key_json).collect::<Vec<_>>() })
+        }
+    };
+    Ok(HttpResponse::json(200, &stored))
+}
+
+fn last(name: &str, db: &Db) -> Result<HttpResponse, Exception> {
+    let value: TSCacheValue = result(&call(db, MethodKind::Get, param(name))?);
+    Ok(HttpResponse::json(200, &json!({ "value": value_json(&value) })))
+}
+
+// start and end default to the whole queue, `precision` is their unit and the keys' unit in the response
+fn range(name: &str, request: &HttpRequest, db: &Db) -> Result<HttpResponse, Exception> {
+    let bound = |key: &str, default: u128| -> Result<u128, Exception> {
+        match request.query.get(key) {
+            Some(text) => text.parse().map_err(|_| parse_error(format!("bad {} `{}`", key, text))),
+            None => Ok(default),
+        }
+    };
+    let precision = request.query.get("precision").map(|text| text.parse::<Precision>().map_err(parse_error)).transpose()?;
+    let range = RangeParam { name: name.to_string(), start: bound("start", 0)?, end: bound("end", u128::MAX)?, precision };
+    let points: Vec<TSPoint> = result(&call(db, MethodKind::Range, param(&range))?);
+    Ok(HttpResponse::json(200, &points.iter().map(point_json).collect::<Vec<_>>()))
+}
+
+// line protocol body, `precision` as in InfluxDB 1.x and 2.x; a partial write answers 400 with the first error
+fn write(request: &HttpRequest, db: &Db) -> HttpResponse {
+    if request.header("Content-Encoding").is_some_and(|it| !it.eq_ignore_ascii_case("identity")) {
+        return HttpResponse::text(415, "compressed bodies are not supported, send identity encoding");
+    }
+    let precision = match request.query.get("precision") {
+        Some(text) => match text.parse::<Precision>() {
+            Ok(precision) => precision,
+            Err(e) => return HttpResponse::json(400, &json!({ "code": "invalid", "message": e })),
+        },
+        None => config::get().influx.precision,
+    };
+    let result = influx::write(db, &String::from_utf8_lossy(&request.body), precision);
+    match result.errors.first() {
+        None => HttpResponse::new(204),
+        Some(first) if result.busy => HttpResponse::json(503, &json!({ "code": "unavailable", "message": first })).header("Retry-After", "1"),
+        Some(first) => HttpResponse::json(400, &json!({
+            "code": "invalid",
+            "message": first,
+            "written": result.points,
+            "failed": result.errors.len(),
+        })),
+    }
+}
+
+// Prometheus drops a batch answered with 4xx and retries it after 5xx
+fn remote_write(request: &HttpRequest, db: &Db) -> HttpResponse {
+    let result = match prometheus::write(db, &request.body) {
+        Ok(result) => result,
+        Err(e) => return HttpResponse::text(400, &e),
+    };
+    match result.errors.first() {
+        None => HttpResponse::new(204),
+        Some(first) if result.busy => HttpResponse::text(503, first).header("Retry-After", "1"),
+        Some(first) => HttpResponse::text(400, &format!("{} samples written, {} errors, first: {}", result.points, result.errors.len(), first)),
+    }
+}
+
+fn remote_read(request: &HttpRequest, db: &Db) -> HttpResponse {
+    match prometheus::read(db, &request.body) {
+        Ok(body) => HttpResponse { content_type: "application/x-protobuf", body, ..HttpResponse::new(200) }.header("Content-Encoding", "snappy"),
+        Err(e) => HttpResponse::text(400, &e),
+    }
+}
+
+// `include` and `exclude` query parameters add one pattern each to the configured ones
+fn metrics(request: &HttpRequest, db: &Db) -> HttpResponse {
+    let conf = &config::get().prometheus;
+    let mut include = conf.metrics_include.clone();
+    let mut exclude = conf.metrics_exclude.clone();
+    include.extend(request.query.get("include").cloned());
+    exclude.extend(request.query.get("exclude").cloned());
+    match prometheus::Selector::new(&include, &exclude) {
+        Ok(selector) => HttpResponse {
+            content_type: "text/plain; version=0.0.4; charset=utf-8",
+            body: prometheus::exposition(db, &selector).into_bytes(),
+            ..HttpResponse::new(200)
+        },
+        Err(e) => HttpResponse::text(400, &e),
+    }
+}
+
+// bytes held by each series and in total, largest first
+fn memory(db: &Db) -> HttpResponse {
+    let series = db.memory();
+    let bytes: usize = series.iter().map(|it| it.bytes).sum();
+    let points: usize = series.iter().map(|it| it.points).sum();
+    HttpResponse::json(200, &json!({ "bytes": bytes, "points": points, "series": series }))
+}
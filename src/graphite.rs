@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::{watch, Semaphore};
+use crate::alert::now_millis;
+use crate::config;
+use crate::db::CacheDb;
+use crate::ingest::{self, WriteResult};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub path: String,
+    pub tags: BTreeMap<String, String>,
+    pub value: f64,
+    // milliseconds, None when the sender left it to the server
+    pub timestamp: Option<u128>,
+}
+
+impl Line {
+    // the path followed by `;tag=value` in tag order, the same as a normalized Graphite tagged path
+    pub fn series_name(&self) -> String {
+        let mut name = self.path.clone();
+        self.tags.iter().for_each(|(key, value)| name.push_str(&format!(";{}={}", key, value)));
+        name
+    }
+}
+
+// `path[;tag=value...] value [timestamp]`, the timestamp is in seconds and -1 means now
+pub fn parse_line(line: &str) -> Result<Line, String> {
+    let mut parts = line.split_whitespace();
+    let (path, value) = match (parts.next(), parts.next()) {
+        (Some(path), Some(value)) => (path, value),
+        _ => return Err("expected `path value timestamp`".to_string()),
+    };
+    let timestamp = parts.next();
+    if parts.next().is_some() {
+        return Err("expected `path value timestamp`".to_string());
+    }
+    let mut segments = path.split(';');
+    let path = segments.next().unwrap_or_default().to_string();
+    if path.is_empty() {
+        return Err("missing path".to_string());
+    }
+    let tags = segments.map(|tag| match tag.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !value.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("invalid tag `{}`", tag)),
+    }).collect::<Result<BTreeMap<_, _>, String>>()?;
+    let value = match value.parse::<f64>() {
+        Ok(value) if value.is_finite() => value,
+        _ => return Err(format!("invalid value `{}`", value)),
+    };
+    let timestamp = match timestamp {
+        None | Some("-1") => None,
+        Some(text) => match text.parse::<f64>() {
+            Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Some((seconds * 1000.0).round() as u128),
+            _ => return Err(format!("invalid timestamp `{}`", text)),
+        },
+    };
+    Ok(Line { path, tags, value, timestamp })
+}
+
+// writes every line of `body`, a bad line does not stop the others
+pub fn write(db: &CacheDb, body: &str) -> WriteResult {
+    let auto_create = config::get().graphite.auto_create;
+    let mut result = WriteResult::default();
+    for (n, line) in body.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let parsed = match parse_line(line) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                result.errors.push(format!("line {}: {}", n + 1, e));
+                continue;
+            }
+        };
+        let key = parsed.timestamp.unwrap_or_else(now_millis);
+        match ingest::set_number(db, &parsed.series_name(), || parsed.tags.clone(), key, parsed.value, auto_create) {
+            Ok(()) => result.points += 1,
+            Err(e) => result.fail(&format!("line {}", n + 1), &e),
+        }
+    }
+    result
+}
+
+// plaintext protocol, nothing is sent back, errors are only logged
+pub async fn serve_tcp(listener: TcpListener, db: Arc<CacheDb>, connections: Arc<Semaphore>, shutdown: watch::Receiver<bool>) {
+    ingest::serve_lines(listener, db, connections, shutdown, "graphite", write).await
+}
@@ -1,80 +1,167 @@
 
 use tokio::net::TcpStream;
 
-use std::collections::HashMap;
-use std::io::{Error, ErrorKind};
+use std::future::pending;
 use std::sync::{Arc};
+use std::time::Duration;
 use bytes::BytesMut;
-use rmp_serde::to_vec_named;
+use log::info;
+use rmp_serde::{from_slice, to_vec_named};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::Mutex;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::{mpsc, watch};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{sleep_until, Instant};
 use crate::db::CacheDb;
-use crate::method::{Exception, TSQueue, choose_method};
-use crate::entity::{TSItem,TSValue,TSCacheValue,SaveTimePeriod,DataType};
+use crate::frame::{decode, Request, Response, PAYLOAD_EVENT, VERSION_1};
+use crate::config;
+use crate::method::{Exception, ExceptionKind, choose_method, MethodKind};
 
-type Db = Arc<Mutex<CacheDb>>;
-// type Db = HashMap<u128, Bytes>;
+type Db = Arc<CacheDb>;
 
-struct TsConnection {
-    socket: TcpStream,
+// responses waiting for the socket, the reader stops when this many are queued
+const PIPELINE_DEPTH: usize = 128;
 
-}
-pub async fn process(socket: &mut TcpStream, db: &Db) -> Result<(), Exception> {
-    let mut buff = BytesMut::new();
-    match parse_frame(socket, &mut buff).await {
-        Ok(_) => {}
-        Err(_e) => {
-            return Err(Exception::new(-1, format!("Error while parsing frame from socket {}", _e).as_str()));
+// Requests of a connection are executed in arrival order and their responses are queued
+// for a separate writer, so a client may send many requests before reading any reply.
+pub async fn serve(socket: TcpStream, db: Db, mut shutdown: watch::Receiver<bool>) {
+    let (mut reader, writer) = socket.into_split();
+    let (sender, receiver) = mpsc::channel(PIPELINE_DEPTH);
+    let writing = tokio::spawn(write_responses(writer, receiver));
+    let mut buff = BytesMut::with_capacity(4096);
+    let mut version = VERSION_1;
+    loop {
+        let request = match read_request(&mut reader, &mut buff, &mut shutdown).await {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(e) => {
+                info!("{:?}", e);
+                let _ = sender.send(Response::error(&e).encode(version, 0)).await;
+                break;
+            }
+        };
+        version = request.version;
+        if request.action == MethodKind::SubscribeAlerts.as_code() {
+            tokio::spawn(subscribe_alerts(request, db.clone(), sender.clone(), shutdown.clone()));
+            continue;
+        }
+        let response = process(&request, &db);
+        if let Err(ref e) = response {
+            info!("{:?}", e);
         }
+        if request.no_reply() {
+            continue;
+        }
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => Response::error(&e),
+        };
+        if sender.send(response.encode(request.version, request.id)).await.is_err() {
+            break;
+        }
+    }
+    drop(sender);
+    let _ = writing.await;
+}
+
+pub fn process(request: &Request, db: &Db) -> Result<Response, Exception> {
+    let m = match choose_method(request.action) {
+        Some(m) => m,
+        None => return Err(Exception::err(ExceptionKind::UnknownMethodError, format!("unknown method {}", request.action).as_str())),
     };
-    if buff.is_empty() { return Ok(()); }
-    let action = (&buff[0..2]).read_u16().await.unwrap();
-    let param = &buff[6..];
-    let m = choose_method(action);
-    let mut map = db.lock().await;
     let mut out = BytesMut::new();
-    let result = m.unwrap().do_method(&param, &mut map, &mut out);
-    if result.is_err() {
-        return Err(result.unwrap_err());
+    m.do_method(&request.payload, db, &mut out)?;
+    Ok(Response::ok(m.payload_type(), out.to_vec()))
+}
+
+async fn write_responses(mut writer: OwnedWriteHalf, mut receiver: mpsc::Receiver<Vec<u8>>) {
+    while let Some(response) = receiver.recv().await {
+        if let Err(e) = writer.write_all(&response).await {
+            info!("Error while writing to socket {:?}", e);
+            return;
+        }
     }
-    if out.len() != 0 {
-        match socket.write_all(&out).await {
-            Ok(_) => {}
-            Err(_e) => {
-                return Err(Exception::new(-1, "Error while writing to socket".to_string().as_str()));
+    let _ = writer.shutdown().await;
+}
+
+// streams alert events as responses to the subscribe request until the client goes away
+async fn subscribe_alerts(request: Request, db: Db, sender: mpsc::Sender<Vec<u8>>, mut shutdown: watch::Receiver<bool>) {
+    let filter: Option<String> = from_slice(&request.payload).unwrap_or(None);
+    let mut receiver = db.alerts().subscribe();
+    let ok = Response::ok(PAYLOAD_EVENT, vec![]);
+    if sender.send(ok.encode(request.version, request.id)).await.is_err() {
+        return;
+    }
+    loop {
+        let received = tokio::select! {
+            received = receiver.recv() => received,
+            _ = shutdown.wait_for(|stop| *stop) => return,
+            _ = sender.closed() => return,
+        };
+        let event = match received {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                info!("alert subscriber lagged {} events", n);
+                continue;
             }
+            Err(RecvError::Closed) => return,
+        };
+        if filter.as_ref().is_some_and(|name| *name != event.ts_name) {
+            continue;
+        }
+        let response = Response::ok(PAYLOAD_EVENT, to_vec_named(&event).unwrap());
+        if sender.send(response.encode(request.version, request.id)).await.is_err() {
+            return;
         }
-    }else {
-        socket.write_all(to_vec_named(&TSCacheValue::String("OK".to_string())).unwrap().as_slice()).await.unwrap();
     }
-    Ok(())
 }
 
-async fn parse_frame(socket: &mut TcpStream, buff: &mut BytesMut) -> Result<(), Error> {
-    let mut data = [0; 512];
+// Ok(None) when the client closed the connection between requests or stayed idle too long
+async fn read_request(reader: &mut OwnedReadHalf, buff: &mut BytesMut, shutdown: &mut watch::Receiver<bool>) -> Result<Option<Request>, Exception> {
+    let conf = config::get();
+    let mut deadline = if buff.is_empty() { after(conf.idle_timeout_secs) } else { after(conf.read_timeout_secs) };
     loop {
-        if buff.len() > 6 {
-            let length = (&buff[2..6]).read_u32().await.unwrap();
-            let param = &buff[6..];
-            if param.len() == length as usize {
-                break;
-            }
+        match decode(buff, conf.limits.max_frame_size) {
+            Ok(Some(request)) => return Ok(Some(request)),
+            Ok(None) => {}
+            Err(e) => return Err(Exception::new(-1, format!("Error while parsing frame from socket {}", e).as_str())),
         }
-        let n = match socket.read(&mut data).await {
-            Ok(0) => {
-                if buff.len() == 0 {
-                    return Ok(());
-                } else {
-                    return Err(Error::from(ErrorKind::ConnectionReset));
+        // only waiting for a request is interrupted, a request already read is always answered
+        let started = !buff.is_empty();
+        let read = tokio::select! {
+            read = reader.read_buf(buff) => read,
+            _ = expire(deadline) => {
+                if !started {
+                    info!("closing idle connection");
+                    return Ok(None);
                 }
+                return Err(Exception::new(-1, "timed out reading request frame"));
             }
-            Ok(n) => n,
-            Err(e) => {
-                return Err(Error::new(ErrorKind::ConnectionReset, format!("connect reset error: {}", e)));
+            _ = shutdown.wait_for(|stop| *stop) => {
+                return Err(Exception::new(-1, "server is shutting down"));
             }
         };
-        buff.extend_from_slice(&data[..n]);
+        match read {
+            Ok(0) if buff.is_empty() => return Ok(None),
+            Ok(0) => return Err(Exception::new(-1, "connection closed in the middle of a frame")),
+            Ok(_) => {}
+            Err(e) => return Err(Exception::new(-1, format!("connect reset error: {}", e).as_str())),
+        }
+        // the whole frame has to arrive within the read timeout of its first byte
+        if !started {
+            deadline = after(conf.read_timeout_secs);
+        }
     }
-    Ok(())
 }
 
+// 0 disables the timeout
+pub(crate) fn after(secs: u64) -> Option<Instant> {
+    if secs == 0 { None } else { Some(Instant::now() + Duration::from_secs(secs)) }
+}
+
+pub(crate) async fn expire(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => pending().await,
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use bytes::{Buf, BytesMut};
+use log::info;
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinSet;
+use crate::config;
+use crate::db::CacheDb;
+use crate::gateway;
+use crate::handle::{after, expire};
+
+type Db = Arc<CacheDb>;
+
+const MAX_HEADERS: usize = 64;
+const MAX_HEAD: usize = 16 * 1024;
+
+#[derive(Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    // percent-decoded path segments, `/series/a%2Fb/last` is ["series", "a/b", "last"]
+    pub fn segments(&self) -> Vec<String> {
+        self.path.split('/').filter(|it| !it.is_empty()).map(decode).collect()
+    }
+}
+
+fn decode(text: &str) -> String {
+    percent_decode_str(&text.replace('+', " ")).decode_utf8_lossy().to_string()
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query.split('&').filter(|it| !it.is_empty()).map(|pair| match pair.split_once('=') {
+        Some((key, value)) => (decode(key), decode(value)),
+        None => (decode(pair), String::new()),
+    }).collect()
+}
+
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn new(status: u16) -> HttpResponse {
+        HttpResponse { status, content_type: "text/plain; charset=utf-8", headers: vec![], body: vec![] }
+    }
+
+    pub fn text(status: u16, text: &str) -> HttpResponse {
+        HttpResponse { body: text.as_bytes().to_vec(), ..HttpResponse::new(status) }
+    }
+
+    pub fn json<T: Serialize + ?Sized>(status: u16, value: &T) -> HttpResponse {
+        HttpResponse { content_type: "application/json", body: serde_json::to_vec(value).unwrap(), ..HttpResponse::new(status) }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> HttpResponse {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    fn encode(&self, keep_alive: bool) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        if !self.body.is_empty() {
+            head.push_str(&format!("Content-Type: {}\r\n", self.content_type));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !keep_alive {
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+        let mut buff = head.into_bytes();
+        buff.extend_from_slice(&self.body);
+        buff
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Entity",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+// the HTTP listener shares the connection limit with the binary protocol
+pub async fn serve(listener: TcpListener, db: Db, connections: Arc<Semaphore>, mut shutdown: watch::Receiver<bool>) {
+    let mut tasks = JoinSet::new();
+    loop {
+        let permit = tokio::select! {
+            permit = connections.clone().acquire_owned() => permit.unwrap(),
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        };
+        let socket = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((socket, _)) => socket,
+                Err(e) => {
+                    info!("http accept error: {:?}", e);
+                    continue;
+                }
+            },
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        };
+        while tasks.try_join_next().is_some() {}
+        let db = db.clone();
+        let shutdown = shutdown.clone();
+        tasks.spawn(async move {
+            let _permit = permit;
+            serve_connection(socket, db, shutdown).await;
+        });
+    }
+    // the connections stop on the same signal, the caller closes the database after they are done
+    while tasks.join_next().await.is_some() {}
+}
+
+pub async fn serve_connection(mut socket: TcpStream, db: Db, mut shutdown: watch::Receiver<bool>) {
+    let mut buff = BytesMut::with_capacity(4096);
+    loop {
+        let request = match read_request(&mut socket, &mut buff, &mut shutdown).await {
+            Ok(Some(request)) => request,
+            Ok(None) => return,
+            Err(response) => {
+                let _ = socket.write_all(&response.encode(false)).await;
+                return;
+            }
+        };
+        let keep_alive = !request.header("Connection").is_some_and(|it| it.eq_ignore_ascii_case("close"));
+        let response = gateway::route(&request, &db);
+        if socket.write_all(&response.encode(keep_alive)).await.is_err() || !keep_alive {
+            return;
+        }
+    }
+}
+
+// Ok(None) when the client closed or idled out between requests, Err is the response to send before closing
+async fn read_request(socket: &mut TcpStream, buff: &mut BytesMut, shutdown: &mut watch::Receiver<bool>) -> Result<Option<HttpRequest>, HttpResponse> {
+    let conf = config::get();
+    let mut deadline = if buff.is_empty() { after(conf.idle_timeout_secs) } else { after(conf.read_timeout_secs) };
+    let mut continued = false;
+    loop {
+        if let Some(request) = parse(buff, conf.limits.max_frame_size, &mut continued, socket).await? {
+            return Ok(Some(request));
+        }
+        let started = !buff.is_empty();
+        let read = tokio::select! {
+            read = socket.read_buf(buff) => read,
+            _ = expire(deadline) => {
+                if !started {
+                    return Ok(None);
+                }
+                return Err(HttpResponse::text(408, "timed out reading request"));
+            }
+            _ = shutdown.wait_for(|stop| *stop), if !started => return Ok(None),
+        };
+        match read {
+            Ok(0) | Err(_) => return Ok(None),
+            Ok(_) => {}
+        }
+        if !started {
+            deadline = after(conf.read_timeout_secs);
+        }
+    }
+}
+
+// takes one complete request off `buff`
+async fn parse(buff: &mut BytesMut, max_body: usize, continued: &mut bool, socket: &mut TcpStream) -> Result<Option<HttpRequest>, HttpResponse> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut parsed = httparse::Request::new(&mut headers);
+    let head = match parsed.parse(buff) {
+        Ok(httparse::Status::Complete(head)) => head,
+        Ok(httparse::Status::Partial) if buff.len() > MAX_HEAD => return Err(HttpResponse::text(431, "request head too large")),
+        Ok(httparse::Status::Partial) => return Ok(None),
+        Err(e) => return Err(HttpResponse::text(400, &format!("malformed request: {}", e))),
+    };
+    let headers: Vec<(String, String)> = parsed.headers.iter()
+        .map(|it| (it.name.to_string(), String::from_utf8_lossy(it.value).trim().to_string()))
+        .collect();
+    let find = |name: &str| headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.clone());
+    if find("Transfer-Encoding").is_some() {
+        return Err(HttpResponse::text(501, "chunked transfer encoding is not supported, send Content-Length"));
+    }
+    let length: usize = match find("Content-Length") {
+        Some(length) => length.parse().map_err(|_| HttpResponse::text(400, "bad Content-Length"))?,
+        None => 0,
+    };
+    if length > max_body {
+        return Err(HttpResponse::text(413, &format!("body is limited to {} bytes", max_body)));
+    }
+    if buff.len() < head + length {
+        // clients sending `Expect: 100-continue` wait for this before the body
+        if !*continued && find("Expect").is_some_and(|it| it.eq_ignore_ascii_case("100-continue")) {
+            *continued = true;
+            let _ = socket.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await;
+        }
+        return Ok(None);
+    }
+    let target = parsed.path.unwrap_or("/").to_string();
+    let (path, query) = target.split_once('?').unwrap_or((target.as_str(), ""));
+    let request = HttpRequest {
+        method: parsed.method.unwrap_or("GET").to_string(),
+        path: path.to_string(),
+        query: parse_query(query),
+        headers,
+        body: vec![],
+    };
+    buff.advance(head);
+    let body = buff.split_to(length).to_vec();
+    Ok(Some(HttpRequest { body, ..request }))
+}
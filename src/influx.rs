@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use log::info;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::{watch, Semaphore};
+use crate::config;
+use crate::db::CacheDb;
+use crate::entity::{DataType, Precision, TSCacheValue, TSValue};
+use crate::ingest::{self, WriteResult};
+use crate::method::{Exception, ExceptionKind};
+
+type Db = Arc<CacheDb>;
+
+// largest datagram accepted by the UDP listener
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Float(f64),
+    Integer(i64),
+    Unsigned(u64),
+    Boolean(bool),
+    String(String),
+}
+
+impl FieldValue {
+    // the datatype of a series created by this value
+    pub fn datatype(&self) -> DataType {
+        match self {
+            FieldValue::Float(_) => DataType::Double,
+            FieldValue::Integer(_) | FieldValue::Unsigned(_) | FieldValue::Boolean(_) => DataType::Long,
+            FieldValue::String(_) => DataType::String,
+        }
+    }
+
+    // numbers widen to the series' type, nothing is rounded or truncated
+    pub fn convert(&self, datatype: &DataType) -> Option<TSCacheValue> {
+        let number = match self {
+            FieldValue::Float(it) => Some(*it),
+            FieldValue::Integer(it) => Some(*it as f64),
+            FieldValue::Unsigned(it) => Some(*it as f64),
+            _ => None,
+        };
+        match (datatype, self) {
+            (DataType::Float, _) => number.map(|it| TSCacheValue::Float(it as f32)),
+            (DataType::Double, _) => number.map(TSCacheValue::Double),
+            (DataType::Number, _) => number.map(TSCacheValue::Number),
+            (DataType::Long, FieldValue::Integer(it)) => Some(TSCacheValue::Long(*it)),
+            (DataType::Long, FieldValue::Unsigned(it)) => i64::try_from(*it).ok().map(TSCacheValue::Long),
+            (DataType::Long, FieldValue::Boolean(it)) => Some(TSCacheValue::Long(*it as i64)),
+            (DataType::String, FieldValue::String(it)) => Some(TSCacheValue::String(it.clone())),
+            (DataType::ByteArray, FieldValue::String(it)) => Some(TSCacheValue::ByteArray(it.as_bytes().to_vec())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub measurement: String,
+    pub tags: Vec<(String, String)>,
+    pub fields: Vec<(String, FieldValue)>,
+    pub timestamp: Option<i64>,
+}
+
+impl Line {
+    // `measurement.field`, followed by `;tag=value` for each tag in key order when tags are part of the name
+    pub fn series_name(&self, field: &str, tags_in_name: bool) -> String {
+        let mut name = format!("{}.{}", self.measurement, field);
+        if tags_in_name {
+            let tags: BTreeMap<&str, &str> = self.tags.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect();
+            tags.iter().for_each(|(key, value)| name.push_str(&format!(";{}={}", key, value)));
+        }
+        name
+    }
+}
+
+// byte offsets of `separator` outside of escapes and, when `quotes` is set, outside double quoted strings
+fn split_points(text: &str, separator: u8, quotes: bool) -> Vec<usize> {
+    let bytes = text.as_bytes();
+    let mut points = vec![];
+    let mut quoted = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'"' if quotes => quoted = !quoted,
+            it if it == separator && !quoted => points.push(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    points
+}
+
+fn split(text: &str, separator: u8, quotes: bool) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    for at in split_points(text, separator, quotes) {
+        parts.push(&text[start..at]);
+        start = at + 1;
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+fn split_once(text: &str, separator: u8, quotes: bool) -> Option<(&str, &str)> {
+    split_points(text, separator, quotes).first().map(|at| (&text[..*at], &text[at + 1..]))
+}
+
+// drops the backslash in front of the characters that may be escaped
+fn unescape(text: &str, escaped: &[char]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.peek().filter(|it| escaped.contains(it)) {
+                out.push(*next);
+                chars.next();
+                continue;
+            }
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn field_value(text: &str) -> Result<FieldValue, String> {
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        return Ok(FieldValue::String(unescape(&text[1..text.len() - 1], &['"', '\\'])));
+    }
+    match text {
+        "t" | "T" | "true" | "True" | "TRUE" => return Ok(FieldValue::Boolean(true)),
+        "f" | "F" | "false" | "False" | "FALSE" => return Ok(FieldValue::Boolean(false)),
+        _ => {}
+    }
+    if let Some(number) = text.strip_suffix('i') {
+        return number.parse().map(FieldValue::Integer).map_err(|_| format!("invalid integer `{}`", text));
+    }
+    if let Some(number) = text.strip_suffix('u') {
+        return number.parse().map(FieldValue::Unsigned).map_err(|_| format!("invalid unsigned `{}`", text));
+    }
+    match text.parse::<f64>() {
+        Ok(number) if number.is_finite() => Ok(FieldValue::Float(number)),
+        _ => Err(format!("invalid field value `{}`", text)),
+    }
+}
+
+// `measurement[,tag=value...] field=value[,field=value...] [timestamp]`
+pub fn parse_line(line: &str) -> Result<Line, String> {
+    let (key, rest) = split_once(line, b' ', false).ok_or("missing fields")?;
+    let (fields, timestamp) = match split_once(rest, b' ', true) {
+        Some((fields, timestamp)) => (fields, Some(timestamp.trim())),
+        None => (rest, None),
+    };
+    let mut parts = split(key, b',', false).into_iter();
+    let measurement = unescape(parts.next().unwrap_or_default(), &[',', ' ']);
+    if measurement.is_empty() {
+        return Err("missing measurement".to_string());
+    }
+    let tags = parts.map(|tag| match split_once(tag, b'=', false) {
+        Some((key, value)) if !key.is_empty() && !value.is_empty() => Ok((unescape(key, &[',', '=', ' ']), unescape(value, &[',', '=', ' ']))),
+        _ => Err(format!("invalid tag `{}`", tag)),
+    }).collect::<Result<Vec<_>, String>>()?;
+    let fields = split(fields, b',', true).into_iter().map(|field| match split_once(field, b'=', true) {
+        Some((key, value)) if !key.is_empty() => Ok((unescape(key, &[',', '=', ' ']), field_value(value)?)),
+        _ => Err(format!("invalid field `{}`", field)),
+    }).collect::<Result<Vec<_>, String>>()?;
+    let timestamp = match timestamp {
+        Some(text) if !text.is_empty() => Some(text.parse().map_err(|_| format!("invalid timestamp `{}`", text))?),
+        _ => None,
+    };
+    Ok(Line { measurement, tags, fields, timestamp })
+}
+
+// writes every line of `body`, a bad line or point is reported and does not stop the others
+pub fn write(db: &CacheDb, body: &str, precision: Precision) -> WriteResult {
+    let mut result = WriteResult::default();
+    for (n, line) in body.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = match parse_line(line) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                result.errors.push(format!("line {}: {}", n + 1, e));
+                continue;
+            }
+        };
+        // converted to each series' precision when written, a line without one is stamped by the server
+        let time = match parsed.timestamp {
+            Some(time) if time < 0 => {
+                result.errors.push(format!("line {}: negative timestamp {}", n + 1, time));
+                continue;
+            }
+            Some(time) => Some((time as u128, precision)),
+            None => None,
+        };
+        for (field, value) in &parsed.fields {
+            match write_point(db, &parsed, field, value, time) {
+                Ok(()) => result.points += 1,
+                Err(e) => result.fail(&format!("line {}", n + 1), &e),
+            }
+        }
+    }
+    result
+}
+
+fn write_point(db: &CacheDb, line: &Line, field: &str, value: &FieldValue, time: Option<(u128, Precision)>) -> Result<(), Exception> {
+    let conf = &config::get().influx;
+    let name = line.series_name(field, conf.tags_in_name);
+    let series = ingest::series(db, &name, value.datatype(), || line.tags.iter().cloned().collect(), conf.auto_create)?;
+    let datatype = &series.item.datatype;
+    let value = match value.convert(datatype) {
+        Some(value) => value,
+        None => return Err(Exception::err(ExceptionKind::SaveTypeError, format!("except type:{:?},but input value:{:?}", datatype, value).as_str())),
+    };
+    let key = time.map_or(0, |(time, precision)| precision.convert(time, series.item.precision));
+    db.set(TSValue { name, key, value }).map(|_| ())
+}
+
+// newline separated lines, nothing is sent back, errors are only logged
+pub async fn serve_tcp(listener: TcpListener, db: Db, connections: Arc<Semaphore>, shutdown: watch::Receiver<bool>) {
+    ingest::serve_lines(listener, db, connections, shutdown, "line protocol", |db, body| write(db, body, config::get().influx.precision)).await
+}
+
+// every datagram holds whole lines
+pub async fn serve_udp(socket: UdpSocket, db: Db, mut shutdown: watch::Receiver<bool>) {
+    let precision = config::get().influx.precision;
+    let mut buff = vec![0u8; MAX_DATAGRAM];
+    loop {
+        let received = tokio::select! {
+            received = socket.recv_from(&mut buff) => received,
+            _ = shutdown.wait_for(|stop| *stop) => return,
+        };
+        match received {
+            Ok((length, peer)) => ingest::log_errors("line protocol", &peer.to_string(), &write(&db, &String::from_utf8_lossy(&buff[..length]), precision)),
+            Err(e) => info!("influx udp error: {:?}", e),
+        }
+    }
+}
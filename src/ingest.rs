@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use bytes::BytesMut;
+use log::info;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinSet;
+use crate::config;
+use crate::db::{CacheDb, Series};
+use crate::entity::{DataType, Precision, TSCacheValue, TSItem, TSValue};
+use crate::handle::{after, expire};
+use crate::method::{Exception, ExceptionKind};
+
+// writes a batch of newline separated lines
+pub type WriteLines = fn(&CacheDb, &str) -> WriteResult;
+
+// outcome of a batch from one of the push receivers, a bad point does not stop the others
+#[derive(Debug, Default, PartialEq)]
+pub struct WriteResult {
+    pub points: usize,
+    pub errors: Vec<String>,
+    // some point hit a full write queue, the whole batch may be sent again later
+    pub busy: bool,
+}
+
+impl WriteResult {
+    pub fn fail(&mut self, context: &str, e: &Exception) {
+        self.busy |= e.kind() == Some(ExceptionKind::BackpressureError);
+        self.errors.push(format!("{}: {}", context, e.msg));
+    }
+}
+
+// the series named `name`, when missing and `auto_create` allows it is created with `datatype`,
+// the labels and the capacity, save period, precision and compression of the matching auto_create template or the defaults
+pub fn series(db: &CacheDb, name: &str, datatype: DataType, labels: impl FnOnce() -> BTreeMap<String, String>, auto_create: bool) -> Result<Arc<Series>, Exception> {
+    if let Some(series) = db.get(name) {
+        return Ok(series);
+    }
+    if !auto_create {
+        return Err(Exception::err(ExceptionKind::TSNameExistsError, format!("TSName {} not exist", name).as_str()));
+    }
+    let template = config::get().auto_create.template(name);
+    let item = TSItem {
+        tsName: name.to_string(),
+        capacity: template.and_then(|it| it.capacity).unwrap_or_else(config::default_capacity),
+        datatype,
+        saveTime: template.and_then(|it| it.save_time.clone()).unwrap_or_else(config::default_save_time),
+        labels: labels(),
+        precision: template.and_then(|it| it.precision).unwrap_or_default(),
+        compression: template.and_then(|it| it.compression).unwrap_or_default(),
+    };
+    match db.create(item) {
+        // another writer created it first
+        Err(e) if e.kind() == Some(ExceptionKind::TSNameExistsError) => {}
+        other => other?,
+    }
+    db.get(name).ok_or_else(|| Exception::err(ExceptionKind::TSNameExistsError, format!("TSName {} not exist", name).as_str()))
+}
+
+// Set and SetBatch create a missing series when the auto_create policy covers its name
+pub fn create_missing(db: &CacheDb, value: &TSValue) -> Result<(), Exception> {
+    if db.contains_key(&value.name) {
+        return Ok(());
+    }
+    let allowed = config::get().auto_create.allows(&value.name);
+    series(db, &value.name, DataType::of(&value.value), BTreeMap::new, allowed).map(|_| ())
+}
+
+// a plain number as a value of `datatype`, integral ones also fit a Long series
+pub fn number(value: f64, datatype: &DataType) -> Option<TSCacheValue> {
+    match datatype {
+        DataType::Float => Some(TSCacheValue::Float(value as f32)),
+        DataType::Double => Some(TSCacheValue::Double(value)),
+        DataType::Number => Some(TSCacheValue::Number(value)),
+        DataType::Long if value.fract() == 0.0 && value.abs() < i64::MAX as f64 => Some(TSCacheValue::Long(value as i64)),
+        _ => None,
+    }
+}
+
+// sets a number at `time` given in milliseconds on `name`, a missing series is created as Double
+pub fn set_number(db: &CacheDb, name: &str, labels: impl FnOnce() -> BTreeMap<String, String>, time: u128, value: f64, auto_create: bool) -> Result<(), Exception> {
+    let series = series(db, name, DataType::Double, labels, auto_create)?;
+    let key = Precision::Millis.convert(time, series.item.precision);
+    let datatype = &series.item.datatype;
+    let value = match number(value, datatype) {
+        Some(value) => value,
+        None => return Err(Exception::err(ExceptionKind::SaveTypeError, format!("except type:{:?},but input value:{}", datatype, value).as_str())),
+    };
+    db.set(TSValue { name: name.to_string(), key, value }).map(|_| ())
+}
+
+pub fn log_errors(protocol: &str, from: &str, result: &WriteResult) {
+    if let Some(first) = result.errors.first() {
+        info!("{} {}: {} points written, {} errors, first: {}", from, protocol, result.points, result.errors.len(), first);
+    }
+}
+
+// newline separated lines over TCP, nothing is sent back, errors are only logged
+pub async fn serve_lines(listener: TcpListener, db: Arc<CacheDb>, connections: Arc<Semaphore>, mut shutdown: watch::Receiver<bool>, protocol: &'static str, write: WriteLines) {
+    let mut tasks = JoinSet::new();
+    loop {
+        let permit = tokio::select! {
+            permit = connections.clone().acquire_owned() => permit.unwrap(),
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        };
+        let socket = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((socket, _)) => socket,
+                Err(e) => {
+                    info!("{} accept error: {:?}", protocol, e);
+                    continue;
+                }
+            },
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        };
+        while tasks.try_join_next().is_some() {}
+        let db = db.clone();
+        let shutdown = shutdown.clone();
+        tasks.spawn(async move {
+            let _permit = permit;
+            serve_connection(socket, db, shutdown, protocol, write).await;
+        });
+    }
+    // the connections stop on the same signal, the caller closes the database after they are done
+    while tasks.join_next().await.is_some() {}
+}
+
+async fn serve_connection(mut socket: TcpStream, db: Arc<CacheDb>, mut shutdown: watch::Receiver<bool>, protocol: &str, write: WriteLines) {
+    let conf = config::get();
+    let peer = socket.peer_addr().map(|it| it.to_string()).unwrap_or_default();
+    let mut buff = BytesMut::with_capacity(4096);
+    loop {
+        // a partial line keeps the connection within the read timeout
+        let started = !buff.is_empty();
+        let deadline = if started { after(conf.read_timeout_secs) } else { after(conf.idle_timeout_secs) };
+        let read = tokio::select! {
+            read = socket.read_buf(&mut buff) => read,
+            _ = expire(deadline) => return,
+            _ = shutdown.wait_for(|stop| *stop), if !started => return,
+        };
+        match read {
+            Ok(0) | Err(_) => {
+                if !buff.is_empty() {
+                    log_errors(protocol, &peer, &write(&db, &String::from_utf8_lossy(&buff)));
+                }
+                return;
+            }
+            Ok(_) => {}
+        }
+        if let Some(end) = buff.iter().rposition(|it| *it == b'\n') {
+            let lines = buff.split_to(end + 1);
+            log_errors(protocol, &peer, &write(&db, &String::from_utf8_lossy(&lines)));
+        } else if buff.len() > conf.limits.max_frame_size {
+            info!("{} {}: line exceeds {} bytes, closing", peer, protocol, conf.limits.max_frame_size);
+            return;
+        }
+    }
+}
@@ -1,20 +1,46 @@
 
-use std::fs::{create_dir, create_dir_all, File, OpenOptions};
-use std::io::{BufWriter, Read, Write};
-use std::path::Path;
+use std::fs::{create_dir, create_dir_all, rename, File, OpenOptions};
+use std::io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
-use byteorder::{BigEndian, WriteBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use chrono::Local;
+use log::info;
 use rmp_serde::{to_vec_named,from_slice};
-use crate::entity::{TSItem, TSValue, TSCacheValue, SaveTimePeriod};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use crate::alert::AlertRule;
+use crate::block::{self, Encoder};
+use crate::config;
+use crate::method::CHUNK_POINTS;
+use crate::notify::NotifierConfig;
+use crate::entity::{Compression, DataType, TSItem, TSValue, TSCacheValue, SaveTimePeriod};
 
-static DATA: &str = "./data";
+// segments start with this, followed by `[u32 length][block]` records; older segments without it
+// hold `[u128 key][msgpack value]` per point
+pub const SEGMENT_MAGIC: &[u8; 4] = b"TCB1";
+// a sealed segment of a series with compression, the rest of the file is the whole segment above
+// compressed as one LZ4 block with its size prepended, or as one Zstd frame
+pub const LZ4_MAGIC: &[u8; 4] = b"TCL4";
+pub const ZSTD_MAGIC: &[u8; 4] = b"TCZS";
+// merged segments, `[u8 compression][u32 length][names of the merged files, newline separated]`, the blocks
+// each compressed on its own, then the index `[u128 first key][u128 last key][u64 offset][u32 length]` per
+// block and `[u32 blocks][u64 index offset]`
+pub const COMPACTED_MAGIC: &[u8; 4] = b"TCX1";
+const INDEX_ENTRY: usize = 44;
+const FOOTER: usize = 12;
+
+pub fn data_dir() -> &'static str {
+    config::get().data_dir.as_str()
+}
 
 
 pub struct FileIOCache {
     ts_item: Box<TSItem>,
     path: String,
     write: Option<BufWriter<File>>,
+    // the segment being written
+    file: String,
     current_time: u128,
 }
 
@@ -24,83 +50,377 @@ impl FileIOCache {
             ts_item,
             path: "".to_string(),
             write: None,
+            file: "".to_string(),
             current_time: 0,
         };
         let item = &io.ts_item;
-        io.path = format!("{}/{}", DATA, item.tsName);
-        let path = Path::new(&io.path);
-        if !path.exists() {
-            create_dir_all(path).unwrap();
-        }
+        io.path = series_dir(&item.tsName).to_string_lossy().to_string();
         io
     }
-    fn create_new_file(&mut self) {
+    fn create_new_file(&mut self) -> std::io::Result<()> {
         let today = Local::now().format("%Y-%m-%d").to_string();
         let dir = &format!("{}/{}", self.path, today);
         if !Path::new(&dir).exists() {
-            create_dir_all(dir).unwrap()
+            create_dir_all(dir)?;
         }
         let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
         if self.write.is_none() {
             self.current_time = time;
-            self.write = Some(BufWriter::new(File::create(format!("{}/{}.tc", dir, time)).unwrap()));
+            self.file = format!("{}/{}.tc", dir, time);
+            let mut write = BufWriter::new(File::create(&self.file)?);
+            write.write_all(SEGMENT_MAGIC)?;
+            self.write = Some(write);
         }
+        Ok(())
     }
 
 
-    pub fn append(&mut self, value: &TSValue) {
-        if self.ts_item.saveTime == SaveTimePeriod::Nerve {
-            return;
+    // the points as one block, see `block`; after an error the segment is left as is and the next append starts a new one
+    pub fn append(&mut self, values: &[TSValue]) -> std::io::Result<()> {
+        if self.ts_item.saveTime == SaveTimePeriod::Nerve || values.is_empty() {
+            return Ok(());
         }
         if self.write.is_none() {
-            self.create_new_file();
+            self.create_new_file()?;
         }
-        match self.write {
-            Some(ref mut w) => {
-                let mut buff = vec![];
-                buff.write_u128::<BigEndian>(value.key).unwrap();
-                buff.write(to_vec_named(&value.value).unwrap().as_slice()).unwrap();
-                w.write_all(&buff).unwrap();
+        if let Some(ref mut w) = self.write {
+            let mut encoder = Encoder::new(&self.ts_item.datatype);
+            values.iter().for_each(|value| encoder.push(value.key, &value.value));
+            let block = encoder.finish();
+            let mut buff = vec![];
+            buff.write_u32::<BigEndian>(block.len() as u32)?;
+            buff.extend_from_slice(&block);
+            if let Err(e) = w.write_all(&buff) {
+                self.write = None;
+                return Err(e);
             }
-            _ => {}
         }
+        // segments rotate on the wall clock in milliseconds, whatever the precision of the keys
         let period = self.ts_item.saveTime.as_period() * 1000;
         let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
-        if (time - self.current_time) / period > 1 {
-            self.close();
-            self.write = None;
+        if time.saturating_sub(self.current_time) / period > 1 {
+            self.seal();
+        }
+        Ok(())
+    }
+
+    pub fn close(&mut self) -> std::io::Result<()> {
+        self.flush()
+    }
+
+    // closes the segment for good, compressed as the series asks; the next append starts a new one
+    pub fn seal(&mut self) {
+        if let Some(mut w) = self.write.take() {
+            if let Err(e) = w.flush() {
+                info!("failed to flush {}: {:?}", self.file, e);
+                return;
+            }
+            drop(w);
+            if let Err(e) = compress_segment(Path::new(&self.file), self.ts_item.compression) {
+                info!("failed to compress {}, it is kept as is: {:?}", self.file, e);
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        match self.write {
+            Some(ref mut w) => w.flush(),
+            _ => Ok(()),
         }
     }
 
-    pub fn close(&mut self) {
+    // flushes the buffer and waits until the segment is on disk
+    pub fn sync(&mut self) -> std::io::Result<()> {
         match self.write {
             Some(ref mut w) => {
-                w.flush().unwrap();
+                w.flush()?;
+                w.get_ref().sync_all()
             }
-            _ => {}
+            _ => Ok(()),
         }
     }
 }
 
 
-pub fn write_all_items(items: &Vec<&TSItem>) {
-    let path = format!("{}/time-cache.tc", DATA);
+pub fn write_all_items(items: &Vec<&TSItem>) -> std::io::Result<()> {
+    write_catalog("time-cache.tc", items)
+}
+
+
+pub fn read_all_items(items: &mut Vec<TSItem>) -> std::io::Result<()> {
+    items.append(&mut read_catalog("time-cache.tc")?);
+    Ok(())
+}
+
+
+pub fn write_all_rules(rules: &Vec<&AlertRule>) -> std::io::Result<()> {
+    write_catalog("alerts.tc", rules)
+}
+
+
+pub fn read_all_rules(rules: &mut Vec<AlertRule>) -> std::io::Result<()> {
+    rules.append(&mut read_catalog("alerts.tc")?);
+    Ok(())
+}
+
+
+pub fn write_all_notifiers(notifiers: &Vec<NotifierConfig>) -> std::io::Result<()> {
+    write_catalog("notifiers.tc", notifiers)
+}
+
+
+pub fn read_all_notifiers(notifiers: &mut Vec<NotifierConfig>) -> std::io::Result<()> {
+    notifiers.append(&mut read_catalog("notifiers.tc")?);
+    Ok(())
+}
+
+
+// the data dir is created here too, an embedded store may never have validated its config
+fn write_catalog<T: Serialize>(name: &str, value: &T) -> std::io::Result<()> {
+    create_dir_all(data_dir())?;
+    let path = format!("{}/{}", data_dir(), name);
+    let buff = to_vec_named(value).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path, e)))?;
+    // written aside and renamed so a reader never sees a half written catalog
+    let tmp = format!("{}.tmp", path);
     let mut file = OpenOptions::new()
         .create(true)
         .write(true)
-        .open(path)
-        .unwrap();
-    file.write_all(to_vec_named(&items).unwrap().as_slice()).unwrap();
+        .truncate(true)
+        .open(&tmp)?;
+    file.write_all(buff.as_slice())?;
+    rename(&tmp, &path)
 }
 
 
-pub fn read_all_items(items: &mut Vec<TSItem>) {
-    let path = format!("{}/time-cache.tc", DATA);
-    let mut file = OpenOptions::new().read(true).open(path).unwrap();
+fn read_catalog<T: DeserializeOwned + Default>(name: &str) -> std::io::Result<T> {
+    let path = format!("{}/{}", data_dir(), name);
+    if !Path::new(&path).exists() {
+        return Ok(T::default());
+    }
+    let mut file = OpenOptions::new().read(true).open(&path)?;
     let mut buff = vec![];
-    file.read_to_end(&mut buff).unwrap();
-    let mut result = from_slice(&buff).unwrap();
-    items.append(&mut result);
+    file.read_to_end(&mut buff)?;
+    from_slice(&buff).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path, e)))
 }
 
 
+fn pack(bytes: &[u8], compression: Compression) -> std::io::Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(bytes.to_vec()),
+        Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(bytes)),
+        Compression::Zstd => zstd::encode_all(bytes, 3),
+    }
+}
+
+fn unpack(bytes: &[u8], compression: Compression) -> Result<Vec<u8>, String> {
+    match compression {
+        Compression::None => Ok(bytes.to_vec()),
+        Compression::Lz4 => lz4_flex::decompress_size_prepended(bytes).map_err(|e| e.to_string()),
+        Compression::Zstd => zstd::decode_all(bytes).map_err(|e| e.to_string()),
+    }
+}
+
+// rewrites a closed segment compressed, through a temporary file so a crash leaves either version
+pub fn compress_segment(path: &Path, compression: Compression) -> std::io::Result<()> {
+    let magic = match compression {
+        Compression::None => return Ok(()),
+        Compression::Lz4 => LZ4_MAGIC,
+        Compression::Zstd => ZSTD_MAGIC,
+    };
+    let mut buff = vec![];
+    File::open(path)?.read_to_end(&mut buff)?;
+    let packed = pack(&buff, compression)?;
+    let temp = path.with_extension("tmp");
+    let mut file = File::create(&temp)?;
+    file.write_all(magic)?;
+    file.write_all(&packed)?;
+    file.sync_all()?;
+    rename(&temp, path)
+}
+
+// the names of the files a merged file was written from, they are in the same directory
+pub fn compacted_inputs(path: &Path) -> std::io::Result<Vec<String>> {
+    let invalid = |e: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e));
+    let mut file = File::open(path)?;
+    let mut header = [0u8; 9];
+    file.read_exact(&mut header).map_err(|_| invalid("file ends before the merged names"))?;
+    if &header[..4] != COMPACTED_MAGIC {
+        return Err(invalid("not a merged file"));
+    }
+    let length = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) as u64;
+    let mut names = String::new();
+    file.take(length).read_to_string(&mut names)?;
+    if names.len() as u64 != length {
+        return Err(invalid("file ends before the merged names"));
+    }
+    Ok(names.split('\n').filter(|it| !it.is_empty()).map(|it| it.to_string()).collect())
+}
+
+// every point of a segment file in any format, compressed or not, oldest first
+pub fn read_segment(path: &Path) -> std::io::Result<Vec<TSValue>> {
+    let mut buff = vec![];
+    File::open(path)?.read_to_end(&mut buff)?;
+    let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e));
+    if buff.starts_with(COMPACTED_MAGIC) {
+        return read_compacted(path, &mut Cursor::new(buff), 0, u128::MAX);
+    }
+    if let Some(packed) = buff.strip_prefix(LZ4_MAGIC) {
+        buff = unpack(packed, Compression::Lz4).map_err(invalid)?;
+    } else if let Some(packed) = buff.strip_prefix(ZSTD_MAGIC) {
+        buff = unpack(packed, Compression::Zstd).map_err(invalid)?;
+    }
+    let name = segment_series(path);
+    let mut values = vec![];
+    let mut reader = &buff[..];
+    match reader.strip_prefix(SEGMENT_MAGIC) {
+        Some(mut blocks) => while !blocks.is_empty() {
+            let length = blocks.read_u32::<BigEndian>()? as usize;
+            let block = blocks.get(..length).ok_or_else(|| invalid("block past the end of the file".to_string()))?;
+            for (key, value) in block::decode(block).map_err(invalid)? {
+                values.push(TSValue { name: name.clone(), key, value });
+            }
+            blocks = &blocks[length..];
+        },
+        None => while !reader.is_empty() {
+            let key = reader.read_u128::<BigEndian>()?;
+            let value: TSCacheValue = rmp_serde::from_read(&mut reader).map_err(|e| invalid(e.to_string()))?;
+            values.push(TSValue { name: name.clone(), key, value });
+        },
+    }
+    Ok(values)
+}
+
+// segments are kept under `{data_dir}/{name}/{date}/`
+fn segment_series(path: &Path) -> String {
+    path.parent().and_then(|it| it.parent()).and_then(|it| it.file_name())
+        .map(|it| dir_name_series(&it.to_string_lossy())).unwrap_or_default()
+}
+
+// the directory of a series' segments; names come from tags and labels such as `path=/`, so `%`, path
+// separators and control characters are written as `%XX`, and `.` / `..` as `%2E` / `%2E%2E`
+pub fn series_dir(name: &str) -> PathBuf {
+    let encoded = match name {
+        "." => "%2E".to_string(),
+        ".." => "%2E%2E".to_string(),
+        _ => name.chars().map(|it| match it {
+            '%' | '/' | '\\' => format!("%{:02X}", it as u32),
+            it if it.is_control() => it.to_string().bytes().map(|b| format!("%{:02X}", b)).collect(),
+            it => it.to_string(),
+        }).collect(),
+    };
+    Path::new(data_dir()).join(encoded)
+}
+
+// the series name a `series_dir` directory was made from
+pub fn dir_name_series(dir: &str) -> String {
+    let mut bytes = Vec::with_capacity(dir.len());
+    let mut rest = dir.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let escaped = tail.get(..2).and_then(|hex| std::str::from_utf8(hex).ok()).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(it) if b == b'%' => {
+                bytes.push(it);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+// the points of a segment with `start <= key <= end`, a merged file only reads the blocks its index
+// places in the range
+pub fn read_segment_range(path: &Path, start: u128, end: u128) -> std::io::Result<Vec<TSValue>> {
+    let mut magic = [0u8; 4];
+    let mut file = File::open(path)?;
+    if file.read(&mut magic)? == magic.len() && &magic == COMPACTED_MAGIC {
+        return read_compacted(path, &mut file, start, end);
+    }
+    let mut values = read_segment(path)?;
+    values.retain(|it| it.key >= start && it.key <= end);
+    Ok(values)
+}
+
+fn read_compacted<R: Read + Seek>(path: &Path, reader: &mut R, start: u128, end: u128) -> std::io::Result<Vec<TSValue>> {
+    let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e));
+    let size = reader.seek(SeekFrom::End(0))? as usize;
+    if size < COMPACTED_MAGIC.len() + 5 + FOOTER {
+        return Err(invalid("file ends before the index".to_string()));
+    }
+    reader.seek(SeekFrom::Start(COMPACTED_MAGIC.len() as u64))?;
+    let compression = match reader.read_u8()? {
+        0 => Compression::None,
+        1 => Compression::Lz4,
+        2 => Compression::Zstd,
+        other => return Err(invalid(format!("unknown compression {}", other))),
+    };
+    reader.seek(SeekFrom::Start((size - FOOTER) as u64))?;
+    let blocks = reader.read_u32::<BigEndian>()? as usize;
+    let offset = reader.read_u64::<BigEndian>()? as usize;
+    if offset.checked_add(blocks.saturating_mul(INDEX_ENTRY)) != Some(size - FOOTER) {
+        return Err(invalid("index does not fit in the file".to_string()));
+    }
+    let mut index = vec![0u8; blocks * INDEX_ENTRY];
+    reader.seek(SeekFrom::Start(offset as u64))?;
+    reader.read_exact(&mut index)?;
+    let mut index = index.as_slice();
+    let name = segment_series(path);
+    let mut values = vec![];
+    for _ in 0..blocks {
+        let (first, last) = (index.read_u128::<BigEndian>()?, index.read_u128::<BigEndian>()?);
+        let (at, length) = (index.read_u64::<BigEndian>()? as usize, index.read_u32::<BigEndian>()? as usize);
+        if last < start || first > end {
+            continue;
+        }
+        if at.saturating_add(length) > offset {
+            return Err(invalid("block past the index".to_string()));
+        }
+        let mut packed = vec![0u8; length];
+        reader.seek(SeekFrom::Start(at as u64))?;
+        reader.read_exact(&mut packed)?;
+        let block = unpack(&packed, compression).map_err(invalid)?;
+        for (key, value) in block::decode(&block).map_err(invalid)? {
+            if key >= start && key <= end {
+                values.push(TSValue { name: name.clone(), key, value });
+            }
+        }
+    }
+    Ok(values)
+}
+
+// one merged file holding `points`, key ordered, in blocks of `CHUNK_POINTS`, and the names of the files
+// merged into it; written next to `path` first and renamed so readers see either nothing or the whole file
+pub fn write_compacted(path: &Path, datatype: &DataType, points: &[(u128, TSCacheValue)], compression: Compression, inputs: &[String]) -> std::io::Result<()> {
+    let mut buff = COMPACTED_MAGIC.to_vec();
+    buff.push(match compression {
+        Compression::None => 0,
+        Compression::Lz4 => 1,
+        Compression::Zstd => 2,
+    });
+    let names = inputs.join("\n");
+    buff.write_u32::<BigEndian>(names.len() as u32)?;
+    buff.extend_from_slice(names.as_bytes());
+    let mut index = vec![];
+    for chunk in points.chunks(CHUNK_POINTS) {
+        let mut encoder = Encoder::new(datatype);
+        chunk.iter().for_each(|(key, value)| encoder.push(*key, value));
+        let packed = pack(&encoder.finish(), compression)?;
+        index.write_u128::<BigEndian>(chunk[0].0)?;
+        index.write_u128::<BigEndian>(chunk[chunk.len() - 1].0)?;
+        index.write_u64::<BigEndian>(buff.len() as u64)?;
+        index.write_u32::<BigEndian>(packed.len() as u32)?;
+        buff.extend_from_slice(&packed);
+    }
+    let offset = buff.len() as u64;
+    buff.extend_from_slice(&index);
+    buff.write_u32::<BigEndian>((index.len() / INDEX_ENTRY) as u32)?;
+    buff.write_u64::<BigEndian>(offset)?;
+    let temp = path.with_extension("tmp");
+    let mut file = File::create(&temp)?;
+    file.write_all(&buff)?;
+    file.sync_all()?;
+    rename(&temp, path)
+}
@@ -0,0 +1,41 @@
+//! time-cache as a library: the storage engine can be embedded without the TCP server.
+//!
+//! ```no_run
+//! use time_cache::{CacheDb, DataType, SaveTimePeriod, TSCacheValue, TSItem, TSValue};
+//!
+//! // optional, without it the defaults of `Config` are used and data goes to ./data
+//! time_cache::config::init(time_cache::Config { data_dir: "/var/lib/app/tc".to_string(), ..Default::default() });
+//! let db = CacheDb::open().unwrap();
+//! db.create(TSItem { tsName: "cpu".to_string(), capacity: 1000, datatype: DataType::Double, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision: Default::default(), compression: Default::default() }).unwrap();
+//! db.set(TSValue { name: "cpu".to_string(), key: 1, value: TSCacheValue::Double(0.5) }).unwrap();
+//! assert_eq!(db.last("cpu").unwrap(), TSCacheValue::Double(0.5));
+//! db.shutdown();
+//! ```
+
+pub mod entity;
+pub mod column;
+pub mod block;
+pub mod method;
+pub mod io;
+pub mod handle;
+pub mod db;
+pub mod alert;
+pub mod notify;
+pub mod config;
+pub mod writer;
+pub mod compact;
+pub mod frame;
+pub mod http;
+pub mod gateway;
+pub mod resp;
+pub mod ingest;
+pub mod influx;
+pub mod prometheus;
+pub mod graphite;
+pub mod statsd;
+
+pub use config::Config;
+pub use db::{CacheDb, MemoryUsage, Series, SeriesInfo};
+pub use entity::{Compression, DataType, Precision, SaveTimePeriod, TSCacheValue, TSItem, TSPoint, TSValue};
+pub use io::FileIOCache;
+pub use method::{Exception, ExceptionKind, TSQueue};
@@ -1,109 +1,168 @@
-mod entity;
-mod method;
-mod io;
-mod handle;
-mod db;
-
-use tokio::net::{TcpListener, TcpStream};
-use std::collections::HashMap;
-use std::io::{Error, ErrorKind, Read};
-use std::panic;
+use tokio::net::{TcpListener, UdpSocket};
 use std::sync::{Arc};
-use bytes::BytesMut;
+use std::time::Duration;
 use log::info;
-use rmp_serde::to_vec_named;
-use tokio::io::{AsyncReadExt, BufWriter};
-use tokio::sync::Mutex;
+use clap::Parser;
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinSet;
+use time_cache::{compact, config, graphite, handle, http, influx, notify, resp, statsd, CacheDb};
 
 
 #[tokio::main]
 async fn main() {
-    log4rs::init_file("log4rs.yaml", Default::default()).unwrap();
-    let listener = TcpListener::bind("127.0.0.1:8080").await.unwrap();
-    let mut db = Arc::new(Mutex::new(CacheDb::new()));
-    {
-        let mut result = db.lock().await;
-        result.init();
+    let args = config::Args::parse();
+    let conf = match config::Config::load(&args) {
+        Ok(conf) => conf,
+        Err(e) => {
+            eprintln!("config error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    log4rs::init_file(&conf.log_config, Default::default()).unwrap();
+    let listener = match TcpListener::bind(&conf.listen).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("cannot listen on {}: {}", conf.listen, e);
+            std::process::exit(2);
+        }
+    };
+    let http_listener = bind(&conf.http_listen).await;
+    let resp_listener = bind(&conf.resp_listen).await;
+    let influx_listener = bind(&conf.influx.tcp_listen).await;
+    let influx_socket = bind_udp(&conf.influx.udp_listen).await;
+    let graphite_listener = bind(&conf.graphite.tcp_listen).await;
+    let statsd_socket = bind_udp(&conf.statsd.udp_listen).await;
+    let connections = Arc::new(Semaphore::new(conf.limits.max_connections));
+    info!("listening on {}, data in {}", conf.listen, conf.data_dir);
+    config::init(conf);
+    let db = match CacheDb::open() {
+        Ok(db) => Arc::new(db),
+        Err(e) => {
+            eprintln!("cannot load {}: {}", config::get().data_dir, e.msg);
+            std::process::exit(2);
+        }
+    };
+    tokio::spawn(notify::run_dispatcher(db.clone()));
+    let alert_db = db.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            alert_db.check_alerts();
+        }
+    });
+    let (stop, shutdown) = watch::channel(false);
+    tokio::spawn(compact::run(db.clone(), shutdown.clone()));
+    // the other listeners write too and are waited for like the binary protocol's connections
+    let mut listeners = vec![];
+    if let Some(listener) = http_listener {
+        info!("http gateway on {}", listener.local_addr().unwrap());
+        listeners.push(tokio::spawn(http::serve(listener, db.clone(), connections.clone(), shutdown.clone())));
+    }
+    if let Some(listener) = resp_listener {
+        info!("resp listener on {}", listener.local_addr().unwrap());
+        listeners.push(tokio::spawn(resp::serve(listener, db.clone(), connections.clone(), shutdown.clone())));
+    }
+    if let Some(listener) = influx_listener {
+        info!("line protocol on tcp {}", listener.local_addr().unwrap());
+        listeners.push(tokio::spawn(influx::serve_tcp(listener, db.clone(), connections.clone(), shutdown.clone())));
+    }
+    if let Some(socket) = influx_socket {
+        info!("line protocol on udp {}", socket.local_addr().unwrap());
+        listeners.push(tokio::spawn(influx::serve_udp(socket, db.clone(), shutdown.clone())));
+    }
+    if let Some(listener) = graphite_listener {
+        info!("graphite on tcp {}", listener.local_addr().unwrap());
+        listeners.push(tokio::spawn(graphite::serve_tcp(listener, db.clone(), connections.clone(), shutdown.clone())));
     }
-    println!("{:p}", &db);
+    // flushes what it aggregated when stopping, the database has to wait for it
+    let statsd_task = statsd_socket.map(|socket| {
+        info!("statsd on udp {}", socket.local_addr().unwrap());
+        tokio::spawn(statsd::serve_udp(socket, db.clone(), shutdown.clone()))
+    });
+    let mut tasks = JoinSet::new();
+    let signal = shutdown_signal();
+    tokio::pin!(signal);
     loop {
-        let (mut socket, _) = listener.accept().await.unwrap();
+        let permit = tokio::select! {
+            permit = connections.clone().acquire_owned() => permit.unwrap(),
+            _ = &mut signal => break,
+        };
+        let (socket, _) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    info!("accept error: {:?}", e);
+                    continue;
+                }
+            },
+            _ = &mut signal => break,
+        };
+        while tasks.try_join_next().is_some() {}
         let db_ = db.clone();
-        // println!("Accepted:{:p}",&db_);
-        tokio::spawn(async move {
-            loop {
-                match handle::process(&mut socket, &db_).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        info!("{:?}",e);
-                        match socket.write_all(to_vec_named(&e).unwrap().as_slice()).await {
-                            Ok(_) => {}
-                            Err(e) => {
-                                info!("{:?}",e);
-                                break;
-                            }
-                        };
-                        if e.code == -1 {
-                            break;
-                        }
-                    }
-                };
-            }
+        let shutdown = shutdown.clone();
+        tasks.spawn(async move {
+            let _permit = permit;
+            handle::serve(socket, db_, shutdown).await;
         });
     }
+    drop(listener);
+    info!("shutting down, waiting for {} connections", tasks.len());
+    stop.send_replace(true);
+    let timeout = Duration::from_secs(config::get().shutdown_timeout_secs);
+    let finished = tokio::time::timeout(timeout, async {
+        while tasks.join_next().await.is_some() {}
+        for listener in listeners.iter_mut() {
+            let _ = listener.await;
+        }
+    }).await;
+    if finished.is_err() {
+        info!("shutdown timeout, aborting {} connections", tasks.len());
+        tasks.shutdown().await;
+        for listener in listeners {
+            listener.abort();
+            let _ = listener.await;
+        }
+    }
+    if let Some(task) = statsd_task {
+        let _ = task.await;
+    }
+    db.shutdown();
+    info!("shutdown complete");
 }
 
-
-use tokio::io::{AsyncWriteExt};
-use crate::db::CacheDb;
-
-#[tokio::main]
-async fn main1() -> Result<(), Box<dyn std::error::Error>> {
-    let listener = TcpListener::bind("127.0.0.1:8080").await?;
-
-    loop {
-        let (mut socket, _) = listener.accept().await?;
-
-        tokio::spawn(async move {
-            // In a loop, read data from the socket and write the data back.
-            loop {
-                match process(&mut socket).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("{:#?}", e);
-                        break;
-                    }
-                };
-            }
-        });
+// optional listeners, a configured address that cannot be bound stops the server
+async fn bind(addr: &Option<String>) -> Option<TcpListener> {
+    let addr = addr.as_ref()?;
+    match TcpListener::bind(addr).await {
+        Ok(listener) => Some(listener),
+        Err(e) => {
+            eprintln!("cannot listen on {}: {}", addr, e);
+            std::process::exit(2);
+        }
     }
 }
 
-
-async fn process(socket: &mut TcpStream) -> Result<(), Error> {
-    let mut buff = vec![];
-    let mut steam = BufWriter::new(socket);
-    loop {
-        let mut buf = BytesMut::with_capacity(1);
-        // steam.read_buf(&mut buf).await?;
-        let n = match steam.read_buf(&mut buf).await {
-            // socket closed
-            Ok(n) if n == 0 => {
-                println!("read 0 bytes");
-                return Err(Error::new(ErrorKind::Other, "read 0 bytes"));
-            }
-            Ok(n) => n,
-            Err(e) => {
-                eprintln!("failed to read from socket; err = {:?}", e);
-                return Err(Error::new(ErrorKind::Other, "failed to read from socket; err = {:?}"));
-            }
-        };
-        println!("read {} bytes", n);
-        buff.extend_from_slice(&buf[..n]);
+async fn bind_udp(addr: &Option<String>) -> Option<UdpSocket> {
+    let addr = addr.as_ref()?;
+    match UdpSocket::bind(addr).await {
+        Ok(socket) => Some(socket),
+        Err(e) => {
+            eprintln!("cannot listen on udp {}: {}", addr, e);
+            std::process::exit(2);
+        }
     }
-    if buff.len() == 0 {
-        println!("eof");
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut term = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = term.recv() => {}
+        }
     }
-    // Write the data back
-    socket.write_all(&buff).await
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.unwrap();
 }
@@ -1,92 +1,221 @@
 use bytes::{BufMut, BytesMut};
 use lazy_static::lazy_static;
-use msgpack_simple::MsgPack;
-use std::collections::HashMap;
-use tokio::sync::MutexGuard;
+use std::collections::{HashMap, VecDeque};
+use std::mem::size_of;
 
-use crate::entity::{TSCacheValue, TSItem, TSValue};
+use crate::entity::{Precision, TSCacheValue, TSItem, TSValue};
 use crate::io::FileIOCache;
-use rmp_serde::{from_slice, to_vec_named};
+use rmp_serde::{to_vec_named, Deserializer};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use ExceptionKind::{TSNameExistsError, TimeSerieError};
+use crate::alert::AlertRule;
+use crate::block::{self, Encoder};
+use crate::column::{push_bounded, Column};
 use crate::db::CacheDb;
+use crate::ingest;
+use crate::frame::{PAYLOAD_MSGPACK, PAYLOAD_VALUE};
+use crate::notify::NotifierConfig;
 
+// points per sealed block, queues holding no more than this are kept uncompressed
+pub const CHUNK_POINTS: usize = 1024;
+
+// a full chunk encoded by `block`, decoded again for every query that touches it
+struct Block {
+    first: u128,
+    last: u128,
+    count: usize,
+    // bytes the points took before sealing
+    raw: usize,
+    data: Box<[u8]>,
+}
+
+impl Block {
+    fn points(&self) -> Vec<(u128, TSCacheValue)> {
+        block::decode(&self.data).unwrap()
+    }
+}
+
+// the newest `capacity` points: queues up to CHUNK_POINTS are a ring, larger ones fill a chunk
+// and seal it into a compressed block, dropping points from the oldest block once full;
+// keys of the chunk are kept as offsets from its first key and values in a column of the series' type
 pub struct TSQueue {
     ts_item: Box<TSItem>,
     capacity: usize,
+    chunk: usize,
+    blocks: VecDeque<Block>,
+    // points in blocks, of which the first `skip` are already dropped
+    sealed: usize,
+    skip: usize,
+    // slot of the oldest point once the ring is full
     index: usize,
-    len: usize,
-    keys: Vec<u128>,
-    values: Vec<Box<TSCacheValue>>,
+    base: u128,
+    keys: Vec<u64>,
+    values: Column,
 }
 impl TSQueue {
     pub fn new(item: Box<TSItem>, capacity: usize) -> TSQueue {
+        let values = Column::new(&item.datatype);
         TSQueue {
             ts_item: item,
             capacity,
+            chunk: capacity.min(CHUNK_POINTS),
+            blocks: VecDeque::new(),
+            sealed: 0,
+            skip: 0,
             index: 0,
-            len: 0,
-            keys: vec![0; capacity],
-            values: vec![Box::new(TSCacheValue::Long(0)); capacity],
+            base: 0,
+            keys: vec![],
+            values,
         }
     }
 
-    pub fn insert(&mut self, time: u128, value: Box<TSCacheValue>) -> Result<(), Exception> {
-        if self.index == self.capacity {
-            self.index = 0;
+    pub fn insert(&mut self, time: u128, value: TSCacheValue) -> Result<(), Exception> {
+        match self.last_key() {
+            None if time == 0 => return Err(Exception::err(TimeSerieError, "time must be greater than 0")),
+            Some(last) if last >= time => {
+                return Err(Exception::err(TimeSerieError, format!("current key:{} must be greater than last time", time).as_str()));
+            }
+            _ => {}
         }
-        if self.len == 0 && self.keys[0] >= time {
-            return Err(Exception::err(TimeSerieError, "time must be greater than 0"));
+        if !self.ts_item.datatype.equal(&value) {
+            return Err(Exception::err(ExceptionKind::SaveTypeError, format!("except type:{:?},but input type:{:?}", self.ts_item.datatype, value).as_str()));
         }
-        if self.len > 0 && self.index == 0 && self.keys[self.capacity - 1] >= time {
-            return Err(Exception::err(TimeSerieError, format!("current key:{} must be greater than last time", time).as_str()));
+        if self.keys.len() == self.chunk && self.chunk < self.capacity {
+            self.seal();
         }
-        if self.len > 0 && self.index != 0 && self.keys[self.index - 1] >= time {
-            return Err(Exception::err(TSNameExistsError, format!("current key:{} must be greater than last time", time).as_str()));
+        let offset = match self.keys.is_empty() {
+            true => 0,
+            false => u64::try_from(time - self.base)
+                .map_err(|_| Exception::err(TimeSerieError, format!("current key:{} is too far from the first key {}", time, self.base).as_str()))?,
+        };
+        let slot = if self.keys.len() < self.chunk { self.keys.len() } else { self.index };
+        let stored = self.values.put(slot, &value, self.chunk);
+        debug_assert!(stored);
+        if self.keys.is_empty() {
+            self.base = time;
+        }
+        if slot == self.keys.len() {
+            push_bounded(&mut self.keys, offset, self.chunk);
+        } else {
+            self.keys[slot] = offset;
+            self.index = (slot + 1) % self.chunk;
+        }
+        if self.count() > self.capacity {
+            self.skip += 1;
+            if self.skip == self.blocks[0].count {
+                self.sealed -= self.blocks.pop_front().unwrap().count;
+                self.skip = 0;
+            }
         }
-        self.keys[self.index] = time;
-        self.values[self.index] = value;
-        self.index += 1;
-        self.len += 1;
         Ok(())
     }
 
-    pub unsafe fn query_times(&mut self, start_time: u128, end_time: u128) -> Vec<&TSCacheValue> {
-        let mut buff = vec![];
-        if self.len < self.capacity {
-            unsafe {
-                for i in 0..self.index {
-                    if self.keys[i] < end_time && self.keys[i] > start_time {
-                        buff.push(&*self.values[i])
-                    }
-                }
+    fn seal(&mut self) {
+        let mut encoder = Encoder::new(&self.ts_item.datatype);
+        (0..self.keys.len()).for_each(|n| encoder.push(self.key(n), &self.values.get(self.slot(n))));
+        let count = self.keys.len();
+        let raw = count * size_of::<u64>() + self.values.used_bytes();
+        self.blocks.push_back(Block { first: self.key(0), last: self.key(count - 1), count, raw, data: encoder.finish().into_boxed_slice() });
+        self.sealed += count;
+        self.keys.clear();
+        self.values.clear();
+        self.index = 0;
+    }
+
+    // the slot of the n-th oldest point of the chunk
+    fn slot(&self, n: usize) -> usize {
+        (self.index + n) % self.chunk
+    }
+
+    fn key(&self, n: usize) -> u128 {
+        self.base + self.keys[self.slot(n)] as u128
+    }
+
+    fn point(&self, n: usize) -> (u128, TSCacheValue) {
+        (self.key(n), self.values.get(self.slot(n)))
+    }
+
+    // how many points of the chunk have a key below `time`, the keys are ordered oldest to newest
+    fn before(&self, time: u128) -> usize {
+        let (mut low, mut high) = (0, self.keys.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.key(mid) < time { low = mid + 1 } else { high = mid }
+        }
+        low
+    }
+
+    // the points of the i-th block that are still held
+    fn block_points(&self, i: usize) -> impl Iterator<Item = (u128, TSCacheValue)> {
+        self.blocks[i].points().into_iter().skip(if i == 0 { self.skip } else { 0 })
+    }
+
+    // points with start <= key <= end, oldest first
+    pub fn query_times(&self, start_time: u128, end_time: u128) -> Vec<(u128, TSCacheValue)> {
+        let mut points = vec![];
+        for (i, block) in self.blocks.iter().enumerate() {
+            if block.last >= start_time && block.first <= end_time {
+                points.extend(self.block_points(i).filter(|(key, _)| *key >= start_time && *key <= end_time));
             }
-        } else {
-            for i in self.index..(self.index + self.capacity) {
-                let mut j = i;
-                if i >= self.capacity { j = i % self.capacity; }
-                if self.keys[j] < end_time && self.keys[j] > start_time {
-                    buff.push(&*self.values[i])
-                }
+        }
+        let first = self.before(start_time);
+        let last = self.before(end_time.saturating_add(1)).max(first);
+        points.extend((first..last).map(|n| self.point(n)));
+        points
+    }
+
+    // the newest point at or before `time`
+    pub fn query_time(&self, time: u128) -> Option<(u128, TSCacheValue)> {
+        match self.before(time.saturating_add(1)) {
+            0 => {
+                let i = self.blocks.iter().rposition(|block| block.first <= time)?;
+                self.block_points(i).take_while(|(key, _)| *key <= time).last()
             }
+            n => Some(self.point(n - 1)),
         }
-        buff
     }
 
-    pub fn query_time(&mut self, time: u128) -> Option<&TSCacheValue> {
-        let key = self.keys.iter().enumerate().min_by(|a, b| {
-            (a.1 - time).cmp(&(b.1 - time))
-        });
-        if key.is_some() {
-            return None;
+    pub fn query_last(&self) -> Option<TSCacheValue> {
+        match self.keys.len() {
+            0 => self.blocks.back().and_then(|block| block.points().pop()).map(|(_, value)| value),
+            n => Some(self.values.get(self.slot(n - 1))),
         }
-        let i = key?.0;
-        // let cache_value = self.values;
-        Some(&self.values[i])
     }
 
-    pub fn query_last(&mut self) -> Option<&TSCacheValue> {
-        if self.len == 0 { None } else { Some(&self.values[self.index - 1]) }
+    pub fn last_key(&self) -> Option<u128> {
+        match self.keys.len() {
+            0 => self.blocks.back().map(|block| block.last),
+            n => Some(self.key(n - 1)),
+        }
+    }
+
+    pub fn query_first(&self) -> Option<(u128, TSCacheValue)> {
+        match self.blocks.is_empty() {
+            true if self.keys.is_empty() => None,
+            true => Some(self.point(0)),
+            false => self.block_points(0).next(),
+        }
+    }
+
+    // points currently held, at most the capacity
+    pub fn count(&self) -> usize {
+        self.sealed - self.skip + self.keys.len()
+    }
+
+    // bytes held by the queue including its keys and values
+    pub fn memory_usage(&self) -> usize {
+        let blocks: usize = self.blocks.iter().map(|it| it.data.len()).sum();
+        size_of::<TSQueue>() + size_of::<TSItem>() + self.blocks.capacity() * size_of::<Block>() + blocks
+            + self.keys.capacity() * size_of::<u64>() + self.values.heap_bytes()
+    }
+
+    // bytes the points would take uncompressed over the bytes they take, 1 without sealed blocks
+    pub fn compression_ratio(&self) -> f64 {
+        let active = self.keys.len() * size_of::<u64>() + self.values.used_bytes();
+        let raw: usize = self.blocks.iter().map(|it| it.raw).sum::<usize>() + active;
+        let stored: usize = self.blocks.iter().map(|it| it.data.len()).sum::<usize>() + active;
+        if stored == 0 { 1.0 } else { raw as f64 / stored as f64 }
     }
 }
 
@@ -96,15 +225,42 @@ pub struct Exception {
     pub msg: String,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ExceptionKind {
     ParamParseError,
     TSNameExistsError,
     QueueIsNullError,
     TimeSerieError,
     SaveTypeError,
+    AlertRuleError,
+    NotifyError,
+    LimitError,
+    BackpressureError,
+    UnknownMethodError,
+    InvalidNameError,
+    DiskError,
 }
 
 impl ExceptionKind {
+    pub fn from_code(code: i16) -> Option<ExceptionKind> {
+        let kind = match code {
+            4001 => ExceptionKind::ParamParseError,
+            4002 => TSNameExistsError,
+            4003 => ExceptionKind::QueueIsNullError,
+            4004 => TimeSerieError,
+            4005 => ExceptionKind::SaveTypeError,
+            4006 => ExceptionKind::AlertRuleError,
+            4007 => ExceptionKind::NotifyError,
+            4008 => ExceptionKind::LimitError,
+            4009 => ExceptionKind::BackpressureError,
+            4010 => ExceptionKind::UnknownMethodError,
+            4011 => ExceptionKind::InvalidNameError,
+            4012 => ExceptionKind::DiskError,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
     fn as_code(&self) -> i16 {
         match self {
             ExceptionKind::ParamParseError => 4001,
@@ -112,6 +268,13 @@ impl ExceptionKind {
             ExceptionKind::QueueIsNullError => 4003,
             TimeSerieError => 4004,
             ExceptionKind::SaveTypeError => 4005,
+            ExceptionKind::AlertRuleError => 4006,
+            ExceptionKind::NotifyError => 4007,
+            ExceptionKind::LimitError => 4008,
+            ExceptionKind::BackpressureError => 4009,
+            ExceptionKind::UnknownMethodError => 4010,
+            ExceptionKind::InvalidNameError => 4011,
+            ExceptionKind::DiskError => 4012,
         }
     }
 }
@@ -131,6 +294,10 @@ impl Exception {
     pub fn ok(&self, msg: &str) -> Exception {
         Exception::new(0, msg)
     }
+
+    pub fn kind(&self) -> Option<ExceptionKind> {
+        ExceptionKind::from_code(self.code)
+    }
 }
 pub struct TSMethod {
     code: u16,
@@ -139,21 +306,42 @@ pub struct TSMethod {
 
 pub enum MethodKind {
     Create,
+    List,
+    Drop,
     Set,
+    SetBatch,
 
     Get,
     Range,
     Query,
+
+    CreateAlert,
+    DropAlert,
+    ListAlerts,
+    SubscribeAlerts,
+    CreateNotifier,
+    DropNotifier,
+    ListNotifiers,
 }
 
 impl MethodKind {
     pub fn as_code(&self) -> u16 {
         match self {
             MethodKind::Create => 101,
+            MethodKind::List => 102,
+            MethodKind::Drop => 103,
             MethodKind::Set => 201,
+            MethodKind::SetBatch => 202,
             MethodKind::Get => 301,
             MethodKind::Range => 302,
             MethodKind::Query => 303,
+            MethodKind::CreateAlert => 401,
+            MethodKind::DropAlert => 402,
+            MethodKind::ListAlerts => 403,
+            MethodKind::SubscribeAlerts => 404,
+            MethodKind::CreateNotifier => 405,
+            MethodKind::DropNotifier => 406,
+            MethodKind::ListNotifiers => 407,
         }
     }
 }
@@ -167,8 +355,19 @@ impl TSMethod {
 lazy_static!(
     static ref  HANDLER_METHOD: Vec<TSMethod> = vec![
         TSMethod::new(MethodKind::Create,Box::new(CreateItemAction)),
+        TSMethod::new(MethodKind::List,Box::new(ListItemsAction)),
+        TSMethod::new(MethodKind::Drop,Box::new(DropItemAction)),
         TSMethod::new(MethodKind::Set,Box::new(SetValueAction)),
+        TSMethod::new(MethodKind::SetBatch,Box::new(SetBatchAction)),
         TSMethod::new(MethodKind::Get,Box::new(GetValueAction)),
+        TSMethod::new(MethodKind::Range,Box::new(RangeValuesAction)),
+        TSMethod::new(MethodKind::Query,Box::new(QueryValueAction)),
+        TSMethod::new(MethodKind::CreateAlert,Box::new(CreateAlertAction)),
+        TSMethod::new(MethodKind::DropAlert,Box::new(DropAlertAction)),
+        TSMethod::new(MethodKind::ListAlerts,Box::new(ListAlertsAction)),
+        TSMethod::new(MethodKind::CreateNotifier,Box::new(CreateNotifierAction)),
+        TSMethod::new(MethodKind::DropNotifier,Box::new(DropNotifierAction)),
+        TSMethod::new(MethodKind::ListNotifiers,Box::new(ListNotifiersAction)),
     ];
 );
 
@@ -184,83 +383,196 @@ pub fn choose_method(action: u16) -> Option<&'static Box<dyn Method>> {
     methods.iter().find(|&method| { method.code == action }).map(|it| { &it.method })
 }
 
+// the whole payload must be one msgpack value, trailing bytes are rejected rather than ignored
+fn parse_param<T: DeserializeOwned>(param: &[u8]) -> Result<T, Exception> {
+    let mut rest = param;
+    let value = match T::deserialize(&mut Deserializer::new(&mut rest)) {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(Exception::err(ExceptionKind::ParamParseError, format!("parse msgpack error:{}", e).as_str()));
+        }
+    };
+    if !rest.is_empty() {
+        return Err(Exception::err(ExceptionKind::ParamParseError, format!("{} unexpected bytes after msgpack value", rest.len()).as_str()));
+    }
+    Ok(value)
+}
+
 pub trait Method: Send + Sync {
-    fn do_method(&self, param: &[u8], db: &mut MutexGuard<CacheDb>, out: &mut BytesMut) -> Result<(), Exception>;
+    fn do_method(&self, param: &[u8], db: &CacheDb, out: &mut BytesMut) -> Result<(), Exception>;
+
+    // what `out` holds, announced in the response frame
+    fn payload_type(&self) -> u8 {
+        PAYLOAD_MSGPACK
+    }
 }
 // #[derive(Debug, Copy,Clone)]
 struct CreateItemAction;
 impl Method for CreateItemAction {
-    fn do_method(&self, param: &[u8], db: &mut MutexGuard<CacheDb>, out: &mut BytesMut) -> Result<(), Exception> {
-        let item: TSItem = match from_slice(param) {
-            Ok(v) => v,
-            Err(e) => {
-                return Err(Exception::err(ExceptionKind::ParamParseError, format!("parse msgpack error:{}", e).as_str()));
-            }
-        };
-        let name = item.tsName.as_str();
-        let cap = item.capacity;
-        if !db.contains_key(name) {
-            let new = item.clone();
-            db.create_new_item(item, TSQueue::new(Box::new(new), cap));
-        } else {
-            return Err(Exception::err(ExceptionKind::TSNameExistsError, format!("duplicate TSName {}", item.tsName).as_str()));
-        }
+    fn do_method(&self, param: &[u8], db: &CacheDb, _out: &mut BytesMut) -> Result<(), Exception> {
+        let item: TSItem = parse_param(param)?;
+        db.create(item)
+    }
+}
+
+// List, the optional parameter is a name prefix
+struct ListItemsAction;
+impl Method for ListItemsAction {
+    fn do_method(&self, param: &[u8], db: &CacheDb, out: &mut BytesMut) -> Result<(), Exception> {
+        let prefix: Option<String> = if param.is_empty() { None } else { parse_param(param)? };
+        let mut items = db.describe();
+        items.retain(|it| prefix.as_ref().is_none_or(|prefix| it.item.tsName.starts_with(prefix.as_str())));
+        items.sort_by(|a, b| a.item.tsName.cmp(&b.item.tsName));
+        out.put_slice(to_vec_named(&items).unwrap().as_slice());
         Ok(())
     }
 }
 
-// Set
+struct DropItemAction;
+impl Method for DropItemAction {
+    fn do_method(&self, param: &[u8], db: &CacheDb, _out: &mut BytesMut) -> Result<(), Exception> {
+        let name: String = parse_param(param)?;
+        db.drop_item(name.as_str())
+    }
+}
+
+// Set, replies with the stored key, the one the server assigned when the point had none
 struct SetValueAction;
 impl Method for SetValueAction {
-    fn do_method(&self, param: &[u8], db: &mut MutexGuard<CacheDb>, out: &mut BytesMut) -> Result<(), Exception> {
-        let mut value: TSValue = match from_slice(param) {
-            Ok(v) => v,
-            Err(e) => {
-                return Err(Exception::err(ExceptionKind::ParamParseError, format!("parse msgpack error:{}", e).as_str()));
+    fn do_method(&self, param: &[u8], db: &CacheDb, out: &mut BytesMut) -> Result<(), Exception> {
+        let mut value: TSValue = parse_param(param)?;
+        ingest::create_missing(db, &value)?;
+        let key = db.insert_new_value(&mut value)?;
+        out.put_slice(to_vec_named(&key).unwrap().as_slice());
+        Ok(())
+    }
+}
+
+// points are inserted in order and the first failure stops the batch, earlier points stay written;
+// replies with the stored keys
+struct SetBatchAction;
+impl Method for SetBatchAction {
+    fn do_method(&self, param: &[u8], db: &CacheDb, out: &mut BytesMut) -> Result<(), Exception> {
+        let values: Vec<TSValue> = parse_param(param)?;
+        let mut keys = Vec::with_capacity(values.len());
+        for (i, mut value) in values.into_iter().enumerate() {
+            match ingest::create_missing(db, &value).and_then(|_| db.insert_new_value(&mut value)) {
+                Ok(key) => keys.push(key),
+                Err(e) => return Err(Exception::new(e.code, format!("point {}: {}", i, e.msg).as_str())),
             }
-        };
-        if !db.contains_key(value.name.as_str()) {
-            return Err(Exception::err(ExceptionKind::TSNameExistsError, format!("TSName {} not exist", value.name).as_str()));
         }
-        db.insert_new_value(&mut value)
+        out.put_slice(to_vec_named(&keys).unwrap().as_slice());
+        Ok(())
     }
 }
 
-
 //
 struct GetValueAction;
 impl Method for GetValueAction {
-    fn do_method(&self, param: &[u8], db: &mut MutexGuard<CacheDb>, out: &mut BytesMut) -> Result<(), Exception> {
-        let ts_name = match MsgPack::parse(param) {
-            Ok(v) => match v.as_string() {
-                Ok(v) => v,
-                Err(e) => {
-                    return Err(Exception::err(ExceptionKind::ParamParseError, format!("parse msgpack error:{}", e).as_str()))
-                }
-            },
-            Err(e) => {
-                return Err(Exception::err(ExceptionKind::ParamParseError, format!("parse msgpack error:{}", e).as_str()))
-            }
-        };
-        if !db.contains_key(ts_name.as_str()) {
-            return Err(Exception::err(ExceptionKind::TSNameExistsError, format!("TSName {} not exist", ts_name).as_str()));
-        }
-        let queue = db.get_mut(ts_name.as_str()).unwrap();
-        let v = match queue.query_last() {
-            Some(v) => v,
-            None => {
-                return Err(Exception::err(ExceptionKind::QueueIsNullError, format!("Queue is empty:{}", ts_name).as_str()))
-            }
-        };
-        out.put_slice(to_vec_named(v).unwrap().as_slice());
+    fn do_method(&self, param: &[u8], db: &CacheDb, out: &mut BytesMut) -> Result<(), Exception> {
+        let ts_name: String = parse_param(param)?;
+        let v = db.last(ts_name.as_str())?;
+        out.put_slice(to_vec_named(&v).unwrap().as_slice());
         Ok(())
     }
+
+    fn payload_type(&self) -> u8 {
+        PAYLOAD_VALUE
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RangeParam {
+    pub name: String,
+    pub start: u128,
+    pub end: u128,
+    // unit of start, end and the returned keys, the series' precision when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precision: Option<Precision>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QueryParam {
+    pub name: String,
+    pub time: u128,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precision: Option<Precision>,
+}
 
+// Range, every point with start <= key <= end
+struct RangeValuesAction;
+impl Method for RangeValuesAction {
+    fn do_method(&self, param: &[u8], db: &CacheDb, out: &mut BytesMut) -> Result<(), Exception> {
+        let range: RangeParam = parse_param(param)?;
+        let points = match range.precision {
+            Some(unit) => db.range_as(range.name.as_str(), range.start, range.end, unit)?,
+            None => db.range(range.name.as_str(), range.start, range.end)?,
+        };
+        out.put_slice(to_vec_named(&points).unwrap().as_slice());
+        Ok(())
+    }
+}
 
+// Query, the newest point at or before the time
+struct QueryValueAction;
+impl Method for QueryValueAction {
+    fn do_method(&self, param: &[u8], db: &CacheDb, out: &mut BytesMut) -> Result<(), Exception> {
+        let query: QueryParam = parse_param(param)?;
+        let point = match query.precision {
+            Some(unit) => db.query_as(query.name.as_str(), query.time, unit)?,
+            None => db.query(query.name.as_str(), query.time)?,
+        };
+        out.put_slice(to_vec_named(&point).unwrap().as_slice());
+        Ok(())
+    }
+}
+
+// Alert
+struct CreateAlertAction;
+impl Method for CreateAlertAction {
+    fn do_method(&self, param: &[u8], db: &CacheDb, _out: &mut BytesMut) -> Result<(), Exception> {
+        let rule: AlertRule = parse_param(param)?;
+        db.create_alert(rule)
+    }
+}
 
+struct DropAlertAction;
+impl Method for DropAlertAction {
+    fn do_method(&self, param: &[u8], db: &CacheDb, _out: &mut BytesMut) -> Result<(), Exception> {
+        let name: String = parse_param(param)?;
+        db.drop_alert(name.as_str())
+    }
+}
 
+struct ListAlertsAction;
+impl Method for ListAlertsAction {
+    fn do_method(&self, _param: &[u8], db: &CacheDb, out: &mut BytesMut) -> Result<(), Exception> {
+        out.put_slice(to_vec_named(&db.alerts().status()).unwrap().as_slice());
+        Ok(())
+    }
+}
 
+// Notifier
+struct CreateNotifierAction;
+impl Method for CreateNotifierAction {
+    fn do_method(&self, param: &[u8], db: &CacheDb, _out: &mut BytesMut) -> Result<(), Exception> {
+        let notifier: NotifierConfig = parse_param(param)?;
+        db.create_notifier(notifier)
+    }
+}
 
+struct DropNotifierAction;
+impl Method for DropNotifierAction {
+    fn do_method(&self, param: &[u8], db: &CacheDb, _out: &mut BytesMut) -> Result<(), Exception> {
+        let name: String = parse_param(param)?;
+        db.drop_notifier(name.as_str())
+    }
+}
 
+struct ListNotifiersAction;
+impl Method for ListNotifiersAction {
+    fn do_method(&self, _param: &[u8], db: &CacheDb, out: &mut BytesMut) -> Result<(), Exception> {
+        out.put_slice(to_vec_named(&db.notifiers()).unwrap().as_slice());
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use crate::alert::{AlertEvent, AlertState};
+use crate::db::CacheDb;
+use crate::method::{Exception, ExceptionKind};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum NotifierKind {
+    Http { url: String },
+    Command { program: String, args: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NotifierConfig {
+    pub name: String,
+    pub kind: NotifierKind,
+    pub retries: u32,
+    pub backoff_ms: u64,
+    // identical events for the same rule within this window are dropped
+    pub dedup_secs: u64,
+}
+
+pub trait Notifier: Send + Sync {
+    fn notify(&self, event: &AlertEvent) -> Result<(), Exception>;
+}
+
+pub fn build(kind: &NotifierKind) -> Result<Box<dyn Notifier>, Exception> {
+    match kind {
+        NotifierKind::Http { url } => Ok(Box::new(HttpNotifier::new(url)?)),
+        NotifierKind::Command { program, args } => Ok(Box::new(CommandNotifier::new(program, args))),
+    }
+}
+
+// POSTs the event as JSON, plain http only
+pub struct HttpNotifier {
+    host: String,
+    path: String,
+    timeout: Duration,
+}
+
+impl HttpNotifier {
+    pub fn new(url: &str) -> Result<HttpNotifier, Exception> {
+        let rest = match url.strip_prefix("http://") {
+            Some(rest) => rest,
+            None => return Err(Exception::err(ExceptionKind::NotifyError, format!("unsupported url:{}", url).as_str())),
+        };
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return Err(Exception::err(ExceptionKind::NotifyError, format!("missing host in url:{}", url).as_str()));
+        }
+        let host = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+        Ok(HttpNotifier { host, path: path.to_string(), timeout: Duration::from_secs(5) })
+    }
+
+    fn post(&self, body: &[u8]) -> std::io::Result<u16> {
+        let addr = self.host.to_socket_addrs()?.next()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no address"))?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path, self.host, body.len()
+        );
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(body)?;
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+        let line = String::from_utf8_lossy(&response);
+        line.split_whitespace().nth(1).and_then(|code| code.parse().ok())
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "bad http response"))
+    }
+}
+
+impl Notifier for HttpNotifier {
+    fn notify(&self, event: &AlertEvent) -> Result<(), Exception> {
+        let body = serde_json::to_vec(event).unwrap();
+        match self.post(&body) {
+            Ok(code) if (200..300).contains(&code) => Ok(()),
+            Ok(code) => Err(Exception::err(ExceptionKind::NotifyError, format!("http status {} from {}", code, self.host).as_str())),
+            Err(e) => Err(Exception::err(ExceptionKind::NotifyError, format!("post to {} error:{}", self.host, e).as_str())),
+        }
+    }
+}
+
+// runs a local command with the event as JSON on stdin
+pub struct CommandNotifier {
+    program: String,
+    args: Vec<String>,
+}
+
+impl CommandNotifier {
+    pub fn new(program: &str, args: &[String]) -> CommandNotifier {
+        CommandNotifier { program: program.to_string(), args: args.to_vec() }
+    }
+}
+
+impl Notifier for CommandNotifier {
+    fn notify(&self, event: &AlertEvent) -> Result<(), Exception> {
+        let mut child = match Command::new(&self.program).args(&self.args)
+            .stdin(Stdio::piped()).stdout(Stdio::null()).spawn() {
+            Ok(child) => child,
+            Err(e) => return Err(Exception::err(ExceptionKind::NotifyError, format!("spawn {} error:{}", self.program, e).as_str())),
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(&serde_json::to_vec(event).unwrap());
+        }
+        match child.wait() {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(Exception::err(ExceptionKind::NotifyError, format!("{} exited with {}", self.program, status).as_str())),
+            Err(e) => Err(Exception::err(ExceptionKind::NotifyError, format!("wait {} error:{}", self.program, e).as_str())),
+        }
+    }
+}
+
+// retries with exponential backoff, returns the last error
+pub fn deliver(notifier: &dyn Notifier, event: &AlertEvent, retries: u32, backoff_ms: u64) -> Result<(), Exception> {
+    let mut attempt = 0;
+    loop {
+        match notifier.notify(event) {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt >= retries => return Err(e),
+            Err(e) => {
+                info!("notify {} attempt {} failed: {}", event.rule, attempt + 1, e.msg);
+                thread::sleep(Duration::from_millis(backoff_ms.saturating_mul(1 << attempt.min(16))));
+                attempt += 1;
+            }
+        }
+    }
+}
+
+// a rule firing again within `dedup_secs` of its last delivered Firing is not delivered, nor is the
+// Inactive that ends it, so a flapping rule notifies once per window
+#[derive(Default)]
+pub struct Dedup {
+    // per notifier and rule: when the last Firing went out and whether its Inactive is still owed
+    delivered: HashMap<(String, String), (Instant, bool)>,
+}
+
+impl Dedup {
+    pub fn admit(&mut self, notifier: &NotifierConfig, event: &AlertEvent) -> bool {
+        let key = (notifier.name.clone(), event.rule.clone());
+        let now = Instant::now();
+        match event.state {
+            AlertState::Firing => match self.delivered.get_mut(&key) {
+                Some((at, owed)) if now.duration_since(*at) < Duration::from_secs(notifier.dedup_secs) => {
+                    *owed = false;
+                    false
+                }
+                _ => {
+                    self.delivered.insert(key, (now, true));
+                    true
+                }
+            },
+            _ => match self.delivered.get_mut(&key) {
+                Some((_, owed)) => std::mem::replace(owed, false),
+                None => true,
+            },
+        }
+    }
+}
+
+pub async fn run_dispatcher(db: Arc<CacheDb>) {
+    let mut receiver = db.alerts().subscribe();
+    let mut dedup = Dedup::default();
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                info!("notifier lagged {} events", n);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        let configs = db.notifiers();
+        for config in configs {
+            if !dedup.admit(&config, &event) {
+                continue;
+            }
+            let event = event.clone();
+            tokio::task::spawn_blocking(move || {
+                let result = build(&config.kind)
+                    .and_then(|notifier| deliver(notifier.as_ref(), &event, config.retries, config.backoff_ms));
+                if let Err(e) = result {
+                    info!("notifier {} gave up on {}: {}", config.name, event.rule, e.msg);
+                }
+            });
+        }
+    }
+}
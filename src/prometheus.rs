@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+use prost::Message;
+use regex::Regex;
+use crate::config;
+use crate::db::CacheDb;
+use crate::entity::{DataType, Precision, TSItem, TSValue};
+use crate::ingest::{self, WriteResult};
+use crate::method::{Exception, ExceptionKind};
+
+// the subset of prometheus/prompb used by remote storage, unknown fields are skipped when decoding
+#[derive(Clone, PartialEq, Message)]
+pub struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    pub labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Label {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Sample {
+    #[prost(double, tag = "1")]
+    pub value: f64,
+    #[prost(int64, tag = "2")]
+    pub timestamp: i64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ReadRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub queries: Vec<Query>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Query {
+    #[prost(int64, tag = "1")]
+    pub start_timestamp_ms: i64,
+    #[prost(int64, tag = "2")]
+    pub end_timestamp_ms: i64,
+    #[prost(message, repeated, tag = "3")]
+    pub matchers: Vec<LabelMatcher>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum MatchType {
+    Eq = 0,
+    Neq = 1,
+    Re = 2,
+    Nre = 3,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct LabelMatcher {
+    #[prost(enumeration = "MatchType", tag = "1")]
+    pub r#type: i32,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(string, tag = "3")]
+    pub value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ReadResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub results: Vec<QueryResult>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct QueryResult {
+    #[prost(message, repeated, tag = "1")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+const METRIC_NAME: &str = "__name__";
+
+// `metric;label=value;...` with the labels in name order, the same shape line protocol series get
+pub fn series_name(labels: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut name = match labels.get(METRIC_NAME) {
+        Some(metric) if !metric.is_empty() => metric.clone(),
+        _ => return Err("series without __name__ label".to_string()),
+    };
+    labels.iter().filter(|(key, _)| *key != METRIC_NAME).for_each(|(key, value)| name.push_str(&format!(";{}={}", key, value)));
+    Ok(name)
+}
+
+// the labels a stored series answers matchers with, parsed back from the name and merged with its own
+pub fn label_set(item: &TSItem) -> BTreeMap<String, String> {
+    let mut parts = item.tsName.split(';');
+    let mut labels = BTreeMap::new();
+    labels.insert(METRIC_NAME.to_string(), parts.next().unwrap_or_default().to_string());
+    for part in parts {
+        if let Some((key, value)) = part.split_once('=') {
+            labels.insert(key.to_string(), value.to_string());
+        }
+    }
+    item.labels.iter().for_each(|(key, value)| { labels.entry(key.clone()).or_insert_with(|| value.clone()); });
+    labels
+}
+
+fn decompress(body: &[u8]) -> Result<Vec<u8>, String> {
+    let max = config::get().limits.max_frame_size;
+    let length = snap::raw::decompress_len(body).map_err(|e| format!("bad snappy block: {}", e))?;
+    if length > max {
+        return Err(format!("decompressed body of {} bytes exceeds {}", length, max));
+    }
+    snap::raw::Decoder::new().decompress_vec(body).map_err(|e| format!("bad snappy block: {}", e))
+}
+
+pub fn compress(body: &[u8]) -> Vec<u8> {
+    snap::raw::Encoder::new().compress_vec(body).unwrap()
+}
+
+// a snappy compressed WriteRequest, Err when the body cannot be decoded at all
+pub fn write(db: &CacheDb, body: &[u8]) -> Result<WriteResult, String> {
+    let request = WriteRequest::decode(decompress(body)?.as_slice()).map_err(|e| format!("bad WriteRequest: {}", e))?;
+    let auto_create = config::get().prometheus.auto_create;
+    let mut result = WriteResult::default();
+    for series in &request.timeseries {
+        let labels: BTreeMap<String, String> = series.labels.iter().map(|it| (it.name.clone(), it.value.clone())).collect();
+        let name = match series_name(&labels) {
+            Ok(name) => name,
+            Err(e) => {
+                result.errors.push(e);
+                continue;
+            }
+        };
+        let without_name = || labels.iter().filter(|(key, _)| *key != METRIC_NAME).map(|(key, value)| (key.clone(), value.clone())).collect();
+        let stored = match ingest::series(db, &name, DataType::Double, without_name, auto_create) {
+            Ok(stored) => stored,
+            Err(e) => {
+                result.fail(&name, &e);
+                continue;
+            }
+        };
+        for sample in &series.samples {
+            let point = || -> Result<(), Exception> {
+                let time = u128::try_from(sample.timestamp)
+                    .map_err(|_| Exception::err(ExceptionKind::TimeSerieError, format!("negative timestamp {}", sample.timestamp).as_str()))?;
+                let key = Precision::Millis.convert(time, stored.item.precision);
+                let value = ingest::number(sample.value, &stored.item.datatype)
+                    .ok_or_else(|| Exception::err(ExceptionKind::SaveTypeError, format!("except type:{:?},but input value:{}", stored.item.datatype, sample.value).as_str()))?;
+                db.set(TSValue { name: name.clone(), key, value }).map(|_| ())
+            };
+            match point() {
+                Ok(()) => result.points += 1,
+                Err(e) => result.fail(&name, &e),
+            }
+        }
+    }
+    Ok(result)
+}
+
+struct Matcher {
+    kind: MatchType,
+    name: String,
+    value: String,
+    regex: Option<Regex>,
+}
+
+impl Matcher {
+    fn new(matcher: &LabelMatcher) -> Result<Matcher, String> {
+        let kind = MatchType::try_from(matcher.r#type).map_err(|_| format!("unknown matcher type {}", matcher.r#type))?;
+        // Prometheus regexes match the whole value
+        let regex = match kind {
+            MatchType::Re | MatchType::Nre => Some(Regex::new(&format!("^(?:{})$", matcher.value)).map_err(|e| format!("bad regex: {}", e))?),
+            _ => None,
+        };
+        Ok(Matcher { kind, name: matcher.name.clone(), value: matcher.value.clone(), regex })
+    }
+
+    // a missing label matches as the empty string
+    fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        let value = labels.get(&self.name).map(|it| it.as_str()).unwrap_or("");
+        match (self.kind, &self.regex) {
+            (MatchType::Eq, _) => value == self.value,
+            (MatchType::Neq, _) => value != self.value,
+            (MatchType::Re, Some(regex)) => regex.is_match(value),
+            (MatchType::Nre, Some(regex)) => !regex.is_match(value),
+            _ => false,
+        }
+    }
+}
+
+// a snappy compressed ReadRequest answered with a snappy compressed ReadResponse,
+// series holding strings or bytes never match
+pub fn read(db: &CacheDb, body: &[u8]) -> Result<Vec<u8>, String> {
+    let request = ReadRequest::decode(decompress(body)?.as_slice()).map_err(|e| format!("bad ReadRequest: {}", e))?;
+    let mut response = ReadResponse::default();
+    for query in &request.queries {
+        let matchers = query.matchers.iter().map(Matcher::new).collect::<Result<Vec<_>, _>>()?;
+        let start = query.start_timestamp_ms.max(0) as u128;
+        let end = query.end_timestamp_ms.max(0) as u128;
+        let mut items = db.items();
+        items.sort_by(|a, b| a.tsName.cmp(&b.tsName));
+        let mut result = QueryResult::default();
+        for item in items {
+            if matches!(item.datatype, DataType::String | DataType::ByteArray) {
+                continue;
+            }
+            let labels = label_set(&item);
+            if !matchers.iter().all(|it| it.matches(&labels)) {
+                continue;
+            }
+            let samples: Vec<Sample> = match db.range_as(&item.tsName, start, end, Precision::Millis) {
+                Ok(points) => points.iter()
+                    .filter_map(|point| point.value.as_f64().map(|value| Sample { value, timestamp: point.key as i64 }))
+                    .collect(),
+                // dropped since the catalog was read
+                Err(_) => continue,
+            };
+            if samples.is_empty() {
+                continue;
+            }
+            let labels = labels.into_iter().map(|(name, value)| Label { name, value }).collect();
+            result.timeseries.push(TimeSeries { labels, samples });
+        }
+        response.results.push(result);
+    }
+    Ok(compress(&response.encode_to_vec()))
+}
+
+// series names fully matched by any include pattern, every name when there is none, and by no exclude pattern
+pub struct Selector {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl Selector {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Selector, String> {
+        let compile = |patterns: &[String]| patterns.iter()
+            .map(|it| Regex::new(&format!("^(?:{})$", it)).map_err(|e| format!("bad pattern `{}`: {}", it, e)))
+            .collect::<Result<Vec<_>, _>>();
+        Ok(Selector { include: compile(include)?, exclude: compile(exclude)? })
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|it| it.is_match(name))) && !self.exclude.iter().any(|it| it.is_match(name))
+    }
+}
+
+// invalid characters become `_`, `colon` allows the `:` metric names may hold
+fn sanitize(name: &str, colon: bool) -> String {
+    let mut out: String = name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' || (colon && c == ':') { c } else { '_' }).collect();
+    if out.is_empty() || out.starts_with(|c: char| c.is_ascii_digit()) {
+        out.insert(0, '_');
+    }
+    out
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf".to_string() } else { "-Inf".to_string() }
+    } else {
+        value.to_string()
+    }
+}
+
+// the newest point of every selected numeric series as a gauge in the text exposition format,
+// grouped by metric name and timestamped with its key
+pub fn exposition(db: &CacheDb, selector: &Selector) -> String {
+    let mut samples = vec![];
+    for item in db.items() {
+        if !selector.matches(&item.tsName) {
+            continue;
+        }
+        let Some(series) = db.get(&item.tsName) else { continue };
+        let queue = series.queue();
+        let Some((key, value)) = queue.query_time(u128::MAX) else { continue };
+        let Some(value) = value.as_f64() else { continue };
+        let labels = label_set(&item);
+        let metric = sanitize(&labels[METRIC_NAME], true);
+        let labels: Vec<String> = labels.iter()
+            .filter(|(name, _)| *name != METRIC_NAME)
+            .map(|(name, value)| format!("{}=\"{}\"", sanitize(name, false), escape(value)))
+            .collect();
+        samples.push((metric, labels.join(","), value, item.precision.to_millis(key)));
+    }
+    samples.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+    let mut out = String::new();
+    let mut family = None;
+    for (metric, labels, value, key) in &samples {
+        if family != Some(metric) {
+            out.push_str(&format!("# TYPE {} gauge\n", metric));
+            family = Some(metric);
+        }
+        let labels = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        out.push_str(&format!("{}{} {} {}\n", metric, labels, format_value(*value), key));
+    }
+    out
+}
@@ -0,0 +1,485 @@
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::sync::Arc;
+use bytes::{Buf, BytesMut};
+use log::info;
+use mini_redis::frame::{Error as FrameError, Frame};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinSet;
+use crate::config;
+use crate::db::CacheDb;
+use crate::entity::{Compression, DataType, Precision, SaveTimePeriod, TSCacheValue, TSItem, TSValue};
+use crate::handle::{after, expire};
+use crate::ingest;
+use crate::method::{Exception, ExceptionKind};
+
+type Db = Arc<CacheDb>;
+
+// mini-redis cannot write nested arrays or negative integers, replies are encoded here
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i128),
+    Bulk(Vec<u8>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn ok() -> Reply {
+        Reply::Simple("OK".to_string())
+    }
+
+    fn err(message: &str) -> Reply {
+        Reply::Error(format!("ERR {}", message))
+    }
+
+    pub fn encode(&self, buff: &mut Vec<u8>) {
+        match self {
+            Reply::Simple(text) => buff.extend_from_slice(format!("+{}\r\n", text).as_bytes()),
+            Reply::Error(text) => buff.extend_from_slice(format!("-{}\r\n", text.replace(['\r', '\n'], " ")).as_bytes()),
+            Reply::Integer(value) => buff.extend_from_slice(format!(":{}\r\n", value).as_bytes()),
+            Reply::Bulk(bytes) => {
+                buff.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+                buff.extend_from_slice(bytes);
+                buff.extend_from_slice(b"\r\n");
+            }
+            Reply::Array(items) => {
+                buff.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                items.iter().for_each(|it| it.encode(buff));
+            }
+        }
+    }
+}
+
+impl From<Exception> for Reply {
+    fn from(e: Exception) -> Reply {
+        match e.kind() {
+            Some(ExceptionKind::BackpressureError) => Reply::Error(format!("BUSY {}", e.msg)),
+            _ => Reply::err(&format!("TSDB: {}", e.msg)),
+        }
+    }
+}
+
+// the RESP listener shares the connection limit with the binary protocol
+pub async fn serve(listener: TcpListener, db: Db, connections: Arc<Semaphore>, mut shutdown: watch::Receiver<bool>) {
+    let mut tasks = JoinSet::new();
+    loop {
+        let permit = tokio::select! {
+            permit = connections.clone().acquire_owned() => permit.unwrap(),
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        };
+        let socket = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((socket, _)) => socket,
+                Err(e) => {
+                    info!("resp accept error: {:?}", e);
+                    continue;
+                }
+            },
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        };
+        while tasks.try_join_next().is_some() {}
+        let db = db.clone();
+        let shutdown = shutdown.clone();
+        tasks.spawn(async move {
+            let _permit = permit;
+            serve_connection(socket, db, shutdown).await;
+        });
+    }
+    // the connections stop on the same signal, the caller closes the database after they are done
+    while tasks.join_next().await.is_some() {}
+}
+
+// pipelined commands are answered in order, one write per batch read from the socket
+pub async fn serve_connection(mut socket: TcpStream, db: Db, mut shutdown: watch::Receiver<bool>) {
+    let conf = config::get();
+    let mut buff = BytesMut::with_capacity(4096);
+    loop {
+        let mut out = vec![];
+        let mut quit = false;
+        loop {
+            let frame = match next_frame(&mut buff, conf.limits.max_frame_size) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(message) => {
+                    Reply::err(&format!("Protocol error: {}", message)).encode(&mut out);
+                    let _ = socket.write_all(&out).await;
+                    return;
+                }
+            };
+            let (reply, close) = match command(frame) {
+                Ok(args) if is_quit(&args) => (Reply::ok(), true),
+                Ok(args) => (execute(&args, &db), false),
+                Err(reply) => (reply, false),
+            };
+            reply.encode(&mut out);
+            if close {
+                quit = true;
+                break;
+            }
+        }
+        if !out.is_empty() && socket.write_all(&out).await.is_err() {
+            return;
+        }
+        if quit {
+            return;
+        }
+        let started = !buff.is_empty();
+        let deadline = if started { after(conf.read_timeout_secs) } else { after(conf.idle_timeout_secs) };
+        let read = tokio::select! {
+            read = socket.read_buf(&mut buff) => Some(read),
+            _ = expire(deadline) => None,
+            _ = shutdown.wait_for(|stop| *stop), if !started => return,
+        };
+        match read {
+            Some(Ok(0)) | Some(Err(_)) => return,
+            Some(Ok(_)) => {}
+            None => {
+                if started {
+                    let mut out = vec![];
+                    Reply::err("Protocol error: timed out reading command").encode(&mut out);
+                    let _ = socket.write_all(&out).await;
+                }
+                return;
+            }
+        }
+    }
+}
+
+// takes one complete frame off `buff`, Ok(None) means more bytes are needed
+fn next_frame(buff: &mut BytesMut, max_size: usize) -> Result<Option<Frame>, String> {
+    let mut cursor = Cursor::new(&buff[..]);
+    match Frame::check(&mut cursor) {
+        Ok(()) => {}
+        Err(FrameError::Incomplete) if buff.len() > max_size => return Err(format!("command exceeds {} bytes", max_size)),
+        Err(FrameError::Incomplete) => return Ok(None),
+        Err(FrameError::Other(e)) => return Err(e.to_string()),
+    }
+    let length = cursor.position() as usize;
+    cursor.set_position(0);
+    let frame = Frame::parse(&mut cursor).map_err(|e| e.to_string())?;
+    buff.advance(length);
+    Ok(Some(frame))
+}
+
+// a command is an array of bulk strings, the name is case insensitive
+fn command(frame: Frame) -> Result<Vec<Vec<u8>>, Reply> {
+    let items = match frame {
+        Frame::Array(items) if !items.is_empty() => items,
+        _ => return Err(Reply::err("Protocol error: expected an array of bulk strings")),
+    };
+    items.into_iter().map(|it| match it {
+        Frame::Bulk(bytes) => Ok(bytes.to_vec()),
+        Frame::Simple(text) => Ok(text.into_bytes()),
+        Frame::Integer(value) => Ok(value.to_string().into_bytes()),
+        _ => Err(Reply::err("Protocol error: expected an array of bulk strings")),
+    }).collect()
+}
+
+fn is_quit(args: &[Vec<u8>]) -> bool {
+    args[0].eq_ignore_ascii_case(b"QUIT")
+}
+
+pub fn execute(args: &[Vec<u8>], db: &Db) -> Reply {
+    let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+    let args = &args[1..];
+    let reply = match name.as_str() {
+        "PING" => Ok(match args.first() {
+            Some(message) => Reply::Bulk(message.clone()),
+            None => Reply::Simple("PONG".to_string()),
+        }),
+        "ECHO" if args.len() == 1 => Ok(Reply::Bulk(args[0].clone())),
+        // redis-cli asks for the command table on connect
+        "COMMAND" => Ok(Reply::Array(vec![])),
+        "SELECT" if args.len() == 1 && args[0] == b"0" => Ok(Reply::ok()),
+        "TS.CREATE" => ts_create(args, db),
+        "TS.ADD" => ts_add(args, db),
+        "TS.MADD" => ts_madd(args, db),
+        "TS.GET" => ts_get(args, db),
+        "TS.RANGE" => ts_range(args, db),
+        "TS.INFO" => ts_info(args, db),
+        "ECHO" | "SELECT" => Err(Reply::err(&format!("wrong number of arguments for '{}' command", name.to_lowercase()))),
+        _ => Err(Reply::err(&format!("unknown command '{}'", name))),
+    };
+    reply.unwrap_or_else(|reply| reply)
+}
+
+fn text(arg: &[u8]) -> Result<&str, Reply> {
+    std::str::from_utf8(arg).map_err(|_| Reply::err("TSDB: argument is not valid utf-8"))
+}
+
+fn number<T: std::str::FromStr>(arg: &[u8], what: &str) -> Result<T, Reply> {
+    text(arg)?.parse().map_err(|_| Reply::err(&format!("TSDB: invalid {}", what)))
+}
+
+fn arity(args: &[Vec<u8>], min: usize, command: &str) -> Result<(), Reply> {
+    if args.len() < min {
+        return Err(Reply::err(&format!("wrong number of arguments for '{}' command", command)));
+    }
+    Ok(())
+}
+
+// `*` is the server clock in the series' precision
+fn timestamp(arg: &[u8], precision: Precision) -> Result<u128, Reply> {
+    if arg == b"*" {
+        return Ok(precision.now());
+    }
+    number(arg, "timestamp")
+}
+
+fn datatype(arg: &[u8]) -> Result<DataType, Reply> {
+    match text(arg)?.to_lowercase().as_str() {
+        "float" => Ok(DataType::Float),
+        "long" => Ok(DataType::Long),
+        "double" => Ok(DataType::Double),
+        "number" => Ok(DataType::Number),
+        "string" => Ok(DataType::String),
+        "bytearray" => Ok(DataType::ByteArray),
+        _ => Err(Reply::err(&format!("TSDB: unknown datatype {}", String::from_utf8_lossy(arg)))),
+    }
+}
+
+// values arrive as text and are read as the series' datatype
+fn parse_value(datatype: &DataType, arg: &[u8]) -> Result<TSCacheValue, Reply> {
+    Ok(match datatype {
+        DataType::Float => TSCacheValue::Float(number(arg, "value")?),
+        DataType::Long => TSCacheValue::Long(number(arg, "value")?),
+        DataType::Double => TSCacheValue::Double(number(arg, "value")?),
+        DataType::Number => TSCacheValue::Number(number(arg, "value")?),
+        DataType::String => TSCacheValue::String(text(arg)?.to_string()),
+        DataType::ByteArray => TSCacheValue::ByteArray(arg.to_vec()),
+    })
+}
+
+// numbers are sent as simple strings the way RedisTimeSeries does
+fn value_reply(value: &TSCacheValue) -> Reply {
+    match value {
+        TSCacheValue::Float(it) => Reply::Simple(it.to_string()),
+        TSCacheValue::Long(it) => Reply::Simple(it.to_string()),
+        TSCacheValue::Double(it) | TSCacheValue::Number(it) => Reply::Simple(it.to_string()),
+        TSCacheValue::String(it) => Reply::Bulk(it.as_bytes().to_vec()),
+        TSCacheValue::ByteArray(it) => Reply::Bulk(it.clone()),
+    }
+}
+
+fn sample(key: u128, value: &TSCacheValue) -> Reply {
+    Reply::Array(vec![Reply::Integer(key as i128), value_reply(value)])
+}
+
+fn not_exist(name: &[u8]) -> Reply {
+    Reply::err(&format!("TSDB: the key {} does not exist", String::from_utf8_lossy(name)))
+}
+
+// TS.CREATE key [CAPACITY n] [DATATYPE type] [SAVETIME period] [PRECISION unit] [COMPRESSION none|lz4|zstd]
+//     [RETENTION ms] [LABELS label value ...]
+// LABELS take the rest of the arguments as pairs,
+// RETENTION, ENCODING, CHUNK_SIZE and DUPLICATE_POLICY are accepted for compatibility and ignored,
+// a series keeps its newest `capacity` points
+fn ts_create(args: &[Vec<u8>], db: &Db) -> Result<Reply, Reply> {
+    arity(args, 1, "ts.create")?;
+    let mut item = TSItem {
+        tsName: text(&args[0])?.to_string(),
+        capacity: config::default_capacity(),
+        datatype: DataType::Double,
+        saveTime: config::default_save_time(),
+        labels: BTreeMap::new(),
+        precision: Precision::Millis,
+        compression: Compression::None,
+    };
+    let mut i = 1;
+    while i < args.len() {
+        let option = text(&args[i])?.to_uppercase();
+        if option == "LABELS" {
+            let pairs = &args[i + 1..];
+            if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+                return Err(Reply::err("TSDB: LABELS needs label value pairs"));
+            }
+            for pair in pairs.chunks(2) {
+                item.labels.insert(text(&pair[0])?.to_string(), text(&pair[1])?.to_string());
+            }
+            break;
+        }
+        let value = args.get(i + 1).ok_or_else(|| Reply::err(&format!("TSDB: missing value for {}", option)))?;
+        match option.as_str() {
+            "CAPACITY" => item.capacity = number(value, "CAPACITY")?,
+            "DATATYPE" => item.datatype = datatype(value)?,
+            "SAVETIME" => item.saveTime = text(value)?.parse::<SaveTimePeriod>().map_err(|e| Reply::err(&format!("TSDB: {}", e)))?,
+            "PRECISION" => item.precision = text(value)?.parse::<Precision>().map_err(|e| Reply::err(&format!("TSDB: {}", e)))?,
+            "COMPRESSION" => item.compression = text(value)?.parse::<Compression>().map_err(|e| Reply::err(&format!("TSDB: {}", e)))?,
+            "RETENTION" | "ENCODING" | "CHUNK_SIZE" | "DUPLICATE_POLICY" => {}
+            _ => return Err(Reply::err(&format!("TSDB: unknown option {}", option))),
+        }
+        i += 2;
+    }
+    db.create(item)?;
+    Ok(Reply::ok())
+}
+
+// TS.ADD key timestamp|* value, replies with the timestamp stored
+fn ts_add(args: &[Vec<u8>], db: &Db) -> Result<Reply, Reply> {
+    if args.len() != 3 {
+        return Err(Reply::err("wrong number of arguments for 'ts.add' command"));
+    }
+    add(&args[0], &args[1], &args[2], db)
+}
+
+fn add(name: &[u8], key: &[u8], value: &[u8], db: &Db) -> Result<Reply, Reply> {
+    let series = match db.get(text(name)?) {
+        Some(series) => series,
+        // created under the auto_create policy, a Double series for numbers as in RedisTimeSeries, else a String one
+        None if config::get().auto_create.allows(text(name)?) => {
+            let datatype = if number::<f64>(value, "value").is_ok() { DataType::Double } else { DataType::String };
+            ingest::series(db, text(name)?, datatype, BTreeMap::new, true)?
+        }
+        None => return Err(not_exist(name)),
+    };
+    // 0 lets the server stamp the point, after the newest key even when the clock is behind
+    let key = if key == b"*" { 0 } else { number(key, "timestamp")? };
+    let value = parse_value(&series.item.datatype, value)?;
+    let key = db.set(TSValue { name: series.item.tsName.clone(), key, value })?;
+    Ok(Reply::Integer(key as i128))
+}
+
+// TS.MADD key timestamp value [key timestamp value ...], every sample succeeds or fails on its own
+fn ts_madd(args: &[Vec<u8>], db: &Db) -> Result<Reply, Reply> {
+    if args.is_empty() || !args.len().is_multiple_of(3) {
+        return Err(Reply::err("wrong number of arguments for 'ts.madd' command"));
+    }
+    Ok(Reply::Array(args.chunks(3).map(|it| add(&it[0], &it[1], &it[2], db).unwrap_or_else(|reply| reply)).collect()))
+}
+
+// TS.GET key, an empty array while the series has no points
+fn ts_get(args: &[Vec<u8>], db: &Db) -> Result<Reply, Reply> {
+    if args.len() != 1 {
+        return Err(Reply::err("wrong number of arguments for 'ts.get' command"));
+    }
+    let series = db.get(text(&args[0])?).ok_or_else(|| not_exist(&args[0]))?;
+    let queue = series.queue();
+    Ok(match queue.query_time(u128::MAX) {
+        Some((key, value)) => sample(key, &value),
+        None => Reply::Array(vec![]),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Aggregation {
+    Avg,
+    Sum,
+    Min,
+    Max,
+    Range,
+    Count,
+    First,
+    Last,
+}
+
+impl Aggregation {
+    fn parse(arg: &[u8]) -> Result<Aggregation, Reply> {
+        match text(arg)?.to_lowercase().as_str() {
+            "avg" => Ok(Aggregation::Avg),
+            "sum" => Ok(Aggregation::Sum),
+            "min" => Ok(Aggregation::Min),
+            "max" => Ok(Aggregation::Max),
+            "range" => Ok(Aggregation::Range),
+            "count" => Ok(Aggregation::Count),
+            "first" => Ok(Aggregation::First),
+            "last" => Ok(Aggregation::Last),
+            other => Err(Reply::err(&format!("TSDB: unknown aggregation {}", other))),
+        }
+    }
+
+    // one bucket, oldest first and never empty
+    fn apply(&self, points: &[(u128, TSCacheValue)]) -> Result<Reply, Reply> {
+        match self {
+            Aggregation::Count => return Ok(Reply::Simple(points.len().to_string())),
+            Aggregation::First => return Ok(value_reply(&points[0].1)),
+            Aggregation::Last => return Ok(value_reply(&points[points.len() - 1].1)),
+            _ => {}
+        }
+        let numbers: Vec<f64> = points.iter().map(|(_, value)| value.as_f64())
+            .collect::<Option<_>>()
+            .ok_or_else(|| Reply::err("TSDB: the aggregation needs a numeric series"))?;
+        let min = numbers.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max = numbers.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let sum: f64 = numbers.iter().sum();
+        let result = match self {
+            Aggregation::Avg => sum / numbers.len() as f64,
+            Aggregation::Sum => sum,
+            Aggregation::Min => min,
+            Aggregation::Max => max,
+            _ => max - min,
+        };
+        Ok(Reply::Simple(result.to_string()))
+    }
+}
+
+// TS.RANGE key from|- to|+ [COUNT n] [AGGREGATION avg|sum|min|max|range|count|first|last bucket]
+// aggregated samples are keyed by the start of their bucket
+fn ts_range(args: &[Vec<u8>], db: &Db) -> Result<Reply, Reply> {
+    arity(args, 3, "ts.range")?;
+    let series = db.get(text(&args[0])?).ok_or_else(|| not_exist(&args[0]))?;
+    let precision = series.item.precision;
+    let start = if args[1] == b"-" { 0 } else { timestamp(&args[1], precision)? };
+    let end = if args[2] == b"+" { u128::MAX } else { timestamp(&args[2], precision)? };
+    let mut count = usize::MAX;
+    let mut aggregation = None;
+    let mut i = 3;
+    while i < args.len() {
+        match text(&args[i])?.to_uppercase().as_str() {
+            "COUNT" if i + 1 < args.len() => {
+                count = number(&args[i + 1], "COUNT")?;
+                i += 2;
+            }
+            "AGGREGATION" if i + 2 < args.len() => {
+                let bucket: u128 = number(&args[i + 2], "bucket duration")?;
+                if bucket == 0 {
+                    return Err(Reply::err("TSDB: bucket duration must be greater than 0"));
+                }
+                aggregation = Some((Aggregation::parse(&args[i + 1])?, bucket));
+                i += 3;
+            }
+            other => return Err(Reply::err(&format!("TSDB: unknown or incomplete option {}", other))),
+        }
+    }
+    let points: Vec<(u128, TSCacheValue)> = db.range(&series.item.tsName, start, end)?.into_iter().map(|it| (it.key, it.value)).collect();
+    let (aggregation, bucket) = match aggregation {
+        Some(aggregation) => aggregation,
+        None => return Ok(Reply::Array(points.iter().take(count).map(|(key, value)| sample(*key, value)).collect())),
+    };
+    let mut samples = vec![];
+    for group in points.chunk_by(|a, b| a.0 / bucket == b.0 / bucket).take(count) {
+        let key = group[0].0 / bucket * bucket;
+        samples.push(Reply::Array(vec![Reply::Integer(key as i128), aggregation.apply(group)?]));
+    }
+    Ok(Reply::Array(samples))
+}
+
+// TS.INFO key, field names follow RedisTimeSeries where there is an equivalent
+fn ts_info(args: &[Vec<u8>], db: &Db) -> Result<Reply, Reply> {
+    if args.len() != 1 {
+        return Err(Reply::err("wrong number of arguments for 'ts.info' command"));
+    }
+    let series = db.get(text(&args[0])?).ok_or_else(|| not_exist(&args[0]))?;
+    let queue = series.queue();
+    let key = |key: Option<u128>| Reply::Integer(key.map_or(0, |key| key as i128));
+    let field = |name: &str| Reply::Simple(name.to_string());
+    Ok(Reply::Array(vec![
+        field("totalSamples"), Reply::Integer(queue.count() as i128),
+        field("memoryUsage"), Reply::Integer(queue.memory_usage() as i128),
+        field("compressionRatio"), Reply::Simple(format!("{:.2}", queue.compression_ratio())),
+        field("firstTimestamp"), key(queue.query_first().map(|(key, _)| key)),
+        field("lastTimestamp"), key(queue.last_key()),
+        field("retentionTime"), Reply::Integer(0),
+        field("capacity"), Reply::Integer(series.item.capacity as i128),
+        field("datatype"), Reply::Simple(format!("{:?}", series.item.datatype)),
+        field("saveTime"), Reply::Simple(format!("{:?}", series.item.saveTime)),
+        field("precision"), Reply::Simple(series.item.precision.to_string()),
+        field("compression"), Reply::Simple(series.item.compression.to_string()),
+        field("labels"), Reply::Array(series.item.labels.iter()
+            .map(|(name, value)| Reply::Array(vec![Reply::Bulk(name.as_bytes().to_vec()), Reply::Bulk(value.as_bytes().to_vec())]))
+            .collect()),
+    ]))
+}
@@ -0,0 +1,221 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use log::info;
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+use tokio::time::{interval_at, Instant};
+use crate::alert::now_millis;
+use crate::config;
+use crate::db::CacheDb;
+use crate::entity::TSCacheValue;
+use crate::ingest::{self, WriteResult};
+
+// largest datagram accepted by the listener
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sample {
+    // already divided by the sample rate
+    Counter(f64),
+    Gauge(f64),
+    // `+n` / `-n`, relative to the current value of the gauge
+    GaugeDelta(f64),
+    Timer { value: f64, rate: f64 },
+    Set(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub tags: BTreeMap<String, String>,
+    pub sample: Sample,
+}
+
+// `name:value|type[|@rate][|#tag:value,...]` with type c, g, ms, h, d or s
+pub fn parse_line(line: &str) -> Result<Metric, String> {
+    let (name, rest) = line.split_once(':').ok_or("missing value")?;
+    if name.is_empty() {
+        return Err("missing name".to_string());
+    }
+    let mut parts = rest.split('|');
+    let value = parts.next().unwrap_or_default();
+    let kind = parts.next().ok_or("missing type")?;
+    let mut rate = 1.0;
+    let mut tags = BTreeMap::new();
+    for part in parts {
+        if let Some(text) = part.strip_prefix('@') {
+            rate = match text.parse::<f64>() {
+                Ok(rate) if rate > 0.0 && rate <= 1.0 => rate,
+                _ => return Err(format!("invalid sample rate `{}`", text)),
+            };
+        } else if let Some(text) = part.strip_prefix('#') {
+            for tag in text.split(',').filter(|it| !it.is_empty()) {
+                match tag.split_once(':') {
+                    Some((key, value)) if !key.is_empty() && !value.is_empty() => tags.insert(key.to_string(), value.to_string()),
+                    _ => return Err(format!("invalid tag `{}`", tag)),
+                };
+            }
+        } else {
+            return Err(format!("unknown section `{}`", part));
+        }
+    }
+    let number = || match value.parse::<f64>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(format!("invalid value `{}`", value)),
+    };
+    let sample = match kind {
+        "c" => Sample::Counter(number()? / rate),
+        "g" if value.starts_with('+') || value.starts_with('-') => Sample::GaugeDelta(number()?),
+        "g" => Sample::Gauge(number()?),
+        "ms" | "h" | "d" => Sample::Timer { value: number()?, rate },
+        "s" => Sample::Set(value.to_string()),
+        _ => return Err(format!("unknown type `{}`", kind)),
+    };
+    Ok(Metric { name: name.to_string(), tags, sample })
+}
+
+type Key = (String, BTreeMap<String, String>);
+
+#[derive(Debug, Default)]
+struct Gauge {
+    // the last absolute value of the interval, otherwise the value already stored
+    value: Option<f64>,
+    delta: f64,
+}
+
+#[derive(Debug, Default)]
+struct Timer {
+    values: Vec<f64>,
+    count: f64,
+}
+
+// what arrived since the last flush
+#[derive(Debug, Default)]
+pub struct Aggregator {
+    counters: HashMap<Key, f64>,
+    gauges: HashMap<Key, Gauge>,
+    timers: HashMap<Key, Timer>,
+    sets: HashMap<Key, HashSet<String>>,
+}
+
+// `name.suffix` followed by `;tag=value` in tag order
+fn series_name(name: &str, suffix: Option<&str>, tags: &BTreeMap<String, String>) -> String {
+    let mut name = match suffix {
+        Some(suffix) => format!("{}.{}", name, suffix),
+        None => name.to_string(),
+    };
+    tags.iter().for_each(|(key, value)| name.push_str(&format!(";{}={}", key, value)));
+    name
+}
+
+// nearest rank of the sorted values
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn current(db: &CacheDb, name: &str) -> f64 {
+    match db.last(name) {
+        Ok(TSCacheValue::Double(it)) | Ok(TSCacheValue::Number(it)) => it,
+        Ok(TSCacheValue::Float(it)) => it as f64,
+        Ok(TSCacheValue::Long(it)) => it as f64,
+        _ => 0.0,
+    }
+}
+
+impl Aggregator {
+    pub fn is_empty(&self) -> bool {
+        self.counters.is_empty() && self.gauges.is_empty() && self.timers.is_empty() && self.sets.is_empty()
+    }
+
+    pub fn add(&mut self, metric: Metric) {
+        let key = (metric.name, metric.tags);
+        match metric.sample {
+            Sample::Counter(value) => *self.counters.entry(key).or_default() += value,
+            Sample::Gauge(value) => *self.gauges.entry(key).or_default() = Gauge { value: Some(value), delta: 0.0 },
+            Sample::GaugeDelta(delta) => self.gauges.entry(key).or_default().delta += delta,
+            Sample::Timer { value, rate } => {
+                let timer = self.timers.entry(key).or_default();
+                timer.values.push(value);
+                timer.count += 1.0 / rate;
+            }
+            Sample::Set(value) => {
+                self.sets.entry(key).or_default().insert(value);
+            }
+        }
+    }
+
+    // writes the interval with `key` and starts a new one: counters as name.count, gauges and
+    // the number of distinct set members as name, timers as name.count, name.sum and name.pNN
+    pub fn flush(&mut self, db: &CacheDb, key: u128, percentiles: &[f64], auto_create: bool) -> WriteResult {
+        let mut points = vec![];
+        for ((name, tags), count) in self.counters.drain() {
+            points.push((series_name(&name, Some("count"), &tags), count, tags));
+        }
+        for ((name, tags), gauge) in self.gauges.drain() {
+            let series = series_name(&name, None, &tags);
+            let value = gauge.value.unwrap_or_else(|| current(db, &series)) + gauge.delta;
+            points.push((series, value, tags));
+        }
+        for ((name, tags), mut timer) in self.timers.drain() {
+            timer.values.sort_by(f64::total_cmp);
+            points.push((series_name(&name, Some("count"), &tags), timer.count, tags.clone()));
+            points.push((series_name(&name, Some("sum"), &tags), timer.values.iter().sum(), tags.clone()));
+            for p in percentiles {
+                let suffix = format!("p{}", p).replace('.', "_");
+                points.push((series_name(&name, Some(&suffix), &tags), percentile(&timer.values, *p), tags.clone()));
+            }
+        }
+        for ((name, tags), members) in self.sets.drain() {
+            points.push((series_name(&name, None, &tags), members.len() as f64, tags));
+        }
+        let mut result = WriteResult::default();
+        for (series, value, tags) in points {
+            match ingest::set_number(db, &series, || tags, key, value, auto_create) {
+                Ok(()) => result.points += 1,
+                Err(e) => result.fail(&series, &e),
+            }
+        }
+        result
+    }
+}
+
+// newline separated metrics in each datagram, written once per flush interval and on shutdown
+pub async fn serve_udp(socket: UdpSocket, db: Arc<CacheDb>, mut shutdown: watch::Receiver<bool>) {
+    let conf = &config::get().statsd;
+    let period = Duration::from_secs(conf.flush_interval_secs);
+    let mut flush = interval_at(Instant::now() + period, period);
+    let mut aggregator = Aggregator::default();
+    let mut buff = vec![0u8; MAX_DATAGRAM];
+    loop {
+        let received = tokio::select! {
+            received = socket.recv_from(&mut buff) => received,
+            _ = flush.tick() => {
+                let result = aggregator.flush(&db, now_millis(), &conf.percentiles, conf.auto_create);
+                ingest::log_errors("statsd", "flush", &result);
+                continue;
+            }
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        };
+        let (length, peer) = match received {
+            Ok(received) => received,
+            Err(e) => {
+                info!("statsd udp error: {:?}", e);
+                continue;
+            }
+        };
+        let mut result = WriteResult::default();
+        for line in String::from_utf8_lossy(&buff[..length]).lines().map(str::trim).filter(|it| !it.is_empty()) {
+            match parse_line(line) {
+                Ok(metric) => aggregator.add(metric),
+                Err(e) => result.errors.push(format!("`{}`: {}", line, e)),
+            }
+        }
+        ingest::log_errors("statsd", &peer.to_string(), &result);
+    }
+    if !aggregator.is_empty() {
+        let result = aggregator.flush(&db, now_millis(), &conf.percentiles, conf.auto_create);
+        ingest::log_errors("statsd", "flush", &result);
+    }
+}
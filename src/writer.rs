@@ -0,0 +1,146 @@
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use lazy_static::lazy_static;
+use log::info;
+use crate::config;
+use crate::entity::{SaveTimePeriod, TSItem, TSValue};
+use crate::io::FileIOCache;
+use crate::method::{Exception, ExceptionKind};
+
+lazy_static!(
+    static ref POOL: WriterPool = WriterPool::new(config::get().writer_threads);
+);
+
+// writer threads doing the blocking file I/O, a series is drained by at most one thread at a time
+struct WriterPool {
+    sender: Mutex<Sender<Arc<SegmentWriter>>>,
+}
+
+impl WriterPool {
+    fn new(threads: usize) -> WriterPool {
+        let (sender, receiver) = channel::<Arc<SegmentWriter>>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..threads.max(1) {
+            let receiver: Arc<Mutex<Receiver<Arc<SegmentWriter>>>> = receiver.clone();
+            thread::Builder::new().name(format!("tc-writer-{}", i)).spawn(move || loop {
+                let writer = match receiver.lock().unwrap().recv() {
+                    Ok(writer) => writer,
+                    Err(_) => return,
+                };
+                writer.drain();
+            }).unwrap();
+        }
+        WriterPool { sender: Mutex::new(sender) }
+    }
+
+    fn schedule(&self, writer: Arc<SegmentWriter>) {
+        self.sender.lock().unwrap().send(writer).unwrap();
+    }
+}
+
+// bounded queue of points waiting to be appended to the series' segment
+pub struct SegmentWriter {
+    name: String,
+    persist: bool,
+    limit: usize,
+    io: Mutex<FileIOCache>,
+    pending: Mutex<Vec<TSValue>>,
+    scheduled: AtomicBool,
+    // the first write error, the series takes no more points once its segment could not be written
+    failed: OnceLock<String>,
+}
+
+impl SegmentWriter {
+    pub fn new(item: TSItem, limit: usize) -> Arc<SegmentWriter> {
+        Arc::new(SegmentWriter {
+            name: item.tsName.clone(),
+            persist: item.saveTime != SaveTimePeriod::Nerve,
+            limit,
+            io: Mutex::new(FileIOCache::new(Box::new(item))),
+            pending: Mutex::new(vec![]),
+            scheduled: AtomicBool::new(false),
+            failed: OnceLock::new(),
+        })
+    }
+
+    // fails when the disk falls behind so the client can back off and retry, or when it failed
+    pub fn check(&self) -> Result<(), Exception> {
+        if let Some(e) = self.failed.get() {
+            return Err(Exception::err(ExceptionKind::DiskError, format!("segment of {} cannot be written: {}", self.name, e).as_str()));
+        }
+        if self.persist && self.pending.lock().unwrap().len() >= self.limit {
+            return Err(Exception::err(ExceptionKind::BackpressureError, format!("write queue of {} is full, retry later", self.name).as_str()));
+        }
+        Ok(())
+    }
+
+    pub fn submit(self: &Arc<Self>, value: TSValue) {
+        if !self.persist {
+            return;
+        }
+        self.pending.lock().unwrap().push(value);
+        if !self.scheduled.swap(true, Ordering::AcqRel) {
+            POOL.schedule(self.clone());
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    // group commit: everything queued so far is written with a single flush
+    fn drain(&self) {
+        loop {
+            {
+                let mut io = self.io.lock().unwrap();
+                let batch = mem::take(&mut *self.pending.lock().unwrap());
+                if !batch.is_empty() {
+                    if let Err(e) = io.append(&batch).and_then(|_| io.flush()) {
+                        self.fail(&e);
+                    }
+                }
+            }
+            self.scheduled.store(false, Ordering::Release);
+            // a submit between taking the batch and clearing the flag did not reschedule
+            if self.pending.lock().unwrap().is_empty() || self.scheduled.swap(true, Ordering::AcqRel) {
+                return;
+            }
+        }
+    }
+
+    // runs `read` while no batch is being written, the segment on disk then holds whole blocks
+    pub fn read<T>(&self, read: impl FnOnce() -> T) -> T {
+        let _io = self.io.lock().unwrap();
+        read()
+    }
+
+    // writes whatever is still queued and fsyncs the segment, an error once the series failed
+    pub fn sync(&self) -> std::io::Result<()> {
+        let mut io = self.io.lock().unwrap();
+        let batch = mem::take(&mut *self.pending.lock().unwrap());
+        let result = io.append(&batch).and_then(|_| io.sync());
+        if let Err(ref e) = result {
+            self.fail(e);
+        }
+        // points a writer thread failed to write earlier are not on disk either
+        match self.failed.get() {
+            Some(e) if result.is_ok() => Err(std::io::Error::other(e.clone())),
+            _ => result,
+        }
+    }
+
+    fn fail(&self, e: &std::io::Error) {
+        info!("failed to write {}: {:?}", self.name, e);
+        let _ = self.failed.set(e.to_string());
+    }
+
+    // the last sync before exit, the segment is sealed since a restart starts a new one
+    pub fn close(&self) -> std::io::Result<()> {
+        self.sync()?;
+        self.io.lock().unwrap().seal();
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use time_cache::alert::{AlertCondition, AlertManager, AlertRule, AlertState};
+use time_cache::entity::TSCacheValue;
+use time_cache::alert;
+
+mod common;
+
+// events are appended to `alerts.log` in the data directory
+fn manager() -> AlertManager {
+    common::init();
+    AlertManager::new()
+}
+
+fn rule(name: &str, condition: AlertCondition, duration: u64) -> AlertRule {
+    AlertRule {
+        name: name.to_string(),
+        ts_name: "cpu".to_string(),
+        condition,
+        duration,
+    }
+}
+
+#[test]
+fn threshold_fires_after_duration_and_resolves() {
+    let mut manager = manager();
+    manager.add_rule(rule("cpu_high", AlertCondition::Above(90.0), 10)).unwrap();
+    let mut receiver = manager.subscribe();
+
+    let now = alert::now_millis();
+
+    // keys are not times, the `for` duration runs on the clock passed in and on the timer's
+    manager.on_value("cpu", 1, &TSCacheValue::Double(95.0), now);
+    manager.on_value("cpu", 2, &TSCacheValue::Double(96.0), now + 5_000);
+    manager.tick(now + 6_000);
+    assert!(receiver.try_recv().is_err());
+    assert_eq!(manager.status()[0].state, AlertState::Pending);
+
+    manager.on_value("cpu", 3, &TSCacheValue::Double(97.0), now + 11_000);
+    let event = receiver.try_recv().unwrap();
+    assert_eq!(event.state, AlertState::Firing);
+    assert_eq!((event.value, event.time), (Some(97.0), now + 11_000));
+
+    manager.on_value("cpu", 4, &TSCacheValue::Double(50.0), now + 12_000);
+    assert_eq!(receiver.try_recv().unwrap().state, AlertState::Inactive);
+    // other series do not touch the rule
+    manager.on_value("mem", 5, &TSCacheValue::Double(99.0), now + 13_000);
+    assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn rate_of_change_fires() {
+    let mut manager = manager();
+    manager.add_rule(rule("cpu_jump", AlertCondition::Rate { limit: 5.0, window: 10 }, 0)).unwrap();
+    let mut receiver = manager.subscribe();
+
+    manager.on_value("cpu", 1_000, &TSCacheValue::Long(10), 1_000);
+    manager.on_value("cpu", 3_000, &TSCacheValue::Long(12), 3_000);
+    assert!(receiver.try_recv().is_err());
+    manager.on_value("cpu", 4_000, &TSCacheValue::Long(40), 4_000);
+    assert_eq!(receiver.try_recv().unwrap().state, AlertState::Firing);
+}
+
+#[test]
+fn rate_ignores_out_of_order_keys() {
+    let mut manager = manager();
+    manager.add_rule(rule("cpu_jump", AlertCondition::Rate { limit: 5.0, window: 10 }, 0)).unwrap();
+    let mut receiver = manager.subscribe();
+
+    manager.on_value("cpu", 5_000, &TSCacheValue::Long(10), 5_000);
+    manager.on_value("cpu", 1_000, &TSCacheValue::Long(100), 1_000);
+    assert!(receiver.try_recv().is_err());
+    manager.on_value("cpu", 6_000, &TSCacheValue::Long(40), 6_000);
+    assert_eq!(receiver.try_recv().unwrap().state, AlertState::Firing);
+}
+
+#[test]
+fn absent_fires_on_timer() {
+    let mut manager = manager();
+    manager.add_rule(rule("cpu_absent", AlertCondition::Absent, 5)).unwrap();
+    let mut receiver = manager.subscribe();
+    let now = alert::now_millis();
+
+    manager.tick(now + 1_000);
+    assert!(receiver.try_recv().is_err());
+    manager.tick(now + 6_000);
+    assert_eq!(receiver.try_recv().unwrap().state, AlertState::Firing);
+
+    manager.on_value("cpu", now + 7_000, &TSCacheValue::Long(1), now + 7_000);
+    assert_eq!(receiver.try_recv().unwrap().state, AlertState::Inactive);
+}
+
+#[test]
+fn duplicate_rule_rejected() {
+    let mut manager = manager();
+    manager.add_rule(rule("cpu_high", AlertCondition::Above(1.0), 0)).unwrap();
+    let err = manager.add_rule(rule("cpu_high", AlertCondition::Below(1.0), 0)).unwrap_err();
+    assert_eq!(err.code, 4006);
+}
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+use bytes::BytesMut;
+use rmp_serde::to_vec_named;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::{watch, Semaphore};
+
+use time_cache::config::{AutoCreate, Template};
+use time_cache::method::{choose_method, Exception, ExceptionKind, MethodKind};
+use time_cache::{http, ingest, resp, CacheDb, Compression, Config, DataType, SaveTimePeriod, TSCacheValue, TSValue};
+
+mod common;
+
+// only names under the templates' prefixes are created, `sensor.room.` is the more specific one
+fn init() {
+    common::init_with(Config {
+        default_save_time: SaveTimePeriod::Nerve,
+        auto_create: AutoCreate {
+            enabled: false,
+            templates: vec![
+                Template { prefix: "sensor.".to_string(), capacity: Some(50), save_time: None, precision: None, compression: Some(Compression::Lz4) },
+                Template { prefix: "sensor.room.".to_string(), capacity: Some(5), save_time: Some(SaveTimePeriod::Nerve), precision: None, compression: None },
+            ],
+        },
+        ..Default::default()
+    });
+}
+
+fn call<T: serde::Serialize>(db: &CacheDb, kind: MethodKind, param: &T) -> Result<(), Exception> {
+    let method = choose_method(kind.as_code()).unwrap();
+    method.do_method(&to_vec_named(param).unwrap(), db, &mut BytesMut::new())
+}
+
+fn point(name: &str, key: u128, value: TSCacheValue) -> TSValue {
+    TSValue { name: name.to_string(), key, value }
+}
+
+#[test]
+fn set_creates_series_covered_by_a_template() {
+    init();
+    let db = CacheDb::new();
+    call(&db, MethodKind::Set, &point("sensor.room.a", 1, TSCacheValue::Long(21))).unwrap();
+    let series = db.get("sensor.room.a").unwrap();
+    assert_eq!((series.item.datatype.clone(), series.item.capacity), (DataType::Long, 5));
+    // unset in the closest template, not inherited from the shorter prefix
+    assert_eq!(series.item.compression, Compression::None);
+    assert_eq!(db.last("sensor.room.a").unwrap(), TSCacheValue::Long(21));
+
+    call(&db, MethodKind::SetBatch, &vec![
+        point("sensor.door", 1, TSCacheValue::String("open".to_string())),
+        point("sensor.door", 2, TSCacheValue::String("closed".to_string())),
+        point("sensor.hum", 1, TSCacheValue::Float(0.4)),
+    ]).unwrap();
+    let door = db.get("sensor.door").unwrap();
+    assert_eq!((door.item.datatype.clone(), door.item.capacity), (DataType::String, 50));
+    assert_eq!(door.item.compression, Compression::Lz4);
+    assert_eq!(db.get("sensor.hum").unwrap().item.datatype, DataType::Float);
+
+    // the first value decided the type
+    let e = call(&db, MethodKind::Set, &point("sensor.room.a", 2, TSCacheValue::Double(21.5))).unwrap_err();
+    assert_eq!(e.kind(), Some(ExceptionKind::SaveTypeError));
+}
+
+#[test]
+fn names_outside_the_policy_still_fail() {
+    init();
+    let db = CacheDb::new();
+    let e = call(&db, MethodKind::Set, &point("cpu", 1, TSCacheValue::Double(0.5))).unwrap_err();
+    assert_eq!(e.kind(), Some(ExceptionKind::TSNameExistsError));
+    assert!(db.get("cpu").is_none());
+
+    let e = call(&db, MethodKind::SetBatch, &vec![
+        point("sensor.x", 1, TSCacheValue::Double(1.0)),
+        point("mem", 1, TSCacheValue::Double(1.0)),
+    ]).unwrap_err();
+    assert!(e.msg.starts_with("point 1:"), "{}", e.msg);
+    assert!(db.get("sensor.x").is_some());
+
+    // the receivers' series take the template settings too
+    let created = ingest::series(&db, "sensor.room.b", DataType::Double, Default::default, true).unwrap();
+    assert_eq!(created.item.capacity, 5);
+}
+
+#[test]
+fn template_lookup_and_validation() {
+    let policy = AutoCreate { enabled: false, templates: vec![
+        Template { prefix: "a".to_string(), capacity: None, save_time: None, precision: None, compression: None },
+        Template { prefix: "a.b".to_string(), capacity: None, save_time: None, precision: None, compression: None },
+    ] };
+    assert_eq!(policy.template("a.b.c").unwrap().prefix, "a.b");
+    assert_eq!(policy.template("a.c").unwrap().prefix, "a");
+    assert!(!policy.allows("b"));
+    assert!(AutoCreate { enabled: true, templates: vec![] }.allows("b"));
+
+    let mut config = Config::parse(r#"
+        [auto_create]
+        enabled = true
+        [[auto_create.templates]]
+        prefix = "iot."
+        capacity = 0
+    "#).unwrap();
+    assert!(config.auto_create.enabled);
+    // validate creates the data dir
+    config.data_dir = common::data_dir();
+    assert!(config.validate().unwrap_err().contains("iot."));
+    config.auto_create.templates[0].capacity = Some(10);
+    config.validate().unwrap();
+}
+
+async fn exchange(serve: impl std::future::Future<Output = ()> + Send + 'static, addr: std::net::SocketAddr, raw: &[u8]) -> String {
+    tokio::spawn(serve);
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(raw).await.unwrap();
+    let mut buff = vec![0u8; 4096];
+    let n = stream.read(&mut buff).await.unwrap();
+    String::from_utf8_lossy(&buff[..n]).to_string()
+}
+
+#[tokio::test]
+async fn http_and_resp_writes_create_series() {
+    init();
+    let db = Arc::new(CacheDb::new());
+    let (_stop, shutdown) = watch::channel(false);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let body = r#"[{"key":1,"value":3},{"key":2,"value":4}]"#;
+    let raw = format!("POST /series/sensor.http/points HTTP/1.1\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+    let reply = exchange(http::serve(listener, db.clone(), Arc::new(Semaphore::new(4)), shutdown.clone()), addr, raw.as_bytes()).await;
+    assert!(reply.starts_with("HTTP/1.1 200"), "{}", reply);
+    assert_eq!(db.get("sensor.http").unwrap().item.datatype, DataType::Long);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let raw = "*4\r\n$6\r\nTS.ADD\r\n$11\r\nsensor.resp\r\n$1\r\n5\r\n$3\r\n1.5\r\n*4\r\n$6\r\nTS.ADD\r\n$3\r\ncpu\r\n$1\r\n5\r\n$3\r\n1.5\r\n";
+    let reply = exchange(resp::serve(listener, db.clone(), Arc::new(Semaphore::new(4)), shutdown), addr, raw.as_bytes()).await;
+    assert!(reply.starts_with(":5\r\n"), "{}", reply);
+    assert_eq!(db.get("sensor.resp").unwrap().item.datatype, DataType::Double);
+    assert_eq!(db.last("sensor.resp").unwrap(), TSCacheValue::Double(1.5));
+    // outside the policy TS.ADD still fails
+    assert!(db.get("cpu").is_none());
+}
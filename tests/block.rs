@@ -0,0 +1,94 @@
+use time_cache::block::{decode, Encoder};
+use time_cache::method::CHUNK_POINTS;
+use time_cache::{DataType, SaveTimePeriod, TSCacheValue, TSItem, TSQueue};
+
+fn round_trip(datatype: DataType, points: Vec<(u128, TSCacheValue)>) -> usize {
+    let mut encoder = Encoder::new(&datatype);
+    points.iter().for_each(|(key, value)| encoder.push(*key, value));
+    let block = encoder.finish();
+    let decoded = decode(&block).unwrap();
+    assert_eq!(decoded.len(), points.len());
+    for ((key, value), (expected_key, expected)) in decoded.iter().zip(&points) {
+        assert_eq!(key, expected_key);
+        // NaN is not equal to itself
+        match (value, expected) {
+            (TSCacheValue::Double(a), TSCacheValue::Double(b)) => assert_eq!(a.to_bits(), b.to_bits()),
+            (TSCacheValue::Float(a), TSCacheValue::Float(b)) => assert_eq!(a.to_bits(), b.to_bits()),
+            _ => assert_eq!(value, expected),
+        }
+    }
+    block.len()
+}
+
+#[test]
+fn every_datatype_round_trips() {
+    // irregular gaps hit every delta-of-delta bucket, including the varint fallback
+    let keys: Vec<u128> = [1u128, 2, 3, 10, 80, 400, 3000, 3001, 1 << 70, (1 << 70) + 1, u128::MAX - 5, u128::MAX].to_vec();
+    let doubles = [0.0, -0.0, 1.5, f64::NAN, f64::INFINITY, f64::MIN_POSITIVE, 1.5, 2.25, f64::MAX, -1e-300, 3.0, 3.0];
+    round_trip(DataType::Double, keys.iter().zip(doubles).map(|(key, it)| (*key, TSCacheValue::Double(it))).collect());
+    round_trip(DataType::Float, keys.iter().zip(doubles).map(|(key, it)| (*key, TSCacheValue::Float(it as f32))).collect());
+    round_trip(DataType::Number, keys.iter().zip(doubles).filter(|(_, it)| !it.is_nan()).map(|(key, it)| (*key, TSCacheValue::Number(it))).collect());
+    let longs = [0, -1, i64::MAX, i64::MIN, 7, 7, 8, -100, 1 << 40, 0, i64::MIN, i64::MAX];
+    round_trip(DataType::Long, keys.iter().zip(longs).map(|(key, it)| (*key, TSCacheValue::Long(it))).collect());
+    let texts = ["", "a", "ünïcode", "", "long text repeated long text repeated", "b", "", "c", "d", "e", "f", "g"];
+    round_trip(DataType::String, keys.iter().zip(texts).map(|(key, it)| (*key, TSCacheValue::String(it.to_string()))).collect());
+    round_trip(DataType::ByteArray, keys.iter().zip(texts).map(|(key, it)| (*key, TSCacheValue::ByteArray(it.as_bytes().to_vec()))).collect());
+    round_trip(DataType::Long, vec![]);
+}
+
+#[test]
+fn regular_series_pack_tightly() {
+    // one point a second in nanoseconds, a slowly moving gauge and a counter
+    let keys = (0..1000u128).map(|n| 1_700_000_000_000_000_000 + n * 1_000_000_000);
+    let gauge = round_trip(DataType::Double, keys.clone().map(|key| (key, TSCacheValue::Double(((key / 1_000_000_000) % 7) as f64 * 0.5))).collect());
+    let counter = round_trip(DataType::Long, keys.enumerate().map(|(n, key)| (key, TSCacheValue::Long(n as i64 * 3))).collect());
+    // 16 bytes a point uncompressed
+    assert!(gauge < 1000 * 16 / 6, "{}", gauge);
+    assert!(counter < 1000 * 16 / 10, "{}", counter);
+}
+
+#[test]
+fn broken_blocks_are_errors() {
+    let mut encoder = Encoder::new(&DataType::String);
+    encoder.push(1, &TSCacheValue::String("hello".to_string()));
+    encoder.push(2, &TSCacheValue::String("world".to_string()));
+    let block = encoder.finish();
+    for length in 0..block.len() {
+        assert!(decode(&block[..length]).is_err(), "{}", length);
+    }
+    assert!(decode(&[9, 0]).unwrap_err().contains("datatype"));
+    assert!(decode(&[2, 0xff, 0xff, 0xff, 0x7f]).is_err());
+}
+
+#[test]
+fn large_queues_seal_blocks_and_drop_the_oldest_points() {
+    let capacity = CHUNK_POINTS * 3 + 10;
+    let item = TSItem { tsName: "sealed".to_string(), capacity, datatype: DataType::Double, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision: Default::default(), compression: Default::default() };
+    let mut queue = TSQueue::new(Box::new(item), capacity);
+    let total = CHUNK_POINTS as u128 * 5 + 7;
+    for key in 1..=total {
+        queue.insert(key * 10, TSCacheValue::Double((key % 100) as f64)).unwrap();
+    }
+    assert_eq!(queue.count(), capacity);
+    let first = (total - capacity as u128 + 1) * 10;
+    assert_eq!(queue.query_first(), Some((first, TSCacheValue::Double(((first / 10) % 100) as f64))));
+    assert_eq!(queue.last_key(), Some(total * 10));
+    assert_eq!(queue.query_last(), Some(TSCacheValue::Double((total % 100) as f64)));
+
+    let all = queue.query_times(0, u128::MAX);
+    assert_eq!(all.len(), capacity);
+    assert!(all.windows(2).all(|it| it[1].0 == it[0].0 + 10));
+    // across a block boundary and into the chunk still filling
+    let boundary = queue.query_times(first + 10 * (CHUNK_POINTS as u128 - 2), first + 10 * (CHUNK_POINTS as u128 + 1));
+    assert_eq!(boundary.len(), 4);
+    let tail = queue.query_times(total * 10 - 25, u128::MAX);
+    assert_eq!(tail.iter().map(|it| it.0).collect::<Vec<_>>(), vec![total * 10 - 20, total * 10 - 10, total * 10]);
+
+    assert_eq!(queue.query_time(first - 1), None);
+    assert_eq!(queue.query_time(first + 15).unwrap().0, first + 10);
+    assert_eq!(queue.query_time(u128::MAX).unwrap().0, total * 10);
+    assert!(queue.insert(total * 10, TSCacheValue::Double(0.0)).is_err());
+    assert!(queue.compression_ratio() > 2.0, "{}", queue.compression_ratio());
+    // the chunk still filling is kept uncompressed
+    assert!(queue.memory_usage() < capacity * 16 * 2 / 3, "{}", queue.memory_usage());
+}
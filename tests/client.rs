@@ -8,22 +8,9 @@ use msgpack_simple::MsgPack;
 use rmp_serde::to_vec_named;
 use rmp_serde::{from_slice};
 
-#[path = "../src/entity.rs"]
-mod entity;
-#[path = "../src/method.rs"]
-mod method;
-
-#[path = "../src/io.rs"]
-mod io;
-
-#[path = "../src/db.rs"]
-mod db;
-
-
-
-use entity::{TSItem};
-use crate::entity::*;
-use crate::method::MethodKind;
+use time_cache::entity::{TSItem};
+use time_cache::entity::*;
+use time_cache::method::MethodKind;
 
 #[test]
 fn client_test() {
@@ -32,6 +19,9 @@ fn client_test() {
         capacity: 100,
         datatype: DataType::Long,
         saveTime: SaveTimePeriod::Minute,
+        labels: Default::default(),
+        precision: Default::default(),
+        compression: Default::default(),
     };
     let rt = serde_json::to_string(&demo).unwrap();
     println!("{}", rt);
@@ -93,7 +83,7 @@ fn client_test03() {
 
     let mut ret = vec![0u8; 1024];
     let n = stream.read(&mut ret).unwrap();
-    let ret: TSCacheValue = from_slice(&ret[..n]).unwrap();
+    let ret: TSCacheValue = from_slice(&ret[6..n]).unwrap();
     println!("{:?}", value);
 }
 
@@ -0,0 +1,26 @@
+// shared by the integration test binaries, not every binary uses every helper
+#![allow(dead_code)]
+
+use std::sync::Once;
+use time_cache::config::{self, Config};
+
+static INIT: Once = Once::new();
+
+// the default config with a data dir of the test binary's own; the dir is not created here,
+// the store makes it on its first write like an embedding application's would
+pub fn init() {
+    init_with(Config::default());
+}
+
+// `init` with other settings, their `data_dir` is replaced; only the first call of a binary counts
+pub fn init_with(conf: Config) {
+    INIT.call_once(|| config::init(Config { data_dir: data_dir(), ..conf }));
+}
+
+// `{temp}/tc-{binary}-{pid}`, the binary's name already tells the test files apart
+pub fn data_dir() -> String {
+    let binary = std::env::current_exe().ok()
+        .and_then(|it| it.file_stem().map(|it| it.to_string_lossy().to_string()))
+        .unwrap_or_default();
+    std::env::temp_dir().join(format!("tc-{}-{}", binary, std::process::id())).to_string_lossy().to_string()
+}
@@ -0,0 +1,226 @@
+use std::fs;
+use std::path::PathBuf;
+
+use time_cache::block::Encoder;
+use time_cache::config::CompactionWindow;
+use time_cache::{compact, io, CacheDb, Compression, DataType, Precision, SaveTimePeriod, TSCacheValue, TSItem, TSPoint, TSValue};
+
+mod common;
+
+// 2024-01-01 00:00 UTC and a minute, the writers' segment names
+const DAY: u128 = 1_704_067_200_000;
+const MINUTE: u128 = 60_000;
+
+fn item(name: &str, compression: Compression) -> TSItem {
+    TSItem { tsName: name.to_string(), capacity: 10, datatype: DataType::Long, saveTime: SaveTimePeriod::Minute, labels: Default::default(), precision: Default::default(), compression }
+}
+
+// a segment as a writer leaves it, one block per key
+fn write_segment(name: &str, day: &str, time: u128, keys: &[u128]) -> PathBuf {
+    let dir = io::series_dir(name).join(day);
+    fs::create_dir_all(&dir).unwrap();
+    let mut buff = io::SEGMENT_MAGIC.to_vec();
+    for key in keys {
+        let mut encoder = Encoder::new(&DataType::Long);
+        encoder.push(*key, &TSCacheValue::Long(*key as i64 * 2));
+        let block = encoder.finish();
+        buff.extend_from_slice(&(block.len() as u32).to_be_bytes());
+        buff.extend_from_slice(&block);
+    }
+    let path = dir.join(format!("{}.tc", time));
+    fs::write(&path, buff).unwrap();
+    path
+}
+
+fn files(name: &str, day: &str) -> Vec<String> {
+    let mut files: Vec<String> = fs::read_dir(io::series_dir(name).join(day)).unwrap()
+        .map(|it| it.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    files.sort();
+    files
+}
+
+fn history_keys(item: &TSItem, start: u128, end: u128) -> Vec<u128> {
+    compact::history(item, start, end, usize::MAX).unwrap().iter().map(|it| it.key).collect()
+}
+
+#[test]
+fn finished_days_merge_into_one_file() {
+    common::init();
+    let item = item("compact-day", Compression::Zstd);
+    for n in 0..5u128 {
+        let path = write_segment(&item.tsName, "2024-01-01", DAY + n * MINUTE, &[n * 10 + 1, n * 10 + 2]);
+        if n == 2 {
+            io::compress_segment(&path, Compression::Lz4).unwrap();
+        }
+    }
+    // the newest segment may still be written to, even in a finished day
+    write_segment(&item.tsName, "2024-01-02", DAY * 2, &[100]);
+    let before = history_keys(&item, 0, u128::MAX);
+
+    assert_eq!(compact::compact_series(&item, CompactionWindow::Day).unwrap(), 5);
+    assert_eq!(files(&item.tsName, "2024-01-01"), vec![format!("{}-{}.tcx", DAY, DAY + 4 * MINUTE)]);
+    assert_eq!(files(&item.tsName, "2024-01-02"), vec![format!("{}.tc", DAY * 2)]);
+    assert_eq!(history_keys(&item, 0, u128::MAX), before);
+    assert_eq!(history_keys(&item, 12, 31), vec![12, 21, 22, 31]);
+    let merged = io::series_dir(&item.tsName).join("2024-01-01").join(format!("{}-{}.tcx", DAY, DAY + 4 * MINUTE));
+    let values = io::read_segment(&merged).unwrap();
+    assert_eq!(values.len(), 10);
+    assert!(values.iter().all(|it| it.name == item.tsName && it.value == TSCacheValue::Long(it.key as i64 * 2)));
+    assert_eq!(compact::compact_series(&item, CompactionWindow::Day).unwrap(), 0);
+}
+
+#[test]
+fn hours_merge_separately_and_keys_are_reordered() {
+    common::init();
+    let item = item("compact-hour", Compression::None);
+    let hour = DAY + 3_600_000;
+    // a restart went back in time
+    write_segment(&item.tsName, "2024-01-01", DAY, &[50, 51]);
+    write_segment(&item.tsName, "2024-01-01", DAY + MINUTE, &[5, 6]);
+    write_segment(&item.tsName, "2024-01-01", hour, &[60]);
+    write_segment(&item.tsName, "2024-01-01", hour + MINUTE, &[61]);
+    write_segment(&item.tsName, "2024-01-01", hour + 2 * MINUTE, &[62]);
+
+    assert_eq!(compact::compact_series(&item, CompactionWindow::Hour).unwrap(), 4);
+    assert_eq!(files(&item.tsName, "2024-01-01"), vec![
+        format!("{}-{}.tcx", DAY, DAY + MINUTE),
+        format!("{}-{}.tcx", hour, hour + MINUTE),
+        format!("{}.tc", hour + 2 * MINUTE),
+    ]);
+    assert_eq!(history_keys(&item, 0, u128::MAX), vec![5, 6, 50, 51, 60, 61, 62]);
+
+    // once the day is over the hours are merged again
+    write_segment(&item.tsName, "2024-01-02", DAY * 2, &[70]);
+    assert_eq!(compact::compact_series(&item, CompactionWindow::Day).unwrap(), 3);
+    assert_eq!(files(&item.tsName, "2024-01-01"), vec![format!("{}-{}.tcx", DAY, hour + 2 * MINUTE)]);
+    assert_eq!(history_keys(&item, 0, u128::MAX), vec![5, 6, 50, 51, 60, 61, 62, 70]);
+}
+
+#[test]
+fn inputs_left_by_an_interrupted_merge_are_dropped() {
+    common::init();
+    let item = item("compact-crash", Compression::Lz4);
+    let first = write_segment(&item.tsName, "2024-01-01", DAY, &[1]);
+    let second = write_segment(&item.tsName, "2024-01-01", DAY + MINUTE, &[2]);
+    write_segment(&item.tsName, "2024-01-02", DAY * 2, &[3]);
+    let (keep_first, keep_second) = (fs::read(&first).unwrap(), fs::read(&second).unwrap());
+    compact::compact_series(&item, CompactionWindow::Day).unwrap();
+    // as if the process stopped between the rename and removing the inputs
+    fs::write(&first, keep_first).unwrap();
+    fs::write(&second, keep_second).unwrap();
+    fs::write(first.with_extension("tmp"), b"partial").unwrap();
+
+    assert_eq!(history_keys(&item, 0, u128::MAX), vec![1, 2, 3]);
+    assert_eq!(compact::compact_series(&item, CompactionWindow::Day).unwrap(), 0);
+    assert!(!first.exists() && !second.exists());
+    assert_eq!(history_keys(&item, 0, u128::MAX), vec![1, 2, 3]);
+}
+
+#[test]
+fn files_inside_a_merged_range_are_kept_until_merged() {
+    common::init();
+    let item = item("compact-unmerged", Compression::None);
+    write_segment(&item.tsName, "2024-01-01", DAY, &[1]);
+    write_segment(&item.tsName, "2024-01-01", DAY + 2 * MINUTE, &[3]);
+    write_segment(&item.tsName, "2024-01-02", DAY * 2, &[9]);
+    assert_eq!(compact::compact_series(&item, CompactionWindow::Day).unwrap(), 2);
+    let merged = format!("{}-{}.tcx", DAY, DAY + 2 * MINUTE);
+    let dir = io::series_dir(&item.tsName).join("2024-01-01");
+    assert_eq!(io::compacted_inputs(&dir.join(&merged)).unwrap(), vec![format!("{}.tc", DAY), format!("{}.tc", DAY + 2 * MINUTE)]);
+
+    // not one of the merge's inputs, e.g. restored from a backup
+    let restored = write_segment(&item.tsName, "2024-01-01", DAY + MINUTE, &[2]);
+    assert_eq!(history_keys(&item, 0, u128::MAX), vec![1, 2, 3, 9]);
+    // merged with the earlier merge into a file of the same name
+    assert_eq!(compact::compact_series(&item, CompactionWindow::Day).unwrap(), 2);
+    assert!(!restored.exists());
+    assert_eq!(files(&item.tsName, "2024-01-01"), vec![merged]);
+    assert_eq!(history_keys(&item, 0, u128::MAX), vec![1, 2, 3, 9]);
+}
+
+#[test]
+fn merged_files_read_ranges_through_their_index() {
+    common::init();
+    let dir = io::series_dir("compact-index").join("2024-01-01");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{}-{}.tcx", DAY, DAY));
+    let points: Vec<(u128, TSCacheValue)> = (0..5000u128).map(|key| (key * 3, TSCacheValue::String(format!("v{}", key % 7)))).collect();
+    for compression in [Compression::None, Compression::Lz4, Compression::Zstd] {
+        io::write_compacted(&path, &DataType::String, &points, compression, &[]).unwrap();
+        let range = io::read_segment_range(&path, 3000, 3300).unwrap();
+        assert_eq!(range.iter().map(|it| it.key).collect::<Vec<_>>(), (1000..=1100).map(|it| it * 3).collect::<Vec<_>>());
+        assert_eq!(range[0].value, TSCacheValue::String(format!("v{}", 1000 % 7)));
+        assert_eq!(io::read_segment(&path).unwrap().len(), 5000);
+        assert!(io::read_segment_range(&path, 15_000, u128::MAX).unwrap().is_empty());
+    }
+    let mut broken = fs::read(&path).unwrap();
+    let length = broken.len();
+    broken[length - 1] ^= 0xff;
+    fs::write(&path, broken).unwrap();
+    assert_eq!(io::read_segment_range(&path, 0, 10).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn ranges_older_than_the_queue_are_read_from_disk() {
+    common::init();
+    let db = CacheDb::new();
+    db.create(TSItem { capacity: 3, ..item("compact-range", Compression::Lz4) }).unwrap();
+    for key in 1..=8u128 {
+        db.set(TSValue { name: "compact-range".to_string(), key: key * 1000, value: TSCacheValue::Long(key as i64) }).unwrap();
+    }
+    db.shutdown();
+    let keys = |start, end| db.range("compact-range", start, end).unwrap().iter().map(|it| it.key / 1000).collect::<Vec<_>>();
+    assert_eq!(keys(0, u128::MAX), (1..=8).collect::<Vec<_>>());
+    assert_eq!(keys(2000, 3000), vec![2, 3]);
+    assert_eq!(keys(4500, 7000), vec![5, 6, 7]);
+    assert_eq!(keys(7000, u128::MAX), vec![7, 8]);
+    assert_eq!(db.range_as("compact-range", 1, 2, Precision::Seconds).unwrap(), vec![
+        TSPoint { key: 1, value: TSCacheValue::Long(1) },
+        TSPoint { key: 2, value: TSCacheValue::Long(2) },
+    ]);
+}
+
+#[test]
+fn history_skips_days_written_before_the_range() {
+    common::init();
+    let item = item("compact-skip", Compression::None);
+    // not a segment anyone could read, it must never be opened
+    let dir = io::series_dir(&item.tsName).join("2024-01-01");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join(format!("{}.tc", DAY)), b"garbage").unwrap();
+    write_segment(&item.tsName, "2024-01-05", DAY + 4 * 24 * 60 * MINUTE, &[DAY + 4 * 24 * 60 * MINUTE]);
+    let newest = DAY + 8 * 24 * 60 * MINUTE;
+    write_segment(&item.tsName, "2024-01-09", newest, &[newest, newest + 1]);
+
+    assert_eq!(history_keys(&item, newest, u128::MAX), vec![newest, newest + 1]);
+    assert!(compact::history(&item, 0, u128::MAX, usize::MAX).is_err());
+}
+
+#[test]
+fn history_stops_at_the_point_limit() {
+    common::init();
+    let item = item("compact-limit", Compression::None);
+    write_segment(&item.tsName, "2024-01-01", DAY, &[1, 2, 3]);
+
+    assert_eq!(compact::history(&item, 0, u128::MAX, 3).unwrap().len(), 3);
+    let e = compact::history(&item, 0, u128::MAX, 2).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::QuotaExceeded);
+}
+
+#[test]
+fn names_are_escaped_in_directory_names() {
+    common::init();
+    let dir = |name: &str| io::series_dir(name).file_name().unwrap().to_string_lossy().to_string();
+    assert_eq!(dir("disk.used;path=/"), "disk.used;path=%2F");
+    assert_eq!(dir("cpu.idle;host=a"), "cpu.idle;host=a");
+    assert_eq!((dir("."), dir("..")), ("%2E".to_string(), "%2E%2E".to_string()));
+    for name in ["disk;device=/dev/sda", "a\\b%2F", "..", "tab\there", "温度"] {
+        assert_eq!(io::dir_name_series(&dir(name)), name);
+    }
+
+    let item = item("disk;path=/", Compression::None);
+    write_segment(&item.tsName, "2024-01-01", DAY, &[1, 2]);
+    let values = compact::history(&item, 0, u128::MAX, usize::MAX).unwrap();
+    assert_eq!(values.iter().map(|it| (it.name.as_str(), it.key)).collect::<Vec<_>>(), vec![("disk;path=/", 1), ("disk;path=/", 2)]);
+}
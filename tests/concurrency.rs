@@ -0,0 +1,83 @@
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use time_cache::alert::{AlertCondition, AlertRule, AlertState};
+use time_cache::db::CacheDb;
+use time_cache::entity::{DataType, SaveTimePeriod, TSCacheValue, TSItem, TSValue};
+use time_cache::method::TSQueue;
+
+mod common;
+
+fn create(db: &CacheDb, name: &str) {
+    common::init();
+    let item = TSItem { tsName: name.to_string(), capacity: 100, datatype: DataType::Long, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision: Default::default(), compression: Default::default() };
+    db.create_new_item(item.clone(), TSQueue::new(Box::new(item), 100)).unwrap();
+}
+
+#[test]
+fn reader_of_one_series_does_not_block_other_writers() {
+    let db = Arc::new(CacheDb::new());
+    create(&db, "concurrency-a");
+    create(&db, "concurrency-b");
+    let a = db.get("concurrency-a").unwrap();
+    let reading = a.queue();
+
+    let (sender, receiver) = mpsc::channel();
+    let writer = db.clone();
+    thread::spawn(move || {
+        let mut value = TSValue { name: "concurrency-b".to_string(), key: 1, value: TSCacheValue::Long(1) };
+        sender.send(writer.insert_new_value(&mut value).is_ok()).unwrap();
+    });
+    assert!(receiver.recv_timeout(Duration::from_secs(5)).unwrap());
+    // a second reader of the same series is not blocked either
+    assert!(a.queue().query_last().is_none());
+    drop(reading);
+}
+
+#[test]
+fn parallel_writers_keep_every_point() {
+    let db = Arc::new(CacheDb::new());
+    for i in 0..4 {
+        create(&db, &format!("parallel-{}", i));
+    }
+    let handles: Vec<_> = (0..4).map(|i| {
+        let db = db.clone();
+        thread::spawn(move || {
+            for key in 1..=50u128 {
+                let mut value = TSValue { name: format!("parallel-{}", i), key, value: TSCacheValue::Long(key as i64) };
+                db.insert_new_value(&mut value).unwrap();
+            }
+        })
+    }).collect();
+    handles.into_iter().for_each(|it| it.join().unwrap());
+    for i in 0..4 {
+        let series = db.get(&format!("parallel-{}", i)).unwrap();
+        assert_eq!(series.queue().query_last(), Some(TSCacheValue::Long(50)));
+    }
+    let mut duplicate = TSValue { name: "parallel-0".to_string(), key: 50, value: TSCacheValue::Long(0) };
+    assert!(db.insert_new_value(&mut duplicate).is_err());
+}
+
+#[test]
+fn rules_see_server_assigned_keys_in_order() {
+    let db = Arc::new(CacheDb::new());
+    create(&db, "rated");
+    db.create_alert(AlertRule { name: "rated_jump".to_string(), ts_name: "rated".to_string(), condition: AlertCondition::Rate { limit: 1e12, window: 1 }, duration: 0 }).unwrap();
+    db.create_alert(AlertRule { name: "rated_any".to_string(), ts_name: "rated".to_string(), condition: AlertCondition::Above(0.0), duration: 0 }).unwrap();
+    let handles: Vec<_> = (0..4).map(|_| {
+        let db = db.clone();
+        thread::spawn(move || {
+            for _ in 0..500 {
+                let mut value = TSValue { name: "rated".to_string(), key: 0, value: TSCacheValue::Long(1) };
+                db.insert_new_value(&mut value).unwrap();
+            }
+        })
+    }).collect();
+    handles.into_iter().for_each(|it| it.join().unwrap());
+    // a rule evaluated out of key order used to panic with the alerts lock held
+    let state = |rule: &str| db.alerts().status().into_iter().find(|it| it.rule.name == rule).unwrap().state;
+    assert_eq!(state("rated_jump"), AlertState::Inactive);
+    assert_eq!(state("rated_any"), AlertState::Firing);
+}
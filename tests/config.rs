@@ -0,0 +1,78 @@
+use clap::Parser;
+use rmp_serde::{from_slice, to_vec_named};
+
+use time_cache::config::{Args, CompactionWindow, Config};
+use time_cache::entity::{SaveTimePeriod, TSItem};
+use time_cache::entity;
+
+#[test]
+fn parse_partial_file() {
+    let config = Config::parse(r#"
+        listen = "0.0.0.0:9000"
+        default_save_time = "Hour"
+        [limits]
+        max_series = 5
+    "#).unwrap();
+    assert_eq!(config.listen, "0.0.0.0:9000");
+    assert_eq!(config.default_save_time, SaveTimePeriod::Hour);
+    assert_eq!(config.limits.max_series, 5);
+    assert_eq!(config.data_dir, "./data");
+    assert_eq!(config.limits.max_capacity, 10_000_000);
+    assert!(Config::parse("listne = \"x\"").is_err());
+}
+
+// validate creates the data dir, keep it out of the working directory
+fn defaults() -> Config {
+    Config { data_dir: std::env::temp_dir().join(format!("tc-config-{}", std::process::id())).to_string_lossy().to_string(), ..Default::default() }
+}
+
+#[test]
+fn validate_reports_bad_values() {
+    let mut config = defaults();
+    config.validate().unwrap();
+    config.listen = "localhost".to_string();
+    assert!(config.validate().unwrap_err().contains("listen"));
+    config = defaults();
+    config.default_capacity = 0;
+    assert!(config.validate().unwrap_err().contains("default_capacity"));
+    config = defaults();
+    config.log_config = "missing.yaml".to_string();
+    assert!(config.validate().unwrap_err().contains("log_config"));
+    config = defaults();
+    config.statsd.percentiles = vec![95.0, 0.0];
+    assert!(config.validate().unwrap_err().contains("percentile"));
+}
+
+#[test]
+fn flags_override_file() {
+    let path = std::env::temp_dir().join(format!("tc-config-{}.toml", std::process::id()));
+    let file = format!("listen = \"127.0.0.1:7000\"\ndefault_capacity = 10\ndata_dir = {:?}\n", defaults().data_dir);
+    std::fs::write(&path, file).unwrap();
+    let args = Args::try_parse_from([
+        "time-cache", "--config", path.to_str().unwrap(), "--listen", "127.0.0.1:7001", "--default-save-time", "ten_minutes",
+        "--compaction-window", "hour",
+    ]).unwrap();
+    let config = Config::load(&args).unwrap();
+    assert_eq!(config.listen, "127.0.0.1:7001");
+    assert_eq!(config.default_capacity, 10);
+    assert_eq!(config.default_save_time, SaveTimePeriod::TenMinutes);
+    assert_eq!((config.compaction.window, config.compaction.interval_secs), (CompactionWindow::Hour, 3600));
+    std::fs::remove_file(path).unwrap();
+
+    let args = Args::try_parse_from(["time-cache", "--config", "no-such.toml"]).unwrap();
+    assert!(Config::load(&args).unwrap_err().contains("not found"));
+}
+
+#[test]
+fn item_defaults_come_from_config() {
+    #[allow(non_snake_case)]
+    #[derive(serde::Serialize)]
+    struct Partial {
+        tsName: String,
+        datatype: entity::DataType,
+    }
+    let encoded = to_vec_named(&Partial { tsName: "cpu".to_string(), datatype: entity::DataType::Double }).unwrap();
+    let item: TSItem = from_slice(&encoded).unwrap();
+    assert_eq!(item.capacity, 1000);
+    assert_eq!(item.saveTime, SaveTimePeriod::Minute);
+}
@@ -0,0 +1,57 @@
+use time_cache::{CacheDb, DataType, ExceptionKind, Exception, SaveTimePeriod, TSCacheValue, TSItem, TSPoint, TSValue};
+
+mod common;
+
+fn item(name: &str, capacity: usize) -> TSItem {
+    TSItem { tsName: name.to_string(), capacity, datatype: DataType::Double, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision: Default::default(), compression: Default::default() }
+}
+
+fn value(name: &str, key: u128) -> TSValue {
+    TSValue { name: name.to_string(), key, value: TSCacheValue::Double(key as f64 / 2.0) }
+}
+
+fn code(kind: ExceptionKind) -> i16 {
+    Exception::err(kind, "").code
+}
+
+#[test]
+fn embedded_round_trip() {
+    common::init();
+    // the data dir does not exist yet, creating a series must make it
+    let db = CacheDb::open().unwrap();
+    db.create(item("embedded", 3)).unwrap();
+    for key in 1..=5 {
+        db.set(value("embedded", key)).unwrap();
+    }
+    assert_eq!(db.last("embedded").unwrap(), TSCacheValue::Double(2.5));
+    // the queue keeps the newest 3 points
+    let keys: Vec<u128> = db.range("embedded", 0, u128::MAX).unwrap().iter().map(|it| it.key).collect();
+    assert_eq!(keys, vec![3, 4, 5]);
+    assert_eq!(db.query("embedded", 4).unwrap(), TSPoint { key: 4, value: TSCacheValue::Double(2.0) });
+    assert_eq!(db.query("embedded", 2).unwrap_err().code, code(ExceptionKind::QueueIsNullError));
+    assert_eq!(db.set(value("embedded", 5)).unwrap_err().code, code(ExceptionKind::TimeSerieError));
+
+    db.drop_item("embedded").unwrap();
+    assert_eq!(db.last("embedded").unwrap_err().code, code(ExceptionKind::TSNameExistsError));
+}
+
+#[test]
+fn embedded_create_checks_capacity() {
+    common::init();
+    let db = CacheDb::new();
+    assert_eq!(db.create(item("zero", 0)).unwrap_err().code, code(ExceptionKind::LimitError));
+    db.create(item("once", 1)).unwrap();
+    assert_eq!(db.create(item("once", 1)).unwrap_err().code, code(ExceptionKind::TSNameExistsError));
+}
+
+#[test]
+fn embedded_create_checks_name() {
+    common::init();
+    let db = CacheDb::new();
+    assert_eq!(db.create(item("", 1)).unwrap_err().code, code(ExceptionKind::InvalidNameError));
+    // tag values such as mount points end up in names
+    for name in ["disk.used;path=/", "..", "a\\b", "cpu\n"] {
+        db.create(item(name, 1)).unwrap();
+    }
+    assert_eq!(db.len(), 4);
+}
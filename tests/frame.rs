@@ -0,0 +1,179 @@
+use std::sync::Arc;
+use bytes::BytesMut;
+use rmp_serde::{from_slice, to_vec_named};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+
+use time_cache::db::CacheDb;
+use time_cache::entity::{DataType, SaveTimePeriod, TSCacheValue, TSItem, TSValue};
+use time_cache::frame::{
+    decode, decode_response, encode_request, FrameError, Response, FLAG_NO_REPLY, PAYLOAD_EMPTY, PAYLOAD_EXCEPTION, PAYLOAD_MSGPACK,
+    PAYLOAD_VALUE, STATUS_BUSY, STATUS_CLIENT_ERROR, STATUS_OK, STATUS_PROTOCOL_ERROR, VERSION_1, VERSION_2,
+};
+use time_cache::method::{Exception, MethodKind};
+use time_cache::{frame, handle};
+
+mod common;
+
+const MAX: usize = 1024;
+
+fn v1(action: u16, payload: &[u8]) -> Vec<u8> {
+    let mut buff = action.to_be_bytes().to_vec();
+    buff.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buff.extend_from_slice(payload);
+    buff
+}
+
+#[test]
+fn decode_both_versions_from_one_buffer() {
+    let mut buff = BytesMut::new();
+    buff.extend_from_slice(&v1(301, b"\xa3cpu"));
+    buff.extend_from_slice(&encode_request(7, FLAG_NO_REPLY, 201, b"\x01\x02"));
+    buff.extend_from_slice(&encode_request(8, 0, 301, b"")[..5]);
+
+    let first = decode(&mut buff, MAX).unwrap().unwrap();
+    assert_eq!((first.version, first.id, first.action, &first.payload[..]), (VERSION_1, 0, 301, &b"\xa3cpu"[..]));
+    let second = decode(&mut buff, MAX).unwrap().unwrap();
+    assert_eq!((second.version, second.id, second.action, &second.payload[..]), (VERSION_2, 7, 201, &b"\x01\x02"[..]));
+    assert!(second.no_reply());
+    // the partial third frame stays buffered
+    assert_eq!(decode(&mut buff, MAX).unwrap(), None);
+    assert_eq!(buff.len(), 5);
+}
+
+#[test]
+fn decode_rejects_unknown_version() {
+    let mut buff = BytesMut::from(&[frame::MAGIC, 9][..]);
+    assert_eq!(decode(&mut buff, MAX), Err(FrameError::UnsupportedVersion(9)));
+}
+
+#[test]
+fn decode_rejects_bad_headers_before_the_payload() {
+    // only the header is buffered, the declared payload never has to arrive
+    let mut buff = BytesMut::from(&v1(201, &[0; MAX + 1])[..6]);
+    assert_eq!(decode(&mut buff, MAX), Err(FrameError::TooLarge(MAX + 1)));
+    let mut buff = BytesMut::from(&encode_request(1, 0, 201, &[0; MAX + 1])[..13]);
+    assert_eq!(decode(&mut buff, MAX), Err(FrameError::TooLarge(MAX + 1)));
+    let mut buff = BytesMut::from(&encode_request(1, 0x80 | FLAG_NO_REPLY, 201, b"")[..3]);
+    assert_eq!(decode(&mut buff, MAX), Err(FrameError::UnknownFlags(0x81)));
+}
+
+async fn start_server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    common::init();
+    let db = Arc::new(CacheDb::new());
+    tokio::spawn(async move {
+        let (_stop, shutdown) = watch::channel(false);
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(handle::serve(socket, db.clone(), shutdown.clone()));
+        }
+    });
+    addr
+}
+
+// reads byte by byte so every response goes through partial decoding
+async fn read_response(stream: &mut TcpStream, version: u8) -> (u32, Response) {
+    let mut buff = BytesMut::new();
+    loop {
+        if let Some(response) = decode_response(&mut buff, version) {
+            assert!(buff.is_empty());
+            return response;
+        }
+        buff.extend_from_slice(&[stream.read_u8().await.unwrap()]);
+    }
+}
+
+#[tokio::test]
+async fn pipelined_requests_are_answered_with_their_ids() {
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    let name = format!("pipeline-{}", std::process::id());
+    let item = TSItem { tsName: name.clone(), capacity: 10, datatype: DataType::Long, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision: Default::default(), compression: Default::default() };
+    let mut buff = encode_request(1, 0, MethodKind::Create.as_code(), &to_vec_named(&item).unwrap());
+    for key in 1..=3u128 {
+        let value = TSValue { name: name.clone(), key, value: TSCacheValue::Long(key as i64) };
+        let flags = if key == 2 { FLAG_NO_REPLY } else { 0 };
+        buff.extend(encode_request(10 + key as u32, flags, MethodKind::Set.as_code(), &to_vec_named(&value).unwrap()));
+    }
+    buff.extend(encode_request(20, 0, MethodKind::Get.as_code(), &to_vec_named(&name).unwrap()));
+    buff.extend(encode_request(21, 0, MethodKind::Get.as_code(), &to_vec_named(&"missing").unwrap()));
+    stream.write_all(&buff).await.unwrap();
+
+    let mut ids = vec![];
+    for _ in 0..5 {
+        let (id, response) = read_response(&mut stream, VERSION_2).await;
+        ids.push(id);
+        match id {
+            20 => {
+                assert_eq!((response.status, response.payload_type), (STATUS_OK, PAYLOAD_VALUE));
+                assert_eq!(from_slice::<TSCacheValue>(&response.payload).unwrap(), TSCacheValue::Long(3));
+            }
+            21 => {
+                assert_eq!((response.status, response.payload_type), (STATUS_CLIENT_ERROR, PAYLOAD_EXCEPTION));
+                assert_eq!(from_slice::<Exception>(&response.payload).unwrap().code, 4002);
+            }
+            // Set replies with the stored key
+            11 | 13 => {
+                assert_eq!((response.status, response.payload_type), (STATUS_OK, PAYLOAD_MSGPACK));
+                assert_eq!(from_slice::<u128>(&response.payload).unwrap(), id as u128 - 10);
+            }
+            _ => assert_eq!(response, Response { status: STATUS_OK, payload_type: PAYLOAD_EMPTY, payload: vec![] }),
+        }
+    }
+    assert_eq!(ids, vec![1, 11, 13, 20, 21]);
+
+    // version 1 requests get the same response frame without the id
+    let mut old = TcpStream::connect(&addr).await.unwrap();
+    old.write_all(&v1(MethodKind::Get.as_code(), &to_vec_named(&name).unwrap())).await.unwrap();
+    let (_, response) = read_response(&mut old, VERSION_1).await;
+    assert_eq!(response.payload_type, PAYLOAD_VALUE);
+    assert_eq!(from_slice::<TSCacheValue>(&response.payload).unwrap(), TSCacheValue::Long(3));
+}
+
+#[tokio::test]
+async fn bad_requests_keep_the_connection_in_sync() {
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    let name = format!("strict-{}", std::process::id());
+    let item = TSItem { tsName: name.clone(), capacity: 10, datatype: DataType::Long, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision: Default::default(), compression: Default::default() };
+    let mut trailing = to_vec_named(&item).unwrap();
+    trailing.push(0xc0);
+    let mut buff = encode_request(1, 0, 999, b"\x01");
+    buff.extend(encode_request(2, 0, MethodKind::Create.as_code(), &trailing));
+    buff.extend(encode_request(3, 0, MethodKind::Get.as_code(), b"\xff"));
+    buff.extend(encode_request(4, 0, MethodKind::Create.as_code(), &to_vec_named(&item).unwrap()));
+    stream.write_all(&buff).await.unwrap();
+
+    for (expect_id, code) in [(1, 4010), (2, 4001), (3, 4001)] {
+        let (id, response) = read_response(&mut stream, VERSION_2).await;
+        assert_eq!((id, response.status), (expect_id, STATUS_CLIENT_ERROR));
+        assert_eq!(from_slice::<Exception>(&response.payload).unwrap().code, code);
+    }
+    let (id, response) = read_response(&mut stream, VERSION_2).await;
+    assert_eq!((id, response.status), (4, STATUS_OK));
+
+    // an oversized frame is a protocol error and ends the connection
+    let huge = 16 * 1024 * 1024 + 1;
+    let mut header = encode_request(5, 0, MethodKind::Set.as_code(), b"");
+    header[9..13].copy_from_slice(&(huge as u32).to_be_bytes());
+    stream.write_all(&header).await.unwrap();
+    let (_, response) = read_response(&mut stream, VERSION_2).await;
+    assert_eq!(response.status, STATUS_PROTOCOL_ERROR);
+    assert_eq!(stream.read(&mut [0u8; 1]).await.unwrap(), 0);
+}
+
+#[test]
+fn error_status_classes() {
+    let busy = Response::error(&Exception::new(4009, "full"));
+    assert_eq!((busy.status, busy.payload_type), (STATUS_BUSY, PAYLOAD_EXCEPTION));
+    assert_eq!(Response::error(&Exception::new(-1, "closed")).status, STATUS_PROTOCOL_ERROR);
+    assert_eq!(Response::error(&Exception::new(4001, "parse")).status, STATUS_CLIENT_ERROR);
+
+    let encoded = Response::ok(PAYLOAD_VALUE, vec![7]).encode(VERSION_2, 42);
+    assert_eq!(encoded, vec![0, 0, 0, 42, STATUS_OK, PAYLOAD_VALUE, 0, 0, 0, 1, 7]);
+    let mut buff = BytesMut::from(&encoded[..encoded.len() - 1]);
+    assert_eq!(decode_response(&mut buff, VERSION_2), None);
+}
@@ -0,0 +1,170 @@
+use std::sync::Arc;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{watch, Semaphore};
+
+use time_cache::db::CacheDb;
+use time_cache::{DataType, SaveTimePeriod, TSCacheValue, TSItem, TSValue};
+use time_cache::http;
+
+mod common;
+
+async fn start_gateway() -> String {
+    serve(Arc::new(CacheDb::new())).await
+}
+
+async fn serve(db: Arc<CacheDb>) -> String {
+    common::init();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let (stop, shutdown) = watch::channel(false);
+    tokio::spawn(async move {
+        let _stop = stop;
+        http::serve(listener, db, Arc::new(Semaphore::new(16)), shutdown).await;
+    });
+    addr
+}
+
+async fn send(addr: &str, raw: &[u8]) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(raw).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+    (status, body)
+}
+
+async fn request(addr: &str, method: &str, path: &str, body: Option<Value>) -> (u16, String) {
+    let body = body.map(|it| it.to_string()).unwrap_or_default();
+    let raw = format!("{} {} HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}", method, path, body.len(), body);
+    send(addr, raw.as_bytes()).await
+}
+
+fn code(body: &str) -> i64 {
+    serde_json::from_str::<Value>(body).unwrap()["code"].as_i64().unwrap()
+}
+
+#[tokio::test]
+async fn rest_round_trip() {
+    let addr = start_gateway().await;
+    let name = format!("gw-{}", std::process::id());
+    let item = json!({ "tsName": name, "capacity": 10, "datatype": "Double", "saveTime": "Nerve" });
+    assert_eq!(request(&addr, "POST", "/series", Some(item.clone())).await.0, 201);
+    let (status, body) = request(&addr, "POST", "/series", Some(item)).await;
+    assert_eq!((status, code(&body)), (409, 4002));
+
+    let points = format!("/series/{}/points", name);
+    let (status, body) = request(&addr, "POST", &points, Some(json!({ "key": 1, "value": 1 }))).await;
+    assert_eq!((status, body), (200, r#"{"key":1}"#.to_string()));
+    let batch = json!([{ "key": 2, "value": 2.5 }, { "key": 3, "value": 3.5 }]);
+    let (status, body) = request(&addr, "POST", &points, Some(batch)).await;
+    assert_eq!((status, body), (200, r#"{"keys":[2,3]}"#.to_string()));
+    let (status, body) = request(&addr, "POST", &points, Some(json!({ "key": 3, "value": 4.0 }))).await;
+    assert_eq!((status, code(&body)), (409, 4004));
+    let (status, body) = request(&addr, "POST", &points, Some(json!({ "key": 9, "value": "x" }))).await;
+    assert_eq!((status, code(&body)), (422, 4005));
+
+    let (status, body) = request(&addr, "GET", &format!("/series/{}/last", name), None).await;
+    assert_eq!((status, body), (200, r#"{"value":3.5}"#.to_string()));
+    let (status, body) = request(&addr, "GET", &format!("/series/{}/range?start=2&end=3", name), None).await;
+    assert_eq!((status, body), (200, r#"[{"key":2,"value":2.5},{"key":3,"value":3.5}]"#.to_string()));
+
+    let (status, body) = request(&addr, "GET", &format!("/series?prefix={}", name), None).await;
+    assert_eq!(status, 200);
+    let listed = serde_json::from_str::<Value>(&body).unwrap();
+    assert_eq!(listed[0]["datatype"], "Double");
+    assert_eq!(listed[0]["compressionRatio"], 1.0);
+    assert_eq!(request(&addr, "DELETE", &format!("/series/{}", name), None).await.0, 204);
+    let (status, body) = request(&addr, "GET", &format!("/series/{}/last", name), None).await;
+    assert_eq!((status, code(&body)), (404, 4002));
+}
+
+#[tokio::test]
+async fn points_without_a_key_get_one_assigned() {
+    let addr = start_gateway().await;
+    let name = format!("gw-assigned-{}", std::process::id());
+    let item = json!({ "tsName": name, "capacity": 4, "datatype": "Long", "saveTime": "Nerve" });
+    assert_eq!(request(&addr, "POST", "/series", Some(item)).await.0, 201);
+    let points = format!("/series/{}/points", name);
+    let (status, body) = request(&addr, "POST", &points, Some(json!({ "value": 7 }))).await;
+    assert_eq!(status, 200);
+    let key = serde_json::from_str::<Value>(&body).unwrap()["key"].as_u64().unwrap();
+    assert!(key > 0);
+    let (status, body) = request(&addr, "POST", &points, Some(json!([{ "value": 8 }, { "value": 9 }]))).await;
+    assert_eq!(status, 200);
+    let keys: Vec<u64> = serde_json::from_value(serde_json::from_str::<Value>(&body).unwrap()["keys"].clone()).unwrap();
+    assert!(keys.len() == 2 && key < keys[0] && keys[0] < keys[1]);
+}
+
+#[tokio::test]
+async fn keys_wider_than_u64_are_strings() {
+    let db = Arc::new(CacheDb::new());
+    let addr = serve(db.clone()).await;
+    let name = format!("gw-wide-{}", std::process::id());
+    let item = TSItem { tsName: name.clone(), capacity: 4, datatype: DataType::Long, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision: Default::default(), compression: Default::default() };
+    db.create(item).unwrap();
+    let wide = u64::MAX as u128 + 1;
+    db.set(TSValue { name: name.clone(), key: u64::MAX as u128, value: TSCacheValue::Long(1) }).unwrap();
+    db.set(TSValue { name: name.clone(), key: wide, value: TSCacheValue::Long(2) }).unwrap();
+    let (status, body) = request(&addr, "GET", &format!("/series/{}/range", name), None).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, format!(r#"[{{"key":{},"value":1}},{{"key":"{}","value":2}}]"#, u64::MAX, wide));
+}
+
+#[tokio::test]
+async fn byte_arrays_are_base64() {
+    let addr = start_gateway().await;
+    let name = format!("gw-bytes-{}", std::process::id());
+    let item = json!({ "tsName": name, "capacity": 4, "datatype": "ByteArray", "saveTime": "Nerve" });
+    assert_eq!(request(&addr, "POST", "/series", Some(item)).await.0, 201);
+    let points = format!("/series/{}/points", name);
+    assert_eq!(request(&addr, "POST", &points, Some(json!({ "key": 1, "value": "AAH/" }))).await.0, 200);
+    assert_eq!(request(&addr, "POST", &points, Some(json!({ "key": 2, "value": "not base64!" }))).await.0, 422);
+    let (_, body) = request(&addr, "GET", &format!("/series/{}/last", name), None).await;
+    assert_eq!(body, r#"{"value":"AAH/"}"#);
+}
+
+#[tokio::test]
+async fn malformed_http_is_rejected() {
+    let addr = start_gateway().await;
+    assert_eq!(send(&addr, b"NOT HTTP\r\n\r\n").await.0, 400);
+    let chunked = b"POST /series HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n";
+    assert_eq!(send(&addr, chunked).await.0, 501);
+    let huge = b"POST /series HTTP/1.1\r\nContent-Length: 999999999\r\n\r\n";
+    assert_eq!(send(&addr, huge).await.0, 413);
+    assert_eq!(request(&addr, "GET", "/nothing", None).await.0, 404);
+    assert_eq!(request(&addr, "PUT", "/series", None).await.0, 405);
+    let (status, body) = request(&addr, "POST", "/series", Some(json!({ "tsName": 1 }))).await;
+    assert_eq!((status, code(&body)), (400, 4001));
+}
+
+#[tokio::test]
+async fn keep_alive_serves_several_requests() {
+    let addr = start_gateway().await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    stream.write_all(b"GET /series HTTP/1.1\r\n\r\nGET /series HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+}
+
+#[tokio::test]
+async fn serve_returns_after_its_connections() {
+    common::init();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let (stop, shutdown) = watch::channel(false);
+    let server = tokio::spawn(http::serve(listener, Arc::new(CacheDb::new()), Arc::new(Semaphore::new(4)), shutdown));
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    stream.write_all(b"GET /memory HTTP/1.1\r\nHost: test\r\n\r\n").await.unwrap();
+    let mut buff = vec![0u8; 4096];
+    let n = stream.read(&mut buff).await.unwrap();
+    assert!(buff[..n].starts_with(b"HTTP/1.1 200"));
+
+    stop.send_replace(true);
+    tokio::time::timeout(std::time::Duration::from_secs(5), server).await.unwrap().unwrap();
+    // the kept-alive connection was closed before serve returned
+    assert_eq!(stream.read(&mut buff).await.unwrap(), 0);
+}
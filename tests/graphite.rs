@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{watch, Semaphore};
+
+use time_cache::graphite::{parse_line, write};
+use time_cache::{graphite, CacheDb, Config, DataType, SaveTimePeriod, TSCacheValue, TSItem, TSPoint};
+
+mod common;
+
+fn init() {
+    common::init_with(Config { default_save_time: SaveTimePeriod::Nerve, ..Default::default() });
+}
+
+#[test]
+fn parse_plain_and_tagged_paths() {
+    let line = parse_line("servers.a.load 0.5 1700000000").unwrap();
+    assert_eq!((line.path.as_str(), line.value, line.timestamp), ("servers.a.load", 0.5, Some(1_700_000_000_000)));
+    assert_eq!(line.series_name(), "servers.a.load");
+
+    let line = parse_line("disk.used;path=/var;host=a 42 1700000000.25").unwrap();
+    let tags: BTreeMap<String, String> = [("host", "a"), ("path", "/var")].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    assert_eq!(line.tags, tags);
+    assert_eq!(line.timestamp, Some(1_700_000_000_250));
+    assert_eq!(line.series_name(), "disk.used;host=a;path=/var");
+
+    assert_eq!(parse_line("up 1 -1").unwrap().timestamp, None);
+    assert_eq!(parse_line("up 1").unwrap().timestamp, None);
+    for bad in ["up", "up one 1", "up 1 yesterday", "up 1 2 3", ";host=a 1 1", "up;host 1 1", "up nan 1"] {
+        assert!(parse_line(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn write_creates_double_series() {
+    init();
+    let db = CacheDb::new();
+    let result = write(&db, "net.rx;host=a 10 1\nnet.rx;host=a 12.5 2\n\nbroken\n");
+    assert_eq!(result.points, 2);
+    assert_eq!(result.errors.len(), 1);
+    assert!(result.errors[0].starts_with("line 4"), "{:?}", result.errors);
+    let series = db.get("net.rx;host=a").unwrap();
+    assert_eq!(series.item.datatype, DataType::Double);
+    assert_eq!(series.item.labels.get("host").map(|it| it.as_str()), Some("a"));
+    assert_eq!(db.range("net.rx;host=a", 0, u128::MAX).unwrap(), vec![
+        TSPoint { key: 1000, value: TSCacheValue::Double(10.0) },
+        TSPoint { key: 2000, value: TSCacheValue::Double(12.5) },
+    ]);
+
+    // integral values fit an existing Long series, fractions and strings do not
+    db.create(TSItem { tsName: "jobs".to_string(), capacity: 4, datatype: DataType::Long, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision: Default::default(), compression: Default::default() }).unwrap();
+    db.create(TSItem { tsName: "motd".to_string(), capacity: 4, datatype: DataType::String, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision: Default::default(), compression: Default::default() }).unwrap();
+    let result = write(&db, "jobs 3 1\njobs 3.5 2\nmotd 1 1\n");
+    assert_eq!((result.points, result.errors.len()), (1, 2));
+    assert_eq!(db.last("jobs").unwrap(), TSCacheValue::Long(3));
+}
+
+#[tokio::test]
+async fn plaintext_over_tcp() {
+    init();
+    let db = Arc::new(CacheDb::new());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (_stop, shutdown) = watch::channel(false);
+    tokio::spawn(graphite::serve_tcp(listener, db.clone(), Arc::new(Semaphore::new(16)), shutdown));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    // a line split across writes is written once it is complete
+    stream.write_all(b"tcp.a 1 1\ntcp.b 2").await.unwrap();
+    stream.flush().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    stream.write_all(b" 1\n").await.unwrap();
+    drop(stream);
+    for _ in 0..100 {
+        if db.last("tcp.b").is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(db.last("tcp.a").unwrap(), TSCacheValue::Double(1.0));
+    assert_eq!(db.query("tcp.b", u128::MAX).unwrap(), TSPoint { key: 1000, value: TSCacheValue::Double(2.0) });
+}
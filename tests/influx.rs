@@ -0,0 +1,137 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{watch, Semaphore};
+
+use time_cache::influx::{parse_line, write, FieldValue};
+use time_cache::{http, influx, io, CacheDb, Config, DataType, Precision, SaveTimePeriod, TSCacheValue, TSItem, TSPoint};
+
+mod common;
+
+// auto-created series take the default save period, keep them in memory
+fn init() {
+    common::init_with(Config { default_save_time: SaveTimePeriod::Nerve, ..Default::default() });
+}
+
+#[test]
+fn parse_escapes_and_field_types() {
+    let line = parse_line(r#"cpu\ load,host=a\,b,region=eu\=1 idle=1.5,count=3i,big=7u,up=t,msg="say \"hi\", ok" 1700000000000000000"#).unwrap();
+    assert_eq!(line.measurement, "cpu load");
+    assert_eq!(line.tags, vec![("host".to_string(), "a,b".to_string()), ("region".to_string(), "eu=1".to_string())]);
+    assert_eq!(line.fields, vec![
+        ("idle".to_string(), FieldValue::Float(1.5)),
+        ("count".to_string(), FieldValue::Integer(3)),
+        ("big".to_string(), FieldValue::Unsigned(7)),
+        ("up".to_string(), FieldValue::Boolean(true)),
+        ("msg".to_string(), FieldValue::String(r#"say "hi", ok"#.to_string())),
+    ]);
+    assert_eq!(line.timestamp, Some(1_700_000_000_000_000_000));
+    assert_eq!(line.series_name("idle", true), "cpu load.idle;host=a,b;region=eu=1");
+    assert_eq!(line.series_name("idle", false), "cpu load.idle");
+
+    assert_eq!(parse_line("mem free=2").unwrap().timestamp, None);
+    for bad in ["cpu", "cpu idle", "cpu,host idle=1", "cpu idle=1x", "cpu idle=1 later", ",host=a idle=1"] {
+        assert!(parse_line(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn write_creates_series_with_inferred_types() {
+    init();
+    let db = CacheDb::new();
+    let body = "# telegraf\nsys,host=a load=0.5,procs=12i 1700000000000000000\n\nsys,host=a load=0.75,procs=13i 1700000001000000000\n";
+    let result = write(&db, body, Precision::Nanos);
+    assert_eq!((result.points, result.errors.len()), (4, 0));
+
+    let load = db.get("sys.load;host=a").unwrap();
+    assert_eq!(load.item.datatype, DataType::Double);
+    assert_eq!(load.item.labels.get("host").map(|it| it.as_str()), Some("a"));
+    assert_eq!(db.get("sys.procs;host=a").unwrap().item.datatype, DataType::Long);
+    assert_eq!(db.range("sys.load;host=a", 0, u128::MAX).unwrap(), vec![
+        TSPoint { key: 1_700_000_000_000, value: TSCacheValue::Double(0.5) },
+        TSPoint { key: 1_700_000_001_000, value: TSCacheValue::Double(0.75) },
+    ]);
+
+    // integers widen into the existing Double series, strings do not fit it and bad lines are skipped
+    let result = write(&db, "sys,host=a load=1i 1700000002\nsys,host=a load=\"high\" 1700000003\nsys,host=a\n", Precision::Seconds);
+    assert_eq!(result.points, 1);
+    assert_eq!(result.errors.len(), 2);
+    assert!(result.errors[0].starts_with("line 2:"), "{:?}", result.errors);
+    assert_eq!(db.last("sys.load;host=a").unwrap(), TSCacheValue::Double(1.0));
+}
+
+#[test]
+fn lines_without_timestamp_are_stamped_by_the_server() {
+    init();
+    let db = CacheDb::new();
+    db.create(TSItem { tsName: "stamped.value".to_string(), capacity: 10, datatype: DataType::Double, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision: Precision::Seconds, compression: Default::default() }).unwrap();
+    // both land in the same second, the second one gets the next key instead of a duplicate
+    let result = write(&db, "stamped value=1\nstamped value=2\n", Precision::Nanos);
+    assert_eq!((result.points, result.errors.len()), (2, 0), "{:?}", result.errors);
+    let keys: Vec<u128> = db.range("stamped.value", 0, u128::MAX).unwrap().iter().map(|it| it.key).collect();
+    assert_eq!(keys.len(), 2);
+    assert!(keys[0] + 1 == keys[1] && keys[0] >= Precision::Seconds.now() - 5, "{:?}", keys);
+}
+
+#[tokio::test]
+async fn http_tcp_and_udp_ingestion() {
+    init();
+    let db = Arc::new(CacheDb::new());
+    let (_stop, shutdown) = watch::channel(false);
+    let connections = Arc::new(Semaphore::new(16));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let http_addr = listener.local_addr().unwrap();
+    tokio::spawn(http::serve(listener, db.clone(), connections.clone(), shutdown.clone()));
+    let body = "web,page=home hits=1i 1700000000000\nweb,page=home hits=oops 1700000001000\n";
+    let raw = format!("POST /write?db=x&precision=ms HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+    let mut stream = TcpStream::connect(http_addr).await.unwrap();
+    stream.write_all(raw.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+    assert!(response.contains(r#""written":1"#), "{}", response);
+    assert_eq!(db.range("web.hits;page=home", 0, u128::MAX).unwrap(), vec![TSPoint { key: 1_700_000_000_000, value: TSCacheValue::Long(1) }]);
+
+    // tag values with path separators are kept, the names are escaped in their directories
+    let body = "../../escape value=1 1700000000000\ndisk,path=/ used=1i 1700000000000\n";
+    let raw = format!("POST /write?db=x&precision=ms HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+    let mut stream = TcpStream::connect(http_addr).await.unwrap();
+    stream.write_all(raw.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+    for name in ["../../escape.value", "disk.used;path=/"] {
+        assert!(db.get(name).is_some(), "{}", name);
+        assert_eq!(io::series_dir(name).parent().unwrap(), Path::new(io::data_dir()));
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let tcp_addr = listener.local_addr().unwrap();
+    tokio::spawn(influx::serve_tcp(listener, db.clone(), connections.clone(), shutdown.clone()));
+    let mut stream = TcpStream::connect(tcp_addr).await.unwrap();
+    // a line split across writes is only stored once complete
+    stream.write_all(b"tcp value=1 1000000000\ntcp val").await.unwrap();
+    stream.flush().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    stream.write_all(b"ue=2 2000000000\n").await.unwrap();
+    drop(stream);
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let udp_addr = socket.local_addr().unwrap();
+    tokio::spawn(influx::serve_udp(socket, db.clone(), shutdown.clone()));
+    let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    sender.send_to(b"udp value=3 3000000000\n", udp_addr).await.unwrap();
+
+    for _ in 0..100 {
+        if db.range("tcp.value", 0, u128::MAX).map(|it| it.len()).unwrap_or(0) == 2 && db.last("udp.value").is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let keys: Vec<u128> = db.range("tcp.value", 0, u128::MAX).unwrap().iter().map(|it| it.key).collect();
+    assert_eq!(keys, vec![1000, 2000]);
+    assert_eq!(db.last("udp.value").unwrap(), TSCacheValue::Double(3.0));
+}
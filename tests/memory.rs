@@ -0,0 +1,122 @@
+use std::sync::Arc;
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{watch, Semaphore};
+
+use bytes::BytesMut;
+use rmp_serde::from_slice;
+
+use time_cache::method::{choose_method, MethodKind, CHUNK_POINTS};
+use time_cache::{http, CacheDb, DataType, ExceptionKind, SaveTimePeriod, SeriesInfo, TSCacheValue, TSItem, TSQueue, TSValue};
+
+mod common;
+
+fn item(name: &str, capacity: usize, datatype: DataType) -> TSItem {
+    TSItem { tsName: name.to_string(), capacity, datatype, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision: Default::default(), compression: Default::default() }
+}
+
+fn queue(capacity: usize, datatype: DataType) -> TSQueue {
+    TSQueue::new(Box::new(item("q", capacity, datatype)), capacity)
+}
+
+#[test]
+fn numeric_points_take_at_most_sixteen_bytes() {
+    for (datatype, per_point) in [(DataType::Double, 16), (DataType::Long, 16), (DataType::Float, 12)] {
+        let mut queue = queue(1000, datatype.clone());
+        let empty = queue.memory_usage();
+        // twice around the ring
+        for key in 1..=2000u128 {
+            let value = match datatype {
+                DataType::Float => TSCacheValue::Float(key as f32),
+                DataType::Long => TSCacheValue::Long(key as i64),
+                _ => TSCacheValue::Double(key as f64),
+            };
+            queue.insert(1_700_000_000_000_000_000 + key, value).unwrap();
+        }
+        assert_eq!(queue.count(), 1000);
+        assert_eq!(queue.memory_usage() - empty, 1000 * per_point, "{:?}", datatype);
+        assert_eq!(queue.query_first().unwrap().0, 1_700_000_000_000_001_001);
+        assert_eq!(queue.query_time(1_700_000_000_000_001_500).unwrap().0, 1_700_000_000_000_001_500);
+    }
+}
+
+#[test]
+fn strings_share_one_buffer_that_stays_bounded() {
+    let mut strings = queue(4, DataType::String);
+    for key in 1..=1000u128 {
+        strings.insert(key, TSCacheValue::String(format!("value-{}", key))).unwrap();
+    }
+    let values: Vec<TSCacheValue> = strings.query_times(0, u128::MAX).into_iter().map(|(_, value)| value).collect();
+    assert_eq!(values, (997..=1000).map(|key| TSCacheValue::String(format!("value-{}", key))).collect::<Vec<_>>());
+    assert_eq!(strings.query_last(), Some(TSCacheValue::String("value-1000".to_string())));
+    assert!(strings.memory_usage() < 512, "{}", strings.memory_usage());
+
+    let mut bytes = queue(2, DataType::ByteArray);
+    for key in 1..=5u128 {
+        bytes.insert(key, TSCacheValue::ByteArray(vec![key as u8; key as usize])).unwrap();
+    }
+    assert_eq!(bytes.query_times(0, 4), vec![(4, TSCacheValue::ByteArray(vec![4; 4]))]);
+}
+
+#[test]
+fn keys_are_offsets_from_the_first_one() {
+    let mut queue = queue(4, DataType::Long);
+    assert_eq!(queue.insert(1, TSCacheValue::Double(1.0)).unwrap_err().kind(), Some(ExceptionKind::SaveTypeError));
+    assert_eq!(queue.insert(0, TSCacheValue::Long(1)).unwrap_err().kind(), Some(ExceptionKind::TimeSerieError));
+    queue.insert(u128::MAX - u64::MAX as u128 - 1, TSCacheValue::Long(1)).unwrap();
+    queue.insert(u128::MAX - 1, TSCacheValue::Long(2)).unwrap();
+    assert_eq!(queue.insert(u128::MAX, TSCacheValue::Long(3)).unwrap_err().kind(), Some(ExceptionKind::TimeSerieError));
+    assert_eq!(queue.insert(u128::MAX - 1, TSCacheValue::Long(3)).unwrap_err().kind(), Some(ExceptionKind::TimeSerieError));
+    assert_eq!(queue.last_key(), Some(u128::MAX - 1));
+    assert_eq!(queue.query_time(u128::MAX - 2).unwrap(), (u128::MAX - u64::MAX as u128 - 1, TSCacheValue::Long(1)));
+}
+
+#[tokio::test]
+async fn memory_report_over_http() {
+    common::init();
+    let db = Arc::new(CacheDb::new());
+    db.create(item("small", 10, DataType::Long)).unwrap();
+    db.create(item("large", 1000, DataType::Double)).unwrap();
+    for key in 1..=100 {
+        db.set(TSValue { name: "large".to_string(), key, value: TSCacheValue::Double(key as f64) }).unwrap();
+    }
+    db.set(TSValue { name: "small".to_string(), key: 1, value: TSCacheValue::Long(1) }).unwrap();
+    let report = db.memory();
+    assert_eq!(report.iter().map(|it| (it.name.as_str(), it.points)).collect::<Vec<_>>(), vec![("large", 100), ("small", 1)]);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let (_stop, shutdown) = watch::channel(false);
+    tokio::spawn(http::serve(listener, db.clone(), Arc::new(Semaphore::new(16)), shutdown));
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    stream.write_all(b"GET /memory HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    let body: Value = serde_json::from_str(response.split_once("\r\n\r\n").unwrap().1).unwrap();
+    assert_eq!(body["points"], 101);
+    assert_eq!(body["bytes"], report.iter().map(|it| it.bytes).sum::<usize>());
+    assert_eq!(body["series"][0]["name"], "large");
+    assert_eq!(body["series"][0]["datatype"], "Double");
+}
+
+#[test]
+fn list_reports_the_compression_ratio() {
+    common::init();
+    let db = CacheDb::new();
+    db.create(item("packed", CHUNK_POINTS * 2, DataType::Long)).unwrap();
+    db.create(item("unpacked", 10, DataType::Long)).unwrap();
+    for key in 1..=CHUNK_POINTS as u128 * 2 {
+        db.set(TSValue { name: "packed".to_string(), key, value: TSCacheValue::Long(7) }).unwrap();
+    }
+    let mut out = BytesMut::new();
+    choose_method(MethodKind::List.as_code()).unwrap().do_method(&[], &db, &mut out).unwrap();
+    let listed: Vec<SeriesInfo> = from_slice(&out).unwrap();
+    assert_eq!(listed.iter().map(|it| it.item.tsName.as_str()).collect::<Vec<_>>(), vec!["packed", "unpacked"]);
+    assert!(listed[0].compression_ratio > 1.5, "{}", listed[0].compression_ratio);
+    assert_eq!(listed[1].compression_ratio, 1.0);
+    // clients decoding the reply as items still read it
+    let items: Vec<TSItem> = from_slice(&out).unwrap();
+    assert_eq!(items[0].capacity, CHUNK_POINTS * 2);
+}
@@ -0,0 +1,104 @@
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+
+use time_cache::alert::{AlertEvent, AlertState};
+use time_cache::notify::{deliver, CommandNotifier, Dedup, HttpNotifier, NotifierConfig, NotifierKind};
+
+fn event(state: AlertState) -> AlertEvent {
+    AlertEvent {
+        rule: "cpu_high".to_string(),
+        ts_name: "cpu".to_string(),
+        state,
+        value: Some(95.0),
+        time: 1_000,
+    }
+}
+
+// answers `statuses` in order and reports every request body
+fn stand_in_server(statuses: Vec<u16>) -> (String, mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for status in statuses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut data = [0u8; 1024];
+            loop {
+                let n = stream.read(&mut data).unwrap();
+                request.extend_from_slice(&data[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some(i) = text.find("\r\n\r\n") {
+                    let length: usize = text.lines()
+                        .find_map(|line| line.strip_prefix("Content-Length: "))
+                        .unwrap().trim().parse().unwrap();
+                    if request.len() >= i + 4 + length {
+                        sender.send(text[i + 4..].to_string()).unwrap();
+                        break;
+                    }
+                }
+            }
+            stream.write_all(format!("HTTP/1.1 {} X\r\nContent-Length: 0\r\n\r\n", status).as_bytes()).unwrap();
+        }
+    });
+    (format!("http://{}/hook", addr), receiver)
+}
+
+#[test]
+fn http_notifier_retries_until_success() {
+    let (url, bodies) = stand_in_server(vec![500, 503, 200]);
+    let notifier = HttpNotifier::new(&url).unwrap();
+    deliver(&notifier, &event(AlertState::Firing), 3, 1).unwrap();
+    let received: Vec<String> = bodies.try_iter().collect();
+    assert_eq!(received.len(), 3);
+    let body: AlertEvent = serde_json::from_str(&received[2]).unwrap();
+    assert_eq!(body, event(AlertState::Firing));
+}
+
+#[test]
+fn http_notifier_gives_up() {
+    let (url, _bodies) = stand_in_server(vec![500, 500]);
+    let notifier = HttpNotifier::new(&url).unwrap();
+    let err = deliver(&notifier, &event(AlertState::Firing), 1, 1).unwrap_err();
+    assert_eq!(err.code, 4007);
+    assert!(HttpNotifier::new("https://example.com").is_err());
+}
+
+#[test]
+fn command_notifier_writes_stdin() {
+    let out = std::env::temp_dir().join(format!("tc-notify-{}.json", std::process::id()));
+    let notifier = CommandNotifier::new("sh", &["-c".to_string(), format!("cat > {}", out.display())]);
+    deliver(&notifier, &event(AlertState::Inactive), 0, 1).unwrap();
+    let body: AlertEvent = serde_json::from_slice(&std::fs::read(&out).unwrap()).unwrap();
+    assert_eq!(body.state, AlertState::Inactive);
+    std::fs::remove_file(out).unwrap();
+
+    let failing = CommandNotifier::new("sh", &["-c".to_string(), "exit 3".to_string()]);
+    assert!(deliver(&failing, &event(AlertState::Firing), 0, 1).is_err());
+}
+
+#[test]
+fn dedup_drops_firings_of_a_flapping_rule() {
+    let config = NotifierConfig {
+        name: "hook".to_string(),
+        kind: NotifierKind::Http { url: "http://127.0.0.1:1/".to_string() },
+        retries: 0,
+        backoff_ms: 0,
+        dedup_secs: 60,
+    };
+    let mut dedup = Dedup::default();
+    assert!(dedup.admit(&config, &event(AlertState::Firing)));
+    assert!(dedup.admit(&config, &event(AlertState::Inactive)));
+    // the rule flaps within the window, neither the new Firing nor its Inactive go out
+    assert!(!dedup.admit(&config, &event(AlertState::Firing)));
+    assert!(!dedup.admit(&config, &event(AlertState::Inactive)));
+    assert!(!dedup.admit(&config, &event(AlertState::Firing)));
+
+    let other = NotifierConfig { name: "other".to_string(), ..config.clone() };
+    assert!(dedup.admit(&other, &event(AlertState::Firing)));
+    let eager = NotifierConfig { dedup_secs: 0, ..config };
+    assert!(dedup.admit(&eager, &event(AlertState::Firing)));
+    assert!(dedup.admit(&eager, &event(AlertState::Firing)));
+}
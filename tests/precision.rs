@@ -0,0 +1,76 @@
+use bytes::BytesMut;
+use rmp_serde::{from_slice, to_vec_named};
+
+use time_cache::method::{choose_method, MethodKind, QueryParam, RangeParam};
+use time_cache::{influx, CacheDb, Config, DataType, Precision, SaveTimePeriod, TSCacheValue, TSItem, TSPoint, TSValue};
+
+mod common;
+
+fn init() {
+    common::init_with(Config { default_save_time: SaveTimePeriod::Nerve, ..Default::default() });
+}
+
+fn item(name: &str, precision: Precision) -> TSItem {
+    TSItem { tsName: name.to_string(), capacity: 10, datatype: DataType::Long, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision, compression: Default::default() }
+}
+
+fn set(db: &CacheDb, name: &str, key: u128, value: i64) {
+    db.set(TSValue { name: name.to_string(), key, value: TSCacheValue::Long(value) }).unwrap();
+}
+
+fn keys(points: &[TSPoint]) -> Vec<u128> {
+    points.iter().map(|it| it.key).collect()
+}
+
+#[test]
+fn conversions_truncate_towards_coarser_units() {
+    assert_eq!(Precision::Seconds.convert(2, Precision::Nanos), 2_000_000_000);
+    assert_eq!(Precision::Micros.convert(2_999, Precision::Millis), 2);
+    assert_eq!(Precision::Millis.convert(u128::MAX, Precision::Nanos), u128::MAX);
+    // the whole last millisecond is inside an inclusive end
+    assert_eq!(Precision::Millis.convert_end(2, Precision::Micros), 2_999);
+    assert_eq!(Precision::Nanos.convert_end(2_999_999, Precision::Millis), 2);
+    assert_eq!("u".parse::<Precision>().unwrap(), Precision::Micros);
+    assert!("m".parse::<Precision>().is_err());
+}
+
+#[test]
+fn range_and_query_in_another_unit() {
+    init();
+    let db = CacheDb::new();
+    db.create(item("fine", Precision::Micros)).unwrap();
+    for (key, value) in [(999, 1), (1_000_500, 2), (2_000_000, 3), (2_999_999, 4), (3_000_000, 5)] {
+        set(&db, "fine", key, value);
+    }
+    assert_eq!(keys(&db.range("fine", 1_000_000, 2_000_000).unwrap()), vec![1_000_500, 2_000_000]);
+    assert_eq!(keys(&db.range_as("fine", 1_000, 2_999, Precision::Millis).unwrap()), vec![1_000, 2_000, 2_999]);
+    assert_eq!(db.query_as("fine", 2_999, Precision::Millis).unwrap(), TSPoint { key: 2_999, value: TSCacheValue::Long(4) });
+    assert_eq!(db.query_as("fine", 0, Precision::Seconds).unwrap().key, 0);
+
+    let mut out = BytesMut::new();
+    let param = RangeParam { name: "fine".to_string(), start: 2, end: 2, precision: Some(Precision::Seconds) };
+    choose_method(MethodKind::Range.as_code()).unwrap().do_method(&to_vec_named(&param).unwrap(), &db, &mut out).unwrap();
+    assert_eq!(keys(&from_slice::<Vec<TSPoint>>(&out).unwrap()), vec![2, 2]);
+
+    let mut out = BytesMut::new();
+    let param = QueryParam { name: "fine".to_string(), time: 1_500_000, precision: None };
+    choose_method(MethodKind::Query.as_code()).unwrap().do_method(&to_vec_named(&param).unwrap(), &db, &mut out).unwrap();
+    assert_eq!(from_slice::<TSPoint>(&out).unwrap().key, 1_000_500);
+}
+
+#[test]
+fn receivers_and_server_stamps_use_the_series_unit() {
+    init();
+    let db = CacheDb::new();
+    db.create(item("stamped", Precision::Nanos)).unwrap();
+    let before = Precision::Nanos.now();
+    let key = db.set(TSValue { name: "stamped".to_string(), key: 0, value: TSCacheValue::Long(1) }).unwrap();
+    assert!(key >= before && key <= Precision::Nanos.now(), "{}", key);
+
+    db.create(item("disk.used", Precision::Seconds)).unwrap();
+    db.create(item("disk.free", Precision::Nanos)).unwrap();
+    let result = influx::write(&db, "disk used=1i,free=2i 1700000000123456789", Precision::Nanos);
+    assert_eq!((result.points, result.errors.len()), (2, 0));
+    assert_eq!(db.query("disk.used", u128::MAX).unwrap().key, 1_700_000_000);
+    assert_eq!(db.query("disk.free", u128::MAX).unwrap().key, 1_700_000_000_123_456_789);
+}
@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use prost::Message;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{watch, Semaphore};
+
+use time_cache::prometheus::{compress, exposition, label_set, read, series_name, write, Label, LabelMatcher, MatchType, Query, ReadRequest, ReadResponse, Sample, Selector, TimeSeries, WriteRequest};
+use time_cache::{http, CacheDb, Config, DataType, SaveTimePeriod, TSCacheValue, TSItem, TSValue};
+
+mod common;
+
+// payloads in the wire format Prometheus sends, encoded without prost:
+// two series (up and http_requests_total) with two samples each plus metric metadata,
+// and a read of [1700000000000, 1700000010000] for __name__=~"up|http_requests_total", job!="api" with hints
+const WRITE_REQUEST: &[u8] = include_bytes!("data/prometheus_write.snappy");
+const READ_REQUEST: &[u8] = include_bytes!("data/prometheus_read.snappy");
+
+fn init() {
+    common::init_with(Config { default_save_time: SaveTimePeriod::Nerve, ..Default::default() });
+}
+
+fn decode_response(body: &[u8]) -> ReadResponse {
+    let body = snap::raw::Decoder::new().decompress_vec(body).unwrap();
+    ReadResponse::decode(body.as_slice()).unwrap()
+}
+
+fn labels(pairs: &[(&str, &str)]) -> Vec<Label> {
+    pairs.iter().map(|(name, value)| Label { name: name.to_string(), value: value.to_string() }).collect()
+}
+
+#[test]
+fn series_names_round_trip_to_labels() {
+    let map: BTreeMap<String, String> = [("__name__", "up"), ("job", "node"), ("instance", "a:9100")].iter()
+        .map(|(key, value)| (key.to_string(), value.to_string())).collect();
+    let name = series_name(&map).unwrap();
+    assert_eq!(name, "up;instance=a:9100;job=node");
+    let item = TSItem { tsName: name, capacity: 1, datatype: DataType::Double, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision: Default::default(), compression: Default::default() };
+    assert_eq!(label_set(&item), map);
+    assert!(series_name(&BTreeMap::new()).is_err());
+}
+
+#[test]
+fn recorded_write_and_read() {
+    init();
+    let db = CacheDb::new();
+    let result = write(&db, WRITE_REQUEST).unwrap();
+    assert_eq!((result.points, result.errors.len()), (4, 0));
+    let up = db.get("up;instance=localhost:9090;job=prometheus").unwrap();
+    assert_eq!(up.item.datatype, DataType::Double);
+    assert_eq!(up.item.labels.get("job").map(|it| it.as_str()), Some("prometheus"));
+    assert_eq!(db.last("http_requests_total;code=200;job=api").unwrap(), TSCacheValue::Double(1043.0));
+
+    // the same batch again: every sample is older than what is stored
+    let result = write(&db, WRITE_REQUEST).unwrap();
+    assert_eq!((result.points, result.errors.len(), result.busy), (0, 4, false));
+
+    let response = decode_response(&read(&db, READ_REQUEST).unwrap());
+    assert_eq!(response.results.len(), 1);
+    assert_eq!(response.results[0].timeseries, vec![TimeSeries {
+        labels: labels(&[("__name__", "up"), ("instance", "localhost:9090"), ("job", "prometheus")]),
+        samples: vec![Sample { value: 1.0, timestamp: 1_700_000_000_000 }],
+    }]);
+
+    assert!(write(&db, b"not snappy").is_err());
+    let bad_regex = ReadRequest { queries: vec![Query {
+        start_timestamp_ms: 0,
+        end_timestamp_ms: i64::MAX,
+        matchers: vec![LabelMatcher { r#type: MatchType::Re as i32, name: "job".to_string(), value: "(".to_string() }],
+    }] };
+    assert!(read(&db, &compress(&bad_regex.encode_to_vec())).is_err());
+}
+
+async fn post(addr: &str, path: &str, body: &[u8]) -> (u16, Vec<u8>) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let head = format!("POST {} HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Encoding: snappy\r\nContent-Type: application/x-protobuf\r\nContent-Length: {}\r\n\r\n", path, body.len());
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream.write_all(body).await.unwrap();
+    let mut response = vec![];
+    stream.read_to_end(&mut response).await.unwrap();
+    let status = String::from_utf8_lossy(&response[9..12]).parse().unwrap();
+    let at = response.windows(4).position(|it| it == b"\r\n\r\n").unwrap() + 4;
+    (status, response[at..].to_vec())
+}
+
+#[tokio::test]
+async fn remote_storage_over_http() {
+    init();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let (_stop, shutdown) = watch::channel(false);
+    tokio::spawn(http::serve(listener, Arc::new(CacheDb::new()), Arc::new(Semaphore::new(16)), shutdown));
+
+    let write_request = WriteRequest { timeseries: vec![TimeSeries {
+        labels: labels(&[("__name__", "node_load1"), ("host", "a")]),
+        samples: vec![Sample { value: 0.25, timestamp: 1000 }, Sample { value: 0.5, timestamp: 2000 }],
+    }] };
+    let (status, _) = post(&addr, "/api/v1/write", &compress(&write_request.encode_to_vec())).await;
+    assert_eq!(status, 204);
+    let (status, _) = post(&addr, "/api/v1/write", b"garbage").await;
+    assert_eq!(status, 400);
+
+    let read_request = ReadRequest { queries: vec![Query {
+        start_timestamp_ms: 1500,
+        end_timestamp_ms: 3000,
+        matchers: vec![LabelMatcher { r#type: MatchType::Eq as i32, name: "__name__".to_string(), value: "node_load1".to_string() }],
+    }] };
+    let (status, body) = post(&addr, "/api/v1/read", &compress(&read_request.encode_to_vec())).await;
+    assert_eq!(status, 200);
+    let series = &decode_response(&body).results[0].timeseries;
+    assert_eq!(series.len(), 1);
+    assert_eq!(series[0].samples, vec![Sample { value: 0.5, timestamp: 2000 }]);
+}
+
+fn numeric(db: &CacheDb, name: &str, labels: &[(&str, &str)], key: u128, value: TSCacheValue) {
+    let labels = labels.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+    let datatype = match value { TSCacheValue::Long(_) => DataType::Long, TSCacheValue::String(_) => DataType::String, _ => DataType::Double };
+    db.create(TSItem { tsName: name.to_string(), capacity: 4, datatype, saveTime: SaveTimePeriod::Nerve, labels, precision: Default::default(), compression: Default::default() }).unwrap();
+    db.set(TSValue { name: name.to_string(), key, value }).unwrap();
+}
+
+#[test]
+fn exposition_of_latest_values() {
+    init();
+    let db = CacheDb::new();
+    numeric(&db, "cpu.usage;host=a", &[("host", "a"), ("dc", "eu \"1\"")], 1000, TSCacheValue::Double(0.5));
+    numeric(&db, "cpu.usage;host=b", &[], 2000, TSCacheValue::Double(f64::NAN));
+    numeric(&db, "2xx-count", &[], 3000, TSCacheValue::Long(7));
+    numeric(&db, "motd", &[], 4000, TSCacheValue::String("hi".to_string()));
+    db.create(TSItem { tsName: "empty".to_string(), capacity: 4, datatype: DataType::Double, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision: Default::default(), compression: Default::default() }).unwrap();
+
+    let all = Selector::new(&[], &[]).unwrap();
+    assert_eq!(exposition(&db, &all), concat!(
+        "# TYPE _2xx_count gauge\n",
+        "_2xx_count 7 3000\n",
+        "# TYPE cpu_usage gauge\n",
+        "cpu_usage{dc=\"eu \\\"1\\\"\",host=\"a\"} 0.5 1000\n",
+        "cpu_usage{host=\"b\"} NaN 2000\n",
+    ));
+    let selected = Selector::new(&["cpu\\..*".to_string()], &[".*host=b".to_string()]).unwrap();
+    assert_eq!(exposition(&db, &selected), "# TYPE cpu_usage gauge\ncpu_usage{dc=\"eu \\\"1\\\"\",host=\"a\"} 0.5 1000\n");
+    // patterns match whole names
+    assert_eq!(exposition(&db, &Selector::new(&["cpu".to_string()], &[]).unwrap()), "");
+    assert!(Selector::new(&["(".to_string()], &[]).is_err());
+}
+
+#[tokio::test]
+async fn metrics_endpoint() {
+    init();
+    let db = Arc::new(CacheDb::new());
+    numeric(&db, "mem.free", &[], 1000, TSCacheValue::Long(42));
+    numeric(&db, "mem.used", &[], 1000, TSCacheValue::Long(58));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let (_stop, shutdown) = watch::channel(false);
+    tokio::spawn(http::serve(listener, db, Arc::new(Semaphore::new(16)), shutdown));
+
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    stream.write_all(b"GET /metrics?exclude=mem%5C.used HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4"), "{}", response);
+    assert!(response.ends_with("\r\n\r\n# TYPE mem_free gauge\nmem_free 42 1000\n"), "{}", response);
+}
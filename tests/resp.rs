@@ -0,0 +1,119 @@
+use std::io::Cursor;
+use std::sync::Arc;
+use bytes::BytesMut;
+use mini_redis::Frame;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{watch, Semaphore};
+
+use time_cache::db::CacheDb;
+use time_cache::resp;
+
+mod common;
+
+async fn start_resp() -> String {
+    common::init();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let (stop, shutdown) = watch::channel(false);
+    tokio::spawn(async move {
+        let _stop = stop;
+        resp::serve(listener, Arc::new(CacheDb::new()), Arc::new(Semaphore::new(16)), shutdown).await;
+    });
+    addr
+}
+
+fn encode(args: &[&str]) -> Vec<u8> {
+    let mut buff = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        buff.extend_from_slice(format!("${}\r\n{}\r\n", arg.len(), arg).as_bytes());
+    }
+    buff
+}
+
+async fn read_reply(stream: &mut TcpStream, buff: &mut BytesMut) -> Frame {
+    loop {
+        let mut cursor = Cursor::new(&buff[..]);
+        if Frame::check(&mut cursor).is_ok() {
+            let length = cursor.position() as usize;
+            cursor.set_position(0);
+            let frame = Frame::parse(&mut cursor).unwrap();
+            let _ = buff.split_to(length);
+            return frame;
+        }
+        assert!(stream.read_buf(buff).await.unwrap() > 0, "connection closed");
+    }
+}
+
+// flattens a reply to space separated words, errors start with `error: `
+fn show(frame: &Frame) -> String {
+    match frame {
+        Frame::Simple(text) => text.clone(),
+        Frame::Error(text) => format!("error: {}", text),
+        Frame::Integer(value) => value.to_string(),
+        Frame::Bulk(bytes) => String::from_utf8_lossy(bytes).to_string(),
+        Frame::Null => "(nil)".to_string(),
+        Frame::Array(items) => items.iter().map(show).collect::<Vec<_>>().join(" "),
+    }
+}
+
+async fn call(stream: &mut TcpStream, buff: &mut BytesMut, args: &[&str]) -> String {
+    stream.write_all(&encode(args)).await.unwrap();
+    show(&read_reply(stream, buff).await)
+}
+
+#[tokio::test]
+async fn ts_commands_round_trip() {
+    let addr = start_resp().await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    let mut buff = BytesMut::new();
+    let name = format!("resp-{}", std::process::id());
+    let name = name.as_str();
+
+    assert_eq!(call(&mut stream, &mut buff, &["PING"]).await, "PONG");
+    assert_eq!(call(&mut stream, &mut buff, &["TS.CREATE", name, "CAPACITY", "3", "SAVETIME", "Nerve", "LABELS", "host", "a"]).await, "OK");
+    assert!(call(&mut stream, &mut buff, &["TS.CREATE", name]).await.starts_with("error: ERR TSDB: duplicate"));
+    assert_eq!(call(&mut stream, &mut buff, &["ts.get", name]).await, "");
+
+    assert_eq!(call(&mut stream, &mut buff, &["TS.ADD", name, "1000", "1.5"]).await, "1000");
+    assert_eq!(call(&mut stream, &mut buff, &["TS.MADD", name, "2000", "2.5", name, "1500", "9", name, "3000", "x"]).await,
+        "2000 error: ERR TSDB: current key:1500 must be greater than last time error: ERR TSDB: invalid value");
+    assert_eq!(call(&mut stream, &mut buff, &["TS.ADD", name, "4000", "4"]).await, "4000");
+    assert_eq!(call(&mut stream, &mut buff, &["TS.ADD", name, "5000", "5"]).await, "5000");
+    assert!(call(&mut stream, &mut buff, &["TS.ADD", "missing", "*", "1"]).await.contains("does not exist"));
+
+    assert_eq!(call(&mut stream, &mut buff, &["TS.GET", name]).await, "5000 5");
+    // capacity 3 keeps the newest three points
+    assert_eq!(call(&mut stream, &mut buff, &["TS.RANGE", name, "-", "+"]).await, "2000 2.5 4000 4 5000 5");
+    assert_eq!(call(&mut stream, &mut buff, &["TS.RANGE", name, "2500", "+", "COUNT", "1"]).await, "4000 4");
+    assert_eq!(call(&mut stream, &mut buff, &["TS.RANGE", name, "-", "+", "AGGREGATION", "sum", "3000"]).await, "0 2.5 3000 9");
+    let info = call(&mut stream, &mut buff, &["TS.INFO", name]).await;
+    // the memory usage depends on the platform's struct sizes
+    let (head, rest) = info.split_once(" memoryUsage ").unwrap();
+    let (usage, rest) = rest.split_once(' ').unwrap();
+    assert!(usage.parse::<usize>().unwrap() > 0, "{}", info);
+    assert_eq!(format!("{} {}", head, rest),
+        "totalSamples 3 compressionRatio 1.00 firstTimestamp 2000 lastTimestamp 5000 retentionTime 0 capacity 3 datatype Double saveTime Nerve precision ms compression none labels host a");
+    assert!(call(&mut stream, &mut buff, &["FLUSHALL"]).await.starts_with("error: ERR unknown command"));
+}
+
+#[tokio::test]
+async fn pipelined_commands_and_protocol_errors() {
+    let addr = start_resp().await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    let mut buff = BytesMut::new();
+    let mut pipeline = encode(&["PING"]);
+    pipeline.extend(encode(&["ECHO", "hi"]));
+    pipeline.extend(encode(&["QUIT"]));
+    stream.write_all(&pipeline).await.unwrap();
+    assert_eq!(show(&read_reply(&mut stream, &mut buff).await), "PONG");
+    assert_eq!(show(&read_reply(&mut stream, &mut buff).await), "hi");
+    assert_eq!(show(&read_reply(&mut stream, &mut buff).await), "OK");
+    assert_eq!(stream.read_buf(&mut buff).await.unwrap(), 0);
+
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    stream.write_all(b"?garbage\r\n").await.unwrap();
+    let reply = show(&read_reply(&mut stream, &mut buff).await);
+    assert!(reply.starts_with("error: ERR Protocol error"), "{}", reply);
+    assert_eq!(stream.read_buf(&mut buff).await.unwrap(), 0);
+}
@@ -0,0 +1,114 @@
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::AbortHandle;
+use time_cache_client::{blocking, Client, DataType, Error, ErrorKind, SaveTimePeriod, TSCacheValue, TSItem, TSPoint, TSValue};
+
+use time_cache::db::CacheDb;
+use time_cache::handle;
+
+mod common;
+
+// the returned handles abort the open server connections, like an idle timeout would
+async fn start_server() -> (String, Arc<Mutex<Vec<AbortHandle>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    common::init();
+    let db = Arc::new(CacheDb::new());
+    let connections = Arc::new(Mutex::new(vec![]));
+    let handles = connections.clone();
+    tokio::spawn(async move {
+        let (_stop, shutdown) = watch::channel(false);
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            let task = tokio::spawn(handle::serve(socket, db.clone(), shutdown.clone()));
+            handles.lock().unwrap().push(task.abort_handle());
+        }
+    });
+    (addr, connections)
+}
+
+fn series(name: &str) -> TSItem {
+    TSItem { capacity: Some(4), save_time: Some(SaveTimePeriod::Nerve), ..TSItem::new(name, DataType::Long) }
+}
+
+fn point(name: &str, key: u128) -> TSValue {
+    TSValue::new(name, key, TSCacheValue::Long(key as i64 * 10))
+}
+
+#[tokio::test]
+async fn async_client_round_trip() {
+    let (addr, _) = start_server().await;
+    let client = Client::connect(&addr).await.unwrap();
+    client.create(&series("async-a")).await.unwrap();
+    client.create(&series("async-b")).await.unwrap();
+    assert_eq!(client.set(&point("async-a", 1)).await.unwrap(), 1);
+    let keys = client.set_batch(&(2..=6).map(|key| point("async-a", key)).collect::<Vec<_>>()).await.unwrap();
+    assert_eq!(keys, vec![2, 3, 4, 5, 6]);
+
+    assert_eq!(client.get("async-a").await.unwrap(), TSCacheValue::Long(60));
+    // capacity 4 keeps keys 3..=6
+    let keys: Vec<u128> = client.range("async-a", 0, 5).await.unwrap().iter().map(|it| it.key).collect();
+    assert_eq!(keys, vec![3, 4, 5]);
+    assert_eq!(client.query("async-a", 100).await.unwrap(), TSPoint { key: 6, value: TSCacheValue::Long(60) });
+
+    let names: Vec<String> = client.list(Some("async-")).await.unwrap().into_iter().map(|it| it.ts_name).collect();
+    assert_eq!(names, vec!["async-a", "async-b"]);
+    client.drop("async-b").await.unwrap();
+    assert_eq!(client.list(Some("async-")).await.unwrap().len(), 1);
+
+    let e = client.get("async-b").await.unwrap_err();
+    assert_eq!(e.kind(), Some(ErrorKind::Name));
+    let e = client.set(&point("async-a", 2)).await.unwrap_err();
+    assert!(matches!(e, Error::Server { code: 4004, .. }));
+    assert!(!e.is_retryable());
+}
+
+#[tokio::test]
+async fn async_client_reconnects_after_server_closed_connection() {
+    let (addr, connections) = start_server().await;
+    let client = Client::connect(&addr).await.unwrap();
+    client.create(&series("reconnect")).await.unwrap();
+    connections.lock().unwrap().drain(..).for_each(|it| it.abort());
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    client.set(&point("reconnect", 1)).await.unwrap();
+    assert_eq!(client.get("reconnect").await.unwrap(), TSCacheValue::Long(10));
+    assert_eq!(connections.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn async_clients_share_the_pool() {
+    let (addr, connections) = start_server().await;
+    let client = Client::connect(&addr).await.unwrap();
+    client.create(&series("pool")).await.unwrap();
+    for key in 1..=20 {
+        client.clone().set(&point("pool", key)).await.unwrap();
+    }
+    // sequential calls reuse the first connection
+    assert_eq!(connections.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn blocking_client_round_trip() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let (addr, _) = runtime.block_on(start_server());
+    let client = blocking::Client::connect(&addr).unwrap();
+    client.create(&series("blocking")).unwrap();
+    client.set_batch(&[point("blocking", 1), point("blocking", 2)]).unwrap();
+    assert_eq!(client.get("blocking").unwrap(), TSCacheValue::Long(20));
+    assert_eq!(client.range("blocking", 0, u128::MAX).unwrap().len(), 2);
+
+    let e = client.create(&series("blocking")).unwrap_err();
+    assert_eq!(e.kind(), Some(ErrorKind::Name));
+    // the connection stays usable after a server exception
+    assert_eq!(client.query("blocking", 1).unwrap().key, 1);
+}
+
+#[test]
+fn connect_fails_fast_without_server() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    drop(listener);
+    assert!(matches!(blocking::Client::connect(&addr), Err(Error::Io(_))));
+}
@@ -0,0 +1,53 @@
+use std::fs;
+use std::path::Path;
+
+use time_cache::db::CacheDb;
+use time_cache::entity::{Compression, DataType, SaveTimePeriod, TSCacheValue, TSItem, TSValue};
+use time_cache::io::read_all_items;
+use time_cache::method::TSQueue;
+use time_cache::io;
+
+mod common;
+
+fn segment_points(dir: &Path) -> Vec<(u128, TSCacheValue)> {
+    let mut points = vec![];
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            points.extend(segment_points(&path));
+        } else {
+            // the segment is sealed on the way out
+            assert!(fs::read(&path).unwrap().starts_with(io::ZSTD_MAGIC));
+            points.extend(io::read_segment(&path).unwrap().into_iter().map(|it| (it.key, it.value)));
+        }
+    }
+    points
+}
+
+#[test]
+fn shutdown_flushes_writers_and_catalog() {
+    common::init();
+    let name = format!("shutdown-{}", std::process::id());
+    let item = TSItem {
+        tsName: name.clone(),
+        capacity: 10,
+        datatype: DataType::Long,
+        saveTime: SaveTimePeriod::Hour,
+        labels: Default::default(),
+        precision: Default::default(),
+        compression: Compression::Zstd,
+    };
+    let db = CacheDb::new();
+    db.create_new_item(item.clone(), TSQueue::new(Box::new(item), 10)).unwrap();
+    let mut value = TSValue { name: name.clone(), key: 1, value: TSCacheValue::Long(7) };
+    db.insert_new_value(&mut value).unwrap();
+
+    let dir = format!("{}/{}", io::data_dir(), name);
+    db.shutdown();
+    assert_eq!(segment_points(Path::new(&dir)), vec![(1, TSCacheValue::Long(7))]);
+
+    let mut items = vec![];
+    read_all_items(&mut items).unwrap();
+    assert!(items.iter().any(|it| it.tsName == name));
+    fs::remove_dir_all(io::data_dir()).unwrap();
+}
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+
+use time_cache::statsd::{parse_line, Aggregator, Sample};
+use time_cache::{statsd, CacheDb, Config, DataType, SaveTimePeriod, TSCacheValue};
+
+mod common;
+
+fn init() {
+    common::init_with(Config { default_save_time: SaveTimePeriod::Nerve, ..Default::default() });
+}
+
+fn add(aggregator: &mut Aggregator, lines: &[&str]) {
+    lines.iter().for_each(|line| aggregator.add(parse_line(line).unwrap()));
+}
+
+#[test]
+fn parse_types_rates_and_tags() {
+    assert_eq!(parse_line("hits:2|c|@0.5").unwrap().sample, Sample::Counter(4.0));
+    assert_eq!(parse_line("temp:-3|g").unwrap().sample, Sample::GaugeDelta(-3.0));
+    assert_eq!(parse_line("temp:3|g").unwrap().sample, Sample::Gauge(3.0));
+    assert_eq!(parse_line("rt:12|ms|@0.1").unwrap().sample, Sample::Timer { value: 12.0, rate: 0.1 });
+    assert_eq!(parse_line("users:bob|s").unwrap().sample, Sample::Set("bob".to_string()));
+    let metric = parse_line("rt:1|h|#route:/a,env:prod").unwrap();
+    assert_eq!(metric.name, "rt");
+    assert_eq!(metric.tags.get("env").map(|it| it.as_str()), Some("prod"));
+    for bad in ["hits", "hits:1", ":1|c", "hits:x|c", "hits:1|q", "hits:1|c|@2", "hits:1|c|#env", "hits:1|c|x"] {
+        assert!(parse_line(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn flush_writes_derived_series() {
+    init();
+    let db = CacheDb::new();
+    let mut aggregator = Aggregator::default();
+    add(&mut aggregator, &["hits:1|c", "hits:2|c|@0.5", "temp:20|g", "temp:+2|g", "users:a|s", "users:b|s", "users:a|s"]);
+    add(&mut aggregator, &["rt:1|ms|#env:prod"]);
+    (2..=20).for_each(|n| aggregator.add(parse_line(&format!("rt:{}|ms|#env:prod", n)).unwrap()));
+    let result = aggregator.flush(&db, 1000, &[95.0, 99.9], true);
+    assert_eq!((result.points, result.errors.len()), (7, 0));
+    assert!(aggregator.is_empty());
+
+    assert_eq!(db.last("hits.count").unwrap(), TSCacheValue::Double(5.0));
+    assert_eq!(db.last("temp").unwrap(), TSCacheValue::Double(22.0));
+    assert_eq!(db.last("users").unwrap(), TSCacheValue::Double(2.0));
+    assert_eq!(db.last("rt.count;env=prod").unwrap(), TSCacheValue::Double(20.0));
+    assert_eq!(db.last("rt.sum;env=prod").unwrap(), TSCacheValue::Double(210.0));
+    assert_eq!(db.last("rt.p95;env=prod").unwrap(), TSCacheValue::Double(19.0));
+    assert_eq!(db.last("rt.p99_9;env=prod").unwrap(), TSCacheValue::Double(20.0));
+    let rt = db.get("rt.p95;env=prod").unwrap();
+    assert_eq!(rt.item.datatype, DataType::Double);
+    assert_eq!(rt.item.labels.get("env").map(|it| it.as_str()), Some("prod"));
+
+    // a delta alone applies to the stored gauge, nothing else is written again
+    add(&mut aggregator, &["temp:-5|g"]);
+    assert_eq!(aggregator.flush(&db, 2000, &[95.0], true).points, 1);
+    assert_eq!(db.query("temp", u128::MAX).unwrap().value, TSCacheValue::Double(17.0));
+    assert_eq!(db.range("hits.count", 0, u128::MAX).unwrap().len(), 1);
+
+    add(&mut aggregator, &["unknown:1|c"]);
+    let result = aggregator.flush(&db, 3000, &[95.0], false);
+    assert_eq!((result.points, result.errors.len()), (0, 1));
+    assert!(db.get("unknown.count").is_none());
+}
+
+#[tokio::test]
+async fn udp_flushes_on_shutdown() {
+    init();
+    let db = Arc::new(CacheDb::new());
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let (stop, shutdown) = watch::channel(false);
+    let task = tokio::spawn(statsd::serve_udp(socket, db.clone(), shutdown));
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.send_to(b"udp.hits:1|c\nudp.hits:2|c\nbad line\n", addr).await.unwrap();
+    client.send_to(b"udp.load:0.5|g", addr).await.unwrap();
+    // the default flush interval is far away, only shutdown writes the interval
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(db.get("udp.hits.count").is_none());
+    stop.send_replace(true);
+    task.await.unwrap();
+    assert_eq!(db.last("udp.hits.count").unwrap(), TSCacheValue::Double(3.0));
+    assert_eq!(db.last("udp.load").unwrap(), TSCacheValue::Double(0.5));
+}
@@ -6,21 +6,10 @@ use std::ptr::write;
 use msgpack_simple::MsgPack;
 use rmp_serde::{encode, from_slice, to_vec, to_vec_named};
 
-
-#[path = "../src/entity.rs"]
-mod entity;
-#[path = "../src/method.rs"]
-mod method;
-
-#[path = "../src/io.rs"]
-mod io;
-
-#[path = "../src/db.rs"]
-mod db;
-
-use entity::{TSItem, DataType};
-use crate::entity::{SaveTimePeriod, TSCacheValue};
-use crate::io::read_all_items;
+use time_cache::entity::{TSItem, DataType};
+use time_cache::entity::{SaveTimePeriod, TSCacheValue};
+use time_cache::io::read_all_items;
+use time_cache::entity;
 
 #[test]
 fn test01() {
@@ -71,6 +60,9 @@ fn test05() {
         capacity: 100,
         datatype: DataType::Long,
         saveTime: SaveTimePeriod::Nerve,
+        labels: Default::default(),
+        precision: Default::default(),
+        compression: Default::default(),
     };
     let encode_code = to_vec_named(&demo).unwrap();
     println!("encode len:{}", encode_code.len());
@@ -99,6 +91,9 @@ fn test07() {
         capacity: 0,
         datatype: DataType::Float,
         saveTime: SaveTimePeriod::Nerve,
+        labels: Default::default(),
+        precision: Default::default(),
+        compression: Default::default(),
     }];
     let encode_code = to_vec_named(&item).unwrap();
     println!("encode len:{}", encode_code.len());
@@ -115,6 +110,9 @@ fn test08(){
         capacity: 0,
         datatype: DataType::Float,
         saveTime: SaveTimePeriod::Nerve,
+        labels: Default::default(),
+        precision: Default::default(),
+        compression: Default::default(),
     };
     println!("{:p}", &item);
     demo(Box::new(item))
@@ -0,0 +1,62 @@
+use bytes::BytesMut;
+use rmp_serde::{from_slice, to_vec_named};
+use serde::Serialize;
+
+use time_cache::alert::now_millis;
+use time_cache::method::{choose_method, MethodKind};
+use time_cache::{CacheDb, DataType, SaveTimePeriod, TSCacheValue, TSItem, TSValue};
+
+mod common;
+
+fn item(name: &str) -> TSItem {
+    TSItem { tsName: name.to_string(), capacity: 10, datatype: DataType::Long, saveTime: SaveTimePeriod::Nerve, labels: Default::default(), precision: Default::default(), compression: Default::default() }
+}
+
+fn call<T: Serialize>(db: &CacheDb, kind: MethodKind, param: &T) -> Vec<u8> {
+    let mut out = BytesMut::new();
+    choose_method(kind.as_code()).unwrap().do_method(&to_vec_named(param).unwrap(), db, &mut out).unwrap();
+    out.to_vec()
+}
+
+#[test]
+fn zero_key_is_stamped_by_the_server() {
+    common::init();
+    let db = CacheDb::new();
+    db.create(item("stamped")).unwrap();
+    let before = now_millis();
+    let first = db.set(TSValue { name: "stamped".to_string(), key: 0, value: TSCacheValue::Long(1) }).unwrap();
+    assert!(first >= before && first <= now_millis(), "{}", first);
+    // stamps within the same millisecond still move forward
+    let second = db.set(TSValue { name: "stamped".to_string(), key: 0, value: TSCacheValue::Long(2) }).unwrap();
+    assert!(second > first);
+
+    // a writer ahead of the server clock does not make stamped points fail
+    let ahead = now_millis() + 60_000;
+    assert_eq!(db.set(TSValue { name: "stamped".to_string(), key: ahead, value: TSCacheValue::Long(3) }).unwrap(), ahead);
+    assert_eq!(db.set(TSValue { name: "stamped".to_string(), key: 0, value: TSCacheValue::Long(4) }).unwrap(), ahead + 1);
+    assert_eq!(db.query("stamped", u128::MAX).unwrap().key, ahead + 1);
+}
+
+#[test]
+fn set_replies_with_the_stored_key() {
+    #[derive(Serialize)]
+    struct WithoutKey {
+        name: String,
+        value: TSCacheValue,
+    }
+    common::init();
+    let db = CacheDb::new();
+    db.create(item("replied")).unwrap();
+    let key: u128 = from_slice(&call(&db, MethodKind::Set, &WithoutKey { name: "replied".to_string(), value: TSCacheValue::Long(1) })).unwrap();
+    assert_eq!(db.query("replied", u128::MAX).unwrap().key, key);
+
+    let batch = vec![
+        TSValue { name: "replied".to_string(), key: 0, value: TSCacheValue::Long(2) },
+        TSValue { name: "replied".to_string(), key: key + 1000, value: TSCacheValue::Long(3) },
+        TSValue { name: "replied".to_string(), key: 0, value: TSCacheValue::Long(4) },
+    ];
+    let keys: Vec<u128> = from_slice(&call(&db, MethodKind::SetBatch, &batch)).unwrap();
+    assert_eq!(keys.len(), 3);
+    assert!(keys[0] > key);
+    assert_eq!(&keys[1..], &[key + 1000, key + 1001]);
+}
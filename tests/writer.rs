@@ -0,0 +1,132 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use time_cache::entity::{Compression, DataType, SaveTimePeriod, TSCacheValue, TSItem, TSValue};
+use time_cache::writer::SegmentWriter;
+use time_cache::io;
+
+mod common;
+
+fn item(name: &str, save_time: SaveTimePeriod) -> TSItem {
+    TSItem { tsName: name.to_string(), capacity: 10, datatype: DataType::Long, saveTime: save_time, labels: Default::default(), precision: Default::default(), compression: Default::default() }
+}
+
+fn segment_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() { files.extend(segment_files(&path)) } else { files.push(path) }
+    }
+    files.sort();
+    files
+}
+
+#[test]
+fn queued_points_reach_the_segment() {
+    common::init();
+    let name = format!("writer-{}", std::process::id());
+    let writer = SegmentWriter::new(item(&name, SaveTimePeriod::Hour), 100_000);
+    for key in 1..=1000u128 {
+        writer.check().unwrap();
+        writer.submit(TSValue { name: name.clone(), key, value: TSCacheValue::Long(1) });
+    }
+    writer.sync().unwrap();
+    assert_eq!(writer.pending(), 0);
+    let dir = format!("{}/{}", io::data_dir(), name);
+    let segments = segment_files(Path::new(&dir));
+    let values: Vec<TSValue> = segments.iter().flat_map(|it| io::read_segment(it).unwrap()).collect();
+    assert!(values.iter().all(|it| it.name == name));
+    let points: Vec<(u128, TSCacheValue)> = values.into_iter().map(|it| (it.key, it.value)).collect();
+    assert_eq!(points, (1..=1000u128).map(|key| (key, TSCacheValue::Long(1))).collect::<Vec<_>>());
+    // 17 bytes a point as msgpack, blocks need a few bits for an unchanged value one key later
+    let bytes: u64 = segments.iter().map(|it| fs::metadata(it).unwrap().len()).sum();
+    assert!(bytes < 1000 * 17 / 10, "{}", bytes);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn segments_written_before_blocks_still_read() {
+    let dir = std::env::temp_dir().join(format!("tc-legacy-{}/legacy/2024-01-01", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let mut buff = vec![];
+    for (key, value) in [(1u128, TSCacheValue::Long(7)), (5, TSCacheValue::String("x".to_string()))] {
+        buff.extend_from_slice(&key.to_be_bytes());
+        buff.extend(rmp_serde::to_vec_named(&value).unwrap());
+    }
+    let path = dir.join("1.tc");
+    fs::write(&path, &buff).unwrap();
+    let values = io::read_segment(&path).unwrap();
+    assert_eq!(values.iter().map(|it| (it.name.as_str(), it.key, it.value.clone())).collect::<Vec<_>>(),
+               vec![("legacy", 1, TSCacheValue::Long(7)), ("legacy", 5, TSCacheValue::String("x".to_string()))]);
+    // a block cut short is an error rather than missing points
+    fs::write(&path, [&b"TCB1"[..], &[0, 0, 0, 9, 1]].concat()).unwrap();
+    assert_eq!(io::read_segment(&path).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    fs::remove_dir_all(dir.parent().unwrap().parent().unwrap()).unwrap();
+}
+
+#[test]
+fn sealed_segments_are_compressed_and_read_back() {
+    common::init();
+    let mut sizes = vec![];
+    for (compression, magic) in [(Compression::None, io::SEGMENT_MAGIC), (Compression::Lz4, io::LZ4_MAGIC), (Compression::Zstd, io::ZSTD_MAGIC)] {
+        let name = format!("sealed-{}-{}", compression, std::process::id());
+        let item = TSItem { datatype: DataType::String, compression, ..item(&name, SaveTimePeriod::Hour) };
+        let mut io = io::FileIOCache::new(Box::new(item));
+        let values: Vec<TSValue> = (1..=500u128)
+            .map(|key| TSValue { name: name.clone(), key, value: TSCacheValue::String(format!("GET /index.html 200 {}", key % 3)) })
+            .collect();
+        values.chunks(100).for_each(|batch| io.append(batch).unwrap());
+        io.seal();
+        // a closed segment is not appended to
+        io.append(&values[..1]).unwrap();
+        io.seal();
+
+        let dir = format!("{}/{}", io::data_dir(), name);
+        let segments = segment_files(Path::new(&dir));
+        assert_eq!(segments.len(), 2);
+        assert!(segments.iter().all(|it| fs::read(it).unwrap().starts_with(magic)));
+        let points: Vec<TSValue> = segments.iter().flat_map(|it| io::read_segment(it).unwrap()).collect();
+        assert_eq!(points.len(), 501);
+        assert!(points.iter().zip(values.iter().chain(&values[..1])).all(|(a, b)| a.key == b.key && a.value == b.value && a.name == name));
+        sizes.push(segments.iter().map(|it| fs::metadata(it).unwrap().len()).max().unwrap());
+
+        if compression != Compression::None {
+            let path = segments.iter().max_by_key(|it| fs::metadata(it).unwrap().len()).unwrap();
+            let mut broken = fs::read(path).unwrap();
+            broken.truncate(broken.len() / 2);
+            fs::write(path, broken).unwrap();
+            assert_eq!(io::read_segment(path).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+    assert!(sizes[1] < sizes[0] / 2 && sizes[2] < sizes[0] / 2, "{:?}", sizes);
+}
+
+#[test]
+fn full_queue_reports_backpressure() {
+    common::init();
+    let writer = SegmentWriter::new(item("writer-full", SaveTimePeriod::Hour), 0);
+    assert_eq!(writer.check().unwrap_err().code, 4009);
+    // memory only series never touch the disk queue
+    let writer = SegmentWriter::new(item("writer-memory", SaveTimePeriod::Nerve), 0);
+    writer.check().unwrap();
+    writer.submit(TSValue { name: "writer-memory".to_string(), key: 1, value: TSCacheValue::Long(1) });
+    assert_eq!(writer.pending(), 0);
+    assert!(!Path::new(&format!("{}/writer-memory", io::data_dir())).exists());
+}
+
+#[test]
+fn write_errors_stop_the_series() {
+    common::init();
+    // a file where the series directory should be
+    let name = format!("writer-broken-{}", std::process::id());
+    fs::create_dir_all(io::data_dir()).unwrap();
+    fs::write(format!("{}/{}", io::data_dir(), name), b"").unwrap();
+    let writer = SegmentWriter::new(item(&name, SaveTimePeriod::Hour), 100);
+    writer.check().unwrap();
+    writer.submit(TSValue { name: name.clone(), key: 1, value: TSCacheValue::Long(1) });
+    assert!(writer.sync().is_err());
+    let e = writer.check().unwrap_err();
+    assert_eq!(e.code, 4012);
+    assert!(e.msg.contains(&name), "{}", e.msg);
+}